@@ -16,6 +16,13 @@ module Ireul
   TYPE_I16 = 0x0088
   TYPE_I32 = 0x0089
   TYPE_I64 = 0x008a
+  # starts a ProtocolError sent in place of a response
+  TYPE_PROTOCOL_ERROR = 0x008b
+  TYPE_BOOL = 0x008c
+  TYPE_F64 = 0x008d
+  TYPE_OPTION = 0x008e
+  TYPE_MAP = 0x008f
+  TYPE_ENUM = 0x0090
 
   TYPESET_NUMBER = [
     TYPE_U16,
@@ -24,6 +31,7 @@ module Ireul
     TYPE_I16,
     TYPE_I32,
     TYPE_I64,
+    TYPE_F64,
   ].freeze
   TYPESET_ALL = [
     TYPE_ARRAY,
@@ -40,6 +48,11 @@ module Ireul
     TYPE_I16,
     TYPE_I32,
     TYPE_I64,
+    TYPE_BOOL,
+    TYPE_F64,
+    TYPE_OPTION,
+    TYPE_MAP,
+    TYPE_ENUM,
   ].freeze
 
   def self._expect_type(reader, expected_types)
@@ -129,6 +142,24 @@ module Ireul
         out << Ireul._unpack_instance(reader, Ireul::TYPESET_ALL)
       end
       out
+    when TYPE_BOOL
+      reader.read(1).unpack('C')[0] != 0
+    when TYPE_F64
+      reader.read(8).unpack('G')[0]
+    when TYPE_OPTION
+      present = reader.read(1).unpack('C')[0]
+      present != 0 ? Ireul._unpack_instance(reader) : nil
+    when TYPE_MAP
+      length = reader.read(4).unpack('N')[0]
+      out = {}
+      length.times do
+        key = Ireul._unpack_instance(reader)
+        out[key] = Ireul._unpack_instance(reader)
+      end
+      out
+    when TYPE_ENUM
+      kind = reader.read(4).unpack('N')[0]
+      { kind: kind, value: Ireul._unpack_instance(reader) }
     end
   end
 end
@@ -161,7 +192,7 @@ module Ireul
     class Bound
       def from_frame(buffer)
         result = Result.allocate
-        type_id = Ireul._expect_type(buffer, [TYPE_RESULT_OK, TYPE_RESULT_ERR])
+        type_id = Ireul._expect_type(buffer, [TYPE_RESULT_OK, TYPE_RESULT_ERR, TYPE_PROTOCOL_ERROR])
         case type_id
         when TYPE_RESULT_OK
           ok_fac = @ok_fac
@@ -173,6 +204,11 @@ module Ireul
           result.instance_eval do
             @err = err_fac.call(buffer)
           end
+        when TYPE_PROTOCOL_ERROR
+          err = ProtocolError.from_body(buffer)
+          result.instance_eval do
+            @err = err
+          end
         end
         result
       end
@@ -376,7 +412,38 @@ module Ireul
   class InvalidAddress < StandardError
   end
 
+  # Sent by the server in place of a response when a request can't be
+  # served at all, such as when the connection isn't allowed to make it.
   class ProtocolError < StandardError
+    UNKNOWN = 0
+    BAD_VERSION = 1
+    UNKNOWN_OP_CODE = 2
+    TOO_LARGE = 3
+    MALFORMED_REQUEST = 4
+    PERMISSION_DENIED = 5
+    UNAVAILABLE = 6
+
+    # one of the codes above; newer servers may send others
+    attr_reader :code
+    # the id of the request this answers, or 0
+    attr_reader :request_id
+
+    def initialize(code, message, request_id = 0)
+      super(message)
+      @code = code
+      @request_id = request_id
+    end
+
+    def self.from_frame(buffer)
+      Ireul._expect_type(buffer, [TYPE_PROTOCOL_ERROR])
+      ProtocolError.from_body(buffer)
+    end
+
+    # Reads what follows the TYPE_PROTOCOL_ERROR prefix.
+    def self.from_body(buffer)
+      hash = Ireul._unpack_instance(buffer, [TYPE_STRUCT])
+      ProtocolError.new(hash[:code], hash[:message] || '', hash[:request_id] || 0)
+    end
   end
 
   class UnknownHandle < StandardError
@@ -767,5 +834,22 @@ module Ireul
         return
       end
     end
+
+    def self.test_protocol_error
+      reader = StringIO.new(
+        "\x00\x8b" + "\x00\x05" + "\x00\x00\x00\x02" \
+        "\x00\x84" + "\x00\x00\x00\x04code" \
+        "\x00\x82" + "\x00\x00\x00\x05" \
+        "\x00\x84" + "\x00\x00\x00\x07message" \
+        "\x00\x84" + "\x00\x00\x00\x02no")
+      inst = Ireul::Core::QUEUE_STATUS_RESPONSE_TYPE.from_frame(reader)
+      begin
+        inst.unwrap
+        raise 'Assertion error'
+      rescue ProtocolError => e
+        raise 'Assertion error' if e.code != ProtocolError::PERMISSION_DENIED
+        raise 'Assertion error' if e.message != 'no'
+      end
+    end
   end
 end
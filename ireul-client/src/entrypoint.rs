@@ -1,8 +1,14 @@
 use std::io;
+use std::env;
 use std::ffi::OsString;
+use std::time::Duration;
 
 use byteorder;
 use ogg::OggPageCheckError;
+use ireul_interface::client::{self, IreulClient, ClientError};
+
+// how long to wait on the server before giving up
+const TIMEOUT_SECS: u64 = 30;
 
 pub enum Error {
     InvalidArguments,
@@ -27,8 +33,31 @@ impl From<io::Error> for Error {
     }
 }
 
+impl From<ClientError> for Error {
+    fn from(e: ClientError) -> Error {
+        Error::Unspecified(format!("{:?}", e))
+    }
+}
+
 impl From<OggPageCheckError> for Error {
     fn from(e: OggPageCheckError) -> Error {
         Error::Unspecified(format!("{:?}", e))
     }
 }
+
+/// Connects to the server named by `IREUL_ADDR` (`host:port`, or `unix:`
+/// and a socket path), or the default address when that isn't set,
+/// logging in as `IREUL_USER` with `IREUL_TOKEN` if a user is given.
+pub fn connect() -> Result<IreulClient, Error> {
+    let addr = env::var("IREUL_ADDR").unwrap_or(client::DEFAULT_ADDR.to_string());
+    let mut client = try!(IreulClient::open(&addr)
+        .map_err(|err| Error::Unspecified(format!("error connecting to {}: {:?}", addr, err))));
+    try!(client.set_timeout(Some(Duration::from_secs(TIMEOUT_SECS))));
+
+    if let Ok(user) = env::var("IREUL_USER") {
+        let token = env::var("IREUL_TOKEN").unwrap_or(String::new());
+        try!(try!(client.login(&user, &token))
+            .map_err(|err| Error::Unspecified(format!("error logging in as {}: {:?}", user, err))));
+    }
+    Ok(client)
+}
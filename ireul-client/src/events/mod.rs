@@ -0,0 +1,51 @@
+use std::ffi::OsString;
+
+use ireul_interface::proxy::Event;
+
+use ::entrypoint::{self as ep, Error as EntryPointError};
+
+pub struct EntryPoint;
+
+unsafe impl Sync for EntryPoint {}
+
+impl ::entrypoint::EntryPoint for EntryPoint {
+    fn main(&self, args: Vec<OsString>) -> Result<(), EntryPointError> {
+        main(args)
+    }
+
+    fn print_usage(&self, args: &[OsString]) {
+        print_usage(args)
+    }
+}
+
+fn main(args: Vec<OsString>) -> Result<(), EntryPointError> {
+    assert_eq!(&args[1], "events");
+
+    let mut client = try!(ep::connect());
+    // events may be a long time coming
+    try!(client.set_timeout(None));
+
+    let mut events = match try!(client.subscribe()) {
+        Ok(events) => events,
+        Err(err) => return Err(EntryPointError::Unspecified(format!("{:?}", err))),
+    };
+
+    loop {
+        match try!(events.recv()) {
+            Event::TrackStarted(track) => {
+                println!("started: {} - {} [{:?}]", track.artist, track.title, track.handle);
+            },
+            Event::TrackEnded(track) => {
+                println!("ended: {} - {} [{:?}]", track.artist, track.title, track.handle);
+            },
+            event => println!("{:?}", event),
+        }
+    }
+}
+
+fn print_usage(args: &[OsString]) {
+    println!("{} events", args[0].clone().into_string().ok().unwrap());
+    println!("");
+    println!("    Prints events from the server as they happen");
+    println!("");
+}
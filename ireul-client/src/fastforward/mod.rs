@@ -1,18 +1,8 @@
-use std::io::{self, Read, Write};
 use std::ffi::OsString;
-use std::net::TcpStream;
 
-use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+use ireul_interface::proxy::FastForward;
 
-use ireul_interface::proto;
-use ireul_interface::proxy::{
-    RequestType,
-    FastForwardRequest,
-    FastForward,
-    FastForwardResult,
-};
-
-use ::entrypoint::{Error as EntryPointError};
+use ::entrypoint::{self as ep, Error as EntryPointError};
 
 pub struct EntryPoint;
 
@@ -28,38 +18,48 @@ impl ::entrypoint::EntryPoint for EntryPoint {
     }
 }
 
-fn main(_args: Vec<OsString>) -> Result<(), EntryPointError> {
-    let mut conn = TcpStream::connect("127.0.0.1:3001").unwrap();
+fn parse_kind(args: &[OsString]) -> Result<FastForward, EntryPointError> {
+    if args.len() == 0 {
+        return Ok(FastForward::TrackBoundary);
+    }
+    if args.len() != 2 {
+        return Err(EntryPointError::InvalidArguments);
+    }
 
-    try!(conn.write_u8(0));
-    try!(conn.write_u32::<BigEndian>(RequestType::FastForward.to_op_code()));
+    let samples: u64 = try!(args[1].to_str()
+        .and_then(|val| val.parse().ok())
+        .ok_or(EntryPointError::InvalidArguments));
 
-    let req = FastForwardRequest {
-        kind: FastForward::TrackBoundary,
-    };
-    let buf = proto::serialize(&req).unwrap();
-    try!(conn.write_u32::<BigEndian>(buf.len() as u32));
-    try!(conn.write_all(&buf));
+    match args[0].to_str() {
+        Some("seek") => Ok(FastForward::Seek { position: samples }),
+        Some("forward") => Ok(FastForward::SeekForward { samples: samples }),
+        Some("back") => Ok(FastForward::SeekBackward { samples: samples }),
+        _ => Err(EntryPointError::InvalidArguments),
+    }
+}
 
-    let frame_length = try!(conn.read_u32::<BigEndian>());
-    let mut resp_buf = Vec::new();
-    {
-        let mut limit_reader = Read::by_ref(&mut conn).take(frame_length as u64);
-        try!(limit_reader.read_to_end(&mut resp_buf));
+fn main(args: Vec<OsString>) -> Result<(), EntryPointError> {
+    if args.len() < 2 || args[1] != "fast-forward" {
+        return Err(EntryPointError::InvalidArguments);
     }
+    let kind = try!(parse_kind(&args[2..]));
 
-    let mut frame = io::Cursor::new(resp_buf);
-    let res: FastForwardResult = proto::deserialize(&mut frame).unwrap();
+    let mut client = try!(ep::connect());
+    let res = try!(client.fast_forward(kind));
     println!("got response: {:?}", res);
-
-    try!(conn.write_u8(0));
-    try!(conn.write_u32::<BigEndian>(0));
     Ok(())
 }
 
 fn print_usage(args: &[OsString]) {
-    println!("{} fast-forward", args[0].clone().into_string().ok().unwrap());
+    let app_name = args[0].clone().into_string().ok().unwrap();
+    println!("{} fast-forward", app_name);
     println!("");
     println!("    Skips the currently-playing track");
     println!("");
+    println!("{} fast-forward seek <sample>", app_name);
+    println!("{} fast-forward forward <samples>", app_name);
+    println!("{} fast-forward back <samples>", app_name);
+    println!("");
+    println!("    Seeks within the currently-playing track");
+    println!("");
 }
@@ -0,0 +1,51 @@
+use std::ffi::OsString;
+
+use ireul_interface::proxy::RequestType;
+
+use ::entrypoint::{self as ep, Error as EntryPointError};
+
+pub struct EntryPoint;
+
+unsafe impl Sync for EntryPoint {}
+
+impl ::entrypoint::EntryPoint for EntryPoint {
+    fn main(&self, args: Vec<OsString>) -> Result<(), EntryPointError> {
+        main(args)
+    }
+
+    fn print_usage(&self, args: &[OsString]) {
+        print_usage(args)
+    }
+}
+
+fn main(args: Vec<OsString>) -> Result<(), EntryPointError> {
+    assert_eq!(&args[1], "info");
+
+    let mut client = try!(ep::connect());
+    let caps = match try!(client.hello()) {
+        Ok(caps) => caps,
+        Err(err) => return Err(EntryPointError::Unspecified(format!("{:?}", err))),
+    };
+
+    println!("server version: {}", caps.server_version);
+    println!("protocol version: {}", caps.protocol_version);
+    println!("codecs: {}", caps.codecs.join(", "));
+    println!("sample rate: {}", caps.sample_rate);
+    println!("message size limit: {}", caps.message_size_limit);
+    println!("queue capacity: {}", caps.queue_capacity);
+    println!("requests:");
+    for &op_code in caps.op_codes.iter() {
+        match RequestType::from_op_code(op_code) {
+            Ok(req_type) => println!("    {:?}", req_type),
+            Err(()) => println!("    unknown ({:#x})", op_code),
+        }
+    }
+    Ok(())
+}
+
+fn print_usage(args: &[OsString]) {
+    println!("{} info", args[0].clone().into_string().ok().unwrap());
+    println!("");
+    println!("    Prints what the server supports");
+    println!("");
+}
@@ -0,0 +1,82 @@
+use std::io::{self, Read};
+use std::fs::File;
+use std::ffi::OsString;
+
+use ogg::OggTrackBuf;
+
+use ireul_interface::proxy::InterruptRequest;
+
+use ::entrypoint::{self as ep, Error as EntryPointError};
+
+pub struct EntryPoint;
+
+unsafe impl Sync for EntryPoint {}
+
+impl ::entrypoint::EntryPoint for EntryPoint {
+    fn main(&self, args: Vec<OsString>) -> Result<(), EntryPointError> {
+        main(args)
+    }
+
+    fn print_usage(&self, args: &[OsString]) {
+        print_usage(args)
+    }
+}
+
+#[derive(Debug)]
+struct ProgramArgs {
+    app_name: OsString,
+    target_file: OsString,
+}
+
+impl ProgramArgs {
+    pub fn new(args: Vec<OsString>) -> Result<ProgramArgs, EntryPointError> {
+        if args.len() < 3 {
+            return Err(EntryPointError::InvalidArguments);
+        }
+
+        let app_name = args[0].clone();
+        assert_eq!(&args[1], "interrupt");
+        let target_file = args[2].clone();
+
+        Ok(ProgramArgs {
+            app_name: app_name,
+            target_file: target_file,
+        })
+    }
+}
+
+
+fn main(args: Vec<OsString>) -> Result<(), EntryPointError> {
+    let args = try!(ProgramArgs::new(args));
+
+    let mut file = io::BufReader::new(try!(File::open(&args.target_file)));
+    let mut buffer = Vec::new();
+    try!(file.read_to_end(&mut buffer));
+    let track = try!(OggTrackBuf::new(buffer));
+
+    let mut pages = 0;
+    let mut samples = 0;
+    for page in track.pages() {
+        pages += 1;
+        samples = page.position();
+    }
+
+    println!("loaded {} samples in {} pages", samples, pages);
+    let req = InterruptRequest {
+        track: track,
+        metadata: None,
+    };
+
+    let mut client = try!(ep::connect());
+    let res = try!(client.interrupt(&req));
+    println!("got response: {:?}", res);
+
+    Ok(())
+}
+
+fn print_usage(args: &[OsString]) {
+    println!("{} interrupt <ogg-file>", args[0].clone().into_string().ok().unwrap());
+    println!("");
+    println!("    Plays the target file immediately, then resumes the current track");
+    println!("");
+}
@@ -12,6 +12,9 @@ mod fastforward;
 mod entrypoint;
 mod queue;
 mod replace_fallback;
+mod interrupt;
+mod events;
+mod info;
 
 use entrypoint::EntryPoint;
 
@@ -20,6 +23,9 @@ static ENTRY_POINT_MAP: &'static [(&'static str, &'static EntryPoint)] = &[
     ("fast-forward", &fastforward::EntryPoint),
     ("queue", &queue::EntryPoint),
     ("replace-fallback", &replace_fallback::EntryPoint),
+    ("interrupt", &interrupt::EntryPoint),
+    ("events", &events::EntryPoint),
+    ("info", &info::EntryPoint),
 ];
 
 fn print_usage(args: &[OsString]) {
@@ -1,15 +1,4 @@
-use std::io::{self, Read, Write};
 use std::ffi::OsString;
-use std::net::TcpStream;
-
-use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
-
-use ireul_interface::proto;
-use ireul_interface::proxy::RequestType;
-use ireul_interface::proxy::track::{
-    StatusRequest,
-    StatusResult,
-};
 
 use ::entrypoint as ep;
 
@@ -28,28 +17,10 @@ impl ep::EntryPoint for EntryPoint {
 }
 
 fn main(_args: Vec<OsString>) -> Result<(), ep::Error> {
-    let mut conn = TcpStream::connect("127.0.0.1:3001").unwrap();
-    try!(conn.write_u8(0));
-    try!(conn.write_u32::<BigEndian>(RequestType::QueueStatus.to_op_code()));
-
-    let buf = proto::serialize(&StatusRequest).unwrap();
-    try!(conn.write_u32::<BigEndian>(buf.len() as u32));
-    try!(conn.write_all(&buf));
-
-    let frame_length = try!(conn.read_u32::<BigEndian>());
-    let mut resp_buf = Vec::new();
-    {
-        let mut limit_reader = Read::by_ref(&mut conn).take(frame_length as u64);
-        try!(limit_reader.read_to_end(&mut resp_buf));
-    }
-
-    let mut frame = io::Cursor::new(resp_buf);
-    let res: StatusResult = proto::deserialize(&mut frame).unwrap();
+    let mut client = try!(ep::connect());
+    let res = try!(client.queue_status());
     println!("got response: {:?}", res);
 
-    try!(conn.write_u8(0));
-    try!(conn.write_u32::<BigEndian>(0));
-
     Ok(())
 }
 
@@ -1,23 +1,13 @@
-use std::io::{self, Read, Write};
-use std::fs::{self, File};
+use std::io::{self, Read};
+use std::fs::File;
 use std::ffi::OsString;
-use std::net::TcpStream;
-use std::process;
-
-use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
 
 use ogg::OggTrackBuf;
 
-use ireul_interface::proto;
-use ireul_interface::proxy::{
-    RequestType,
-    ReplaceFallbackRequest,
-    ReplaceFallbackResult,
-};
+use ireul_interface::proxy::ReplaceFallbackRequest;
 
 use ::entrypoint::{
     self as ep,
-    EntryPoint as EntryPointTrait,
     Error as EntryPointError,
 };
 
@@ -80,28 +70,10 @@ fn main(args: Vec<OsString>) -> Result<(), ep::Error> {
         metadata: None,
     };
 
-    let mut conn = TcpStream::connect("127.0.0.1:3001").unwrap();
-    try!(conn.write_u8(0));
-    try!(conn.write_u32::<BigEndian>(RequestType::ReplaceFallback.to_op_code()));
-
-    let buf = proto::serialize(&req).unwrap();
-    try!(conn.write_u32::<BigEndian>(buf.len() as u32));
-    try!(conn.write_all(&buf));
-
-    let frame_length = try!(conn.read_u32::<BigEndian>());
-    let mut resp_buf = Vec::new();
-    {
-        let mut limit_reader = Read::by_ref(&mut conn).take(frame_length as u64);
-        try!(limit_reader.read_to_end(&mut resp_buf));
-    }
-
-    let mut frame = io::Cursor::new(resp_buf);
-    let res: ReplaceFallbackResult = proto::deserialize(&mut frame).unwrap();
+    let mut client = try!(ep::connect());
+    let res = try!(client.replace_fallback(&req));
     println!("got response: {:?}", res);
 
-    try!(conn.write_u8(0));
-    try!(conn.write_u32::<BigEndian>(0));
-
     Ok(())
 }
 
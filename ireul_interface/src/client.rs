@@ -0,0 +1,539 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+use std::time::Duration;
+
+use byteorder::{self, ReadBytesExt, WriteBytesExt, BigEndian};
+
+use proto::{self, Deserialize, Serialize};
+use proxy::{
+    PROTOCOL_VERSION,
+    PROTOCOL_VERSION_TAGGED,
+    ProtocolError,
+    Request,
+    HelloRequest,
+    HelloResult,
+    EnqueueTrackRequest,
+    EnqueueTrackResult,
+    FastForward,
+    FastForwardRequest,
+    FastForwardResult,
+    ReplaceFallbackRequest,
+    ReplaceFallbackResult,
+    InterruptRequest,
+    InterruptResult,
+    StatusRequest,
+    StatusResult,
+    LoginRequest,
+    LoginResult,
+    RemoveTrackRequest,
+    RemoveTrackResult,
+    MoveTrackRequest,
+    MoveTrackResult,
+    Event,
+    SubscribeRequest,
+    SubscribeError,
+};
+
+/// Where ireul listens unless configured otherwise.
+pub const DEFAULT_ADDR: &'static str = "127.0.0.1:3001";
+
+const OP_GOODBYE: u32 = 0;
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The connection failed or timed out.
+    Io(io::Error),
+    /// A request or response exceeded `proto::MESSAGE_SIZE_LIMIT`.
+    TooLarge(usize),
+    /// The server's response didn't decode as what we asked for.
+    BadResponse(io::Error),
+    /// The server couldn't serve the request at all.
+    Protocol(ProtocolError),
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        ClientError::Io(e)
+    }
+}
+
+impl From<byteorder::Error> for ClientError {
+    fn from(e: byteorder::Error) -> ClientError {
+        ClientError::Io(e.into())
+    }
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref stream) => {
+                try!(stream.set_read_timeout(timeout));
+                stream.set_write_timeout(timeout)
+            },
+            #[cfg(unix)]
+            Stream::Unix(ref stream) => {
+                try!(stream.set_read_timeout(timeout));
+                stream.set_write_timeout(timeout)
+            },
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref mut stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+/// A connection to an ireul server, which can be used for any number of
+/// requests.  Requests are answered in order, one at a time.
+///
+/// Each method returns the server's answer, or a ClientError if we didn't
+/// get one.  After a `ClientError::Protocol` the connection can usually
+/// be used again; after the others it can't be trusted.
+pub struct IreulClient {
+    stream: Stream,
+}
+
+impl IreulClient {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<IreulClient, ClientError> {
+        let stream = try!(TcpStream::connect(addr));
+        Ok(IreulClient::from_stream(stream))
+    }
+
+    /// Connects to a server's Unix socket.
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> Result<IreulClient, ClientError> {
+        let stream = try!(UnixStream::connect(path));
+        Ok(IreulClient { stream: Stream::Unix(stream) })
+    }
+
+    /// Connects to `addr`, which is either `host:port` or `unix:` followed
+    /// by the path of a socket.
+    pub fn open(addr: &str) -> Result<IreulClient, ClientError> {
+        Ok(IreulClient { stream: try!(open_stream(addr)) })
+    }
+
+    pub fn from_stream(stream: TcpStream) -> IreulClient {
+        IreulClient { stream: Stream::Tcp(stream) }
+    }
+
+    /// Gives up on requests which take longer than `timeout` to send or to
+    /// be answered.  There is no timeout by default.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), ClientError> {
+        try!(self.stream.set_timeout(timeout));
+        Ok(())
+    }
+
+    pub fn call<R>(&mut self, req: &R) -> Result<Result<R::Value, R::Error>, ClientError>
+        where
+            R: Request + Serialize,
+            R::Value: Deserialize,
+            R::Error: Deserialize
+    {
+        try!(write_request(&mut self.stream, PROTOCOL_VERSION, None, req));
+        self.read_frame()
+    }
+
+    fn read_frame<T: Deserialize>(&mut self) -> Result<T, ClientError> {
+        let resp_buf = try!(read_body(&mut self.stream));
+        decode_frame(resp_buf)
+    }
+
+    /// Finds out what the server supports.
+    pub fn hello(&mut self) -> Result<HelloResult, ClientError> {
+        self.call(&HelloRequest)
+    }
+
+    /// Identifies ourselves to a server which has users configured.  The
+    /// server hangs up after a failed login.
+    pub fn login(&mut self, user: &str, token: &str) -> Result<LoginResult, ClientError> {
+        self.call(&LoginRequest {
+            user: user.to_string(),
+            token: token.to_string(),
+        })
+    }
+
+    pub fn enqueue_track(&mut self, req: &EnqueueTrackRequest) -> Result<EnqueueTrackResult, ClientError> {
+        self.call(req)
+    }
+
+    pub fn fast_forward(&mut self, kind: FastForward) -> Result<FastForwardResult, ClientError> {
+        self.call(&FastForwardRequest { kind: kind })
+    }
+
+    pub fn queue_status(&mut self) -> Result<StatusResult, ClientError> {
+        self.call(&StatusRequest)
+    }
+
+    pub fn replace_fallback(&mut self, req: &ReplaceFallbackRequest) -> Result<ReplaceFallbackResult, ClientError> {
+        self.call(req)
+    }
+
+    pub fn interrupt(&mut self, req: &InterruptRequest) -> Result<InterruptResult, ClientError> {
+        self.call(req)
+    }
+
+    pub fn remove_track(&mut self, req: &RemoveTrackRequest) -> Result<RemoveTrackResult, ClientError> {
+        self.call(req)
+    }
+
+    pub fn move_track(&mut self, req: &MoveTrackRequest) -> Result<MoveTrackResult, ClientError> {
+        self.call(req)
+    }
+
+    /// Turns this connection into a stream of events.  Any timeout set
+    /// still applies, so it should be generous or unset.
+    pub fn subscribe(mut self) -> Result<Result<Events, SubscribeError>, ClientError> {
+        Ok(try!(self.call(&SubscribeRequest)).map(|()| Events { client: self }))
+    }
+}
+
+fn open_stream(addr: &str) -> Result<Stream, ClientError> {
+    if addr.starts_with("unix:") {
+        return open_unix(&addr["unix:".len()..]);
+    }
+    Ok(Stream::Tcp(try!(TcpStream::connect(addr))))
+}
+
+#[cfg(unix)]
+fn open_unix(path: &str) -> Result<Stream, ClientError> {
+    Ok(Stream::Unix(try!(UnixStream::connect(path))))
+}
+
+#[cfg(not(unix))]
+fn open_unix(_path: &str) -> Result<Stream, ClientError> {
+    Err(ClientError::Io(io::Error::new(io::ErrorKind::Other, "Unix sockets need a Unix system")))
+}
+
+fn write_request<R>(stream: &mut Stream, version: u8, request_id: Option<u64>, req: &R) -> Result<(), ClientError>
+    where R: Request + Serialize
+{
+    let req_buf = try!(proto::serialize(req));
+    if proto::MESSAGE_SIZE_LIMIT < req_buf.len() {
+        return Err(ClientError::TooLarge(req_buf.len()));
+    }
+
+    try!(stream.write_u8(version));
+    try!(stream.write_u32::<BigEndian>(req.req_type().to_op_code()));
+    if let Some(request_id) = request_id {
+        try!(stream.write_u64::<BigEndian>(request_id));
+    }
+    try!(stream.write_u32::<BigEndian>(req_buf.len() as u32));
+    try!(stream.write_all(&req_buf));
+    try!(stream.flush());
+    Ok(())
+}
+
+fn read_body(stream: &mut Stream) -> Result<Vec<u8>, ClientError> {
+    let frame_length = try!(stream.read_u32::<BigEndian>()) as usize;
+    if proto::MESSAGE_SIZE_LIMIT < frame_length {
+        return Err(ClientError::TooLarge(frame_length));
+    }
+
+    let mut resp_buf = Vec::new();
+    {
+        let mut limit_reader = Read::by_ref(stream).take(frame_length as u64);
+        try!(limit_reader.read_to_end(&mut resp_buf));
+    }
+    if resp_buf.len() != frame_length {
+        return Err(ClientError::Io(io::Error::new(
+            io::ErrorKind::UnexpectedEof, "response truncated")));
+    }
+    Ok(resp_buf)
+}
+
+fn decode_frame<T: Deserialize>(resp_buf: Vec<u8>) -> Result<T, ClientError> {
+    let is_error = ProtocolError::is_error_frame(&resp_buf);
+    let mut frame = io::Cursor::new(resp_buf);
+    if is_error {
+        let err = try!(proto::deserialize(&mut frame).map_err(ClientError::BadResponse));
+        return Err(ClientError::Protocol(err));
+    }
+    proto::deserialize(&mut frame).map_err(ClientError::BadResponse)
+}
+
+/// Events pushed by the server after `IreulClient::subscribe`.
+pub struct Events {
+    client: IreulClient,
+}
+
+impl Events {
+    /// Waits for the next event.
+    pub fn recv(&mut self) -> Result<Event, ClientError> {
+        self.client.read_frame()
+    }
+}
+
+impl Drop for IreulClient {
+    fn drop(&mut self) {
+        // say goodbye, if the connection is still up.
+        let _ = self.stream.write_u8(PROTOCOL_VERSION)
+            .and_then(|()| self.stream.write_u32::<BigEndian>(OP_GOODBYE));
+    }
+}
+
+/// A connection whose requests carry ids, so that any number of them can
+/// be sent before reading the answers, which may come back in any order.
+/// Events from subscriptions arrive on the same connection, carrying the
+/// id of their subscribe request.  Needs a server whose `hello` reports
+/// a `protocol_version` of at least `PROTOCOL_VERSION_TAGGED`.
+pub struct Pipeline {
+    stream: Stream,
+    next_id: u64,
+}
+
+/// A response or event read from a `Pipeline`.
+#[derive(Debug)]
+pub struct Tagged {
+    /// The id `Pipeline::send` gave the request this answers
+    pub request_id: u64,
+    frame: Vec<u8>,
+}
+
+impl Tagged {
+    /// Decodes the frame as the response to the request, or as an `Event`.
+    pub fn decode<T: Deserialize>(self) -> Result<T, ClientError> {
+        decode_frame(self.frame)
+    }
+}
+
+impl Pipeline {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Pipeline, ClientError> {
+        let stream = try!(TcpStream::connect(addr));
+        Ok(Pipeline { stream: Stream::Tcp(stream), next_id: 1 })
+    }
+
+    /// As `IreulClient::open`.
+    pub fn open(addr: &str) -> Result<Pipeline, ClientError> {
+        Ok(Pipeline { stream: try!(open_stream(addr)), next_id: 1 })
+    }
+
+    /// As `IreulClient::set_timeout`, for reading and writing frames.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), ClientError> {
+        try!(self.stream.set_timeout(timeout));
+        Ok(())
+    }
+
+    /// Sends `req` without waiting for an answer, returning the id the
+    /// answer will carry.
+    pub fn send<R: Request + Serialize>(&mut self, req: &R) -> Result<u64, ClientError> {
+        let request_id = self.next_id;
+        try!(write_request(&mut self.stream, PROTOCOL_VERSION_TAGGED, Some(request_id), req));
+        self.next_id += 1;
+        Ok(request_id)
+    }
+
+    /// Waits for the next answer or event.
+    pub fn recv(&mut self) -> Result<Tagged, ClientError> {
+        let request_id = try!(self.stream.read_u64::<BigEndian>());
+        let frame = try!(read_body(&mut self.stream));
+        Ok(Tagged {
+            request_id: request_id,
+            frame: frame,
+        })
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        let _ = self.stream.write_u8(PROTOCOL_VERSION_TAGGED)
+            .and_then(|()| self.stream.write_u32::<BigEndian>(OP_GOODBYE));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt, BigEndian};
+
+    use proto;
+    use proxy::{
+        FastForward,
+        FastForwardRequest,
+        FastForwardResult,
+        OP_FAST_FORWARD,
+        OP_QUEUE_STATUS,
+        ErrorCode,
+        ProtocolError,
+        StatusRequest,
+    };
+    use super::{IreulClient, ClientError, Pipeline};
+
+    #[test]
+    fn test_fast_forward() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            for _ in 0..2 {
+                assert_eq!(conn.read_u8().unwrap(), 0);
+                assert_eq!(conn.read_u32::<BigEndian>().unwrap(), OP_FAST_FORWARD);
+                let length = conn.read_u32::<BigEndian>().unwrap();
+                let mut req_buf = Vec::new();
+                Read::by_ref(&mut conn).take(length as u64).read_to_end(&mut req_buf).unwrap();
+                let _: FastForwardRequest = proto::deserialize(&mut io::Cursor::new(req_buf)).unwrap();
+
+                let resp: FastForwardResult = Ok(());
+                let resp_buf = proto::serialize(&resp).unwrap();
+                conn.write_u32::<BigEndian>(resp_buf.len() as u32).unwrap();
+                conn.write_all(&resp_buf).unwrap();
+            }
+
+            // goodbye
+            assert_eq!(conn.read_u8().unwrap(), 0);
+            assert_eq!(conn.read_u32::<BigEndian>().unwrap(), 0);
+        });
+
+        {
+            let mut client = IreulClient::connect(addr).unwrap();
+            // the connection is reused
+            assert!(client.fast_forward(FastForward::TrackBoundary).unwrap().is_ok());
+            assert!(client.fast_forward(FastForward::Seek { position: 0 }).unwrap().is_ok());
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_bad_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut header = [0; 9];
+            conn.read_exact(&mut header).unwrap();
+            let length = BigEndian::read_u32(&header[5..]);
+            Read::by_ref(&mut conn).take(length as u64).read_to_end(&mut Vec::new()).unwrap();
+
+            conn.write_u32::<BigEndian>(2).unwrap();
+            conn.write_all(&[0xFF, 0xFF]).unwrap();
+        });
+
+        let mut client = IreulClient::connect(addr).unwrap();
+        match client.fast_forward(FastForward::TrackBoundary) {
+            Err(ClientError::BadResponse(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_protocol_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut header = [0; 9];
+            conn.read_exact(&mut header).unwrap();
+            let length = BigEndian::read_u32(&header[5..]);
+            Read::by_ref(&mut conn).take(length as u64).read_to_end(&mut Vec::new()).unwrap();
+
+            let mut err = ProtocolError::new(ErrorCode::PermissionDenied, "no".to_string());
+            err.request_id = 1;
+            let resp_buf = proto::serialize(&err).unwrap();
+            conn.write_u32::<BigEndian>(resp_buf.len() as u32).unwrap();
+            conn.write_all(&resp_buf).unwrap();
+        });
+
+        let mut client = IreulClient::connect(addr).unwrap();
+        match client.fast_forward(FastForward::TrackBoundary) {
+            Err(ClientError::Protocol(err)) => {
+                assert_eq!(err.code, ErrorCode::PermissionDenied);
+                assert_eq!(err.request_id, 1);
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut ids = Vec::new();
+            for &op_code in [OP_FAST_FORWARD, OP_QUEUE_STATUS].iter() {
+                assert_eq!(conn.read_u8().unwrap(), 1);
+                assert_eq!(conn.read_u32::<BigEndian>().unwrap(), op_code);
+                ids.push(conn.read_u64::<BigEndian>().unwrap());
+                let length = conn.read_u32::<BigEndian>().unwrap();
+                Read::by_ref(&mut conn).take(length as u64).read_to_end(&mut Vec::new()).unwrap();
+            }
+
+            // answered backwards
+            let mut err = ProtocolError::new(ErrorCode::Unavailable, "busy".to_string());
+            err.request_id = ids[1];
+            let resp: FastForwardResult = Ok(());
+            for &(request_id, ref resp_buf) in [
+                (ids[1], proto::serialize(&err).unwrap()),
+                (ids[0], proto::serialize(&resp).unwrap()),
+            ].iter() {
+                conn.write_u64::<BigEndian>(request_id).unwrap();
+                conn.write_u32::<BigEndian>(resp_buf.len() as u32).unwrap();
+                conn.write_all(resp_buf).unwrap();
+            }
+
+            assert_eq!(conn.read_u8().unwrap(), 1);
+            assert_eq!(conn.read_u32::<BigEndian>().unwrap(), 0);
+        });
+
+        {
+            let mut pipeline = Pipeline::connect(addr).unwrap();
+            let ff_id = pipeline.send(&FastForwardRequest { kind: FastForward::TrackBoundary }).unwrap();
+            let status_id = pipeline.send(&StatusRequest).unwrap();
+            assert!(ff_id != status_id);
+
+            let first = pipeline.recv().unwrap();
+            assert_eq!(first.request_id, status_id);
+            match first.decode::<FastForwardResult>() {
+                Err(ClientError::Protocol(ref err)) if err.request_id == status_id => (),
+                other => panic!("unexpected result: {:?}", other),
+            }
+
+            let second = pipeline.recv().unwrap();
+            assert_eq!(second.request_id, ff_id);
+            assert!(second.decode::<FastForwardResult>().unwrap().is_ok());
+        }
+        server.join().unwrap();
+    }
+}
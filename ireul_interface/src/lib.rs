@@ -1,7 +1,10 @@
 extern crate time;
 extern crate byteorder;
 extern crate ogg;
+#[macro_use]
+extern crate ireul_interface_derive;
 
 pub mod proxy;
 pub mod oggutil;
 pub mod proto;
+pub mod client;
@@ -1,5 +1,145 @@
-use ogg::OggPageBuf;
+use ogg::{IndexedOggTrack, OggBuilder, OggPageBuf, OggTrackBuf};
+use ogg::vorbis::{VorbisPacket, VorbisPacketBuf, Comments as VorbisComments};
 
 pub struct OggTrack {
     pub pages: Vec<OggPageBuf>
 }
+
+/// Why a track can't go on air.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackError {
+    /// Not a Vorbis stream, or its positions run backwards
+    Invalid,
+
+    /// Not at the station's sample rate
+    BadSampleRate,
+}
+
+/// Checks that `track` can be played at `sample_rate` and rewrites its
+/// comments, replacing them with `metadata` if there is any.  This is
+/// the expensive part of taking a track, so it's done on the client's
+/// thread rather than the Core's.
+pub fn prepare_track(track: OggTrackBuf, metadata: Option<Vec<(String, String)>>, sample_rate: u32) -> Result<IndexedOggTrack, TrackError> {
+    let track = IndexedOggTrack::from_track(track);
+
+    if track.is_empty() {
+        return Err(TrackError::Invalid);
+    }
+
+    try!(validate_positions(&track)
+        .map_err(|()| TrackError::Invalid));
+
+    try!(validate_comment_section(&track)
+        .map_err(|()| TrackError::Invalid));
+
+    try!(check_sample_rate(sample_rate, &track)
+        .map_err(|()| TrackError::BadSampleRate));
+
+    rewrite_comments(&track, |comments| {
+        comments.vendor = "Ireul Core".to_string();
+        if let Some(ref metadata) = metadata {
+            comments.comments.clear();
+            comments.comments.extend(metadata.iter().cloned());
+        }
+    }).map_err(|()| TrackError::Invalid)
+}
+
+fn validate_positions(track: &IndexedOggTrack) -> Result<(), ()> {
+    let mut current = 0;
+    let mut is_first = true;
+
+    for page in track.pages() {
+        let position = page.position();
+
+        if is_first {
+            is_first = false;
+
+            if position != 0 {
+                return Err(());
+            }
+        }
+
+        if position < current {
+            return Err(());
+        }
+        current = position;
+    }
+
+    Ok(())
+}
+
+fn validate_comment_section(track: &IndexedOggTrack) -> Result<(), ()> {
+    let _ = try!(VorbisPacket::find_comments(track.pages()));
+    Ok(())
+}
+
+fn check_sample_rate(req: u32, track: &IndexedOggTrack) -> Result<(), ()> {
+    let packet = try!(VorbisPacket::find_identification(track.pages()));
+
+    // find_identification will always find a packet with an identification_header
+    let id_header = packet.identification_header().unwrap();
+
+    if id_header.audio_sample_rate == req {
+        Ok(())
+    } else {
+        Err(())
+    }
+}
+
+fn rewrite_comments<F>(track: &IndexedOggTrack, func: F) -> Result<IndexedOggTrack, ()>
+    where F: Fn(&mut VorbisComments) -> ()
+{
+    let mut track_rw = OggTrackBuf::empty();
+
+    for page in track.pages() {
+        // determine if we have a comment packet
+        let mut have_comment = false;
+        for packet in page.raw_packets() {
+            if let Ok(vpkt) = VorbisPacket::new(packet) {
+                if vpkt.comments().is_some() {
+                    have_comment = true;
+                }
+            }
+        }
+
+        // fast-path: no comment
+        if !have_comment {
+            track_rw.push_page(page);
+            continue;
+        }
+
+        let mut builder = OggBuilder::new();
+        for packet in page.raw_packets() {
+            let mut emitted = false;
+            if let Ok(vpkt) = VorbisPacket::new(packet) {
+                if let Some(mut comments) = vpkt.comments() {
+                    func(&mut comments);
+
+                    let new_vpkt = VorbisPacketBuf::build_comment_packet(&comments);
+                    builder.add_packet(new_vpkt.as_u8_slice());
+                    emitted = true;
+                }
+            }
+            if !emitted {
+                builder.add_packet(packet);
+            }
+        }
+
+        // the comments may no longer fit in a page
+        let mut new_page = try!(builder.build());
+        {
+            let mut tx = new_page.as_mut().begin();
+            tx.set_position(page.position());
+            tx.set_serial(page.serial());
+            tx.set_sequence(page.sequence());
+            tx.set_continued(page.continued());
+            tx.set_bos(page.bos());
+            tx.set_eos(page.eos());
+        }
+
+        track_rw.push_page(&new_page);
+    }
+
+    // every page is already known good, so skip checking them again
+    Ok(IndexedOggTrack::from_track(track_rw))
+}
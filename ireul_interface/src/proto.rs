@@ -1,3 +1,6 @@
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
 use std::io::{self, Read, Write};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
@@ -19,9 +22,28 @@ pub const TYPE_TUPLE: u16 = 0x0087;
 pub const TYPE_I16: u16 = 0x0088;
 pub const TYPE_I32: u16 = 0x0089;
 pub const TYPE_I64: u16 = 0x008a;
+/// Starts a `proxy::ProtocolError` sent in place of a response
+pub const TYPE_PROTOCOL_ERROR: u16 = 0x008b;
+/// A byte, 0 or 1
+pub const TYPE_BOOL: u16 = 0x008c;
+pub const TYPE_F64: u16 = 0x008d;
+/// A byte, 0 or 1, followed by the value if it's 1
+pub const TYPE_OPTION: u16 = 0x008e;
+/// A u32 count of key and value pairs, followed by the pairs
+pub const TYPE_MAP: u16 = 0x008f;
+/// A u32 discriminant followed by the variant's payload
+pub const TYPE_ENUM: u16 = 0x0090;
+
+// how many items we'll allocate for before they've been read, so that a
+// corrupt length can't have us allocate for data that isn't there.
+const PREALLOCATE_LIMIT: usize = 4096;
+
+// how deeply skip_entity will follow arrays and structs in arrays and
+// structs; anything deeper is surely an attack on the stack.
+const MAX_SKIP_DEPTH: u32 = 64;
 
 pub trait Deserialize: Sized {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self>;
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read;
 }
 
 pub trait Serialize {
@@ -59,6 +81,12 @@ impl<T> Serialize for [T] where T: Serialize {
     }
 }
 
+impl<T> Serialize for Vec<T> where [T]: Serialize {
+    fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
+        Serialize::write(&self[..], buf)
+    }
+}
+
 impl Serialize for () {
     fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
         try!(buf.write_u16::<BigEndian>(TYPE_VOID));
@@ -115,17 +143,70 @@ impl Serialize for str {
     }
 }
 
-// TODO(sell): this shouldn't exist.
-impl Serialize for (String, String) {
+impl Serialize for String {
+    fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
+        Serialize::write(&self[..], buf)
+    }
+}
+
+impl Serialize for bool {
+    fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
+        try!(buf.write_u16::<BigEndian>(TYPE_BOOL));
+        try!(buf.write_u8(if *self { 1 } else { 0 }));
+        Ok(())
+    }
+}
+
+impl Serialize for f64 {
     fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
-        try!(buf.write_u16::<BigEndian>(TYPE_TUPLE));
-        try!(buf.write_u32::<BigEndian>(2));
-        try!(Serialize::write(&self.0[..], buf));
-        try!(Serialize::write(&self.1[..], buf));
+        try!(buf.write_u16::<BigEndian>(TYPE_F64));
+        try!(buf.write_f64::<BigEndian>(*self));
         Ok(())
     }
 }
 
+impl<T> Serialize for Option<T> where T: Serialize {
+    fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
+        try!(buf.write_u16::<BigEndian>(TYPE_OPTION));
+        match *self {
+            Some(ref value) => {
+                try!(buf.write_u8(1));
+                try!(Serialize::write(value, buf));
+            },
+            None => try!(buf.write_u8(0)),
+        }
+        Ok(())
+    }
+}
+
+fn write_map<'a, K, V, I>(buf: &mut io::Cursor<Vec<u8>>, length: usize, pairs: I) -> io::Result<()>
+    where K: Serialize + 'a, V: Serialize + 'a, I: Iterator<Item=(&'a K, &'a V)>
+{
+    if 0xFFFFFFFF < length {
+        return Err(io::Error::new(io::ErrorKind::Other, "excessive length"));
+    }
+
+    try!(buf.write_u16::<BigEndian>(TYPE_MAP));
+    try!(buf.write_u32::<BigEndian>(length as u32));
+    for (key, value) in pairs {
+        try!(Serialize::write(key, buf));
+        try!(Serialize::write(value, buf));
+    }
+    Ok(())
+}
+
+impl<K, V> Serialize for BTreeMap<K, V> where K: Serialize + Ord, V: Serialize {
+    fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
+        write_map(buf, self.len(), self.iter())
+    }
+}
+
+impl<K, V> Serialize for HashMap<K, V> where K: Serialize + Eq + Hash, V: Serialize {
+    fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
+        write_map(buf, self.len(), self.iter())
+    }
+}
+
 impl<V, E> Serialize for Result<V, E> where V: Serialize, E: Serialize {
     fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
         match *self {
@@ -143,54 +224,22 @@ impl<V, E> Serialize for Result<V, E> where V: Serialize, E: Serialize {
 }
 
 impl Deserialize for Vec<u8> {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
-        let type_id = try!(buf.read_u16::<BigEndian>());
-        if type_id != TYPE_BLOB {
-            return Err(io::Error::new(io::ErrorKind::Other, "unexpected type"));
-        }
-
-        let length = try!(buf.read_u32::<BigEndian>());
-
-        let mut out = Vec::with_capacity(length as usize);
-        {
-            let mut limit_reader = Read::by_ref(buf).take(length as u64);
-            try!(limit_reader.read_to_end(&mut out));
-        }
-
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
+        let mut out = Vec::new();
+        try!(read_blob_into(buf, &mut out));
         Ok(out)
     }
 }
 
-// TODO(sell): this shouldn't exist.
-impl Deserialize for (String, String) {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
-        let type_id = try!(buf.read_u16::<BigEndian>());
-        if type_id != TYPE_TUPLE {
-            return Err(io::Error::new(io::ErrorKind::Other,
-                format!("unexpected type: {} != {}", TYPE_TUPLE, type_id)));
-        }
-
-        let length = try!(buf.read_u32::<BigEndian>());
-        if length != 2 {
-            return Err(io::Error::new(io::ErrorKind::Other, "unexpected length"));
-        }
-
-        let left = try!(Deserialize::read(buf));
-        let right = try!(Deserialize::read(buf));
-        Ok((left, right))
-    }
-}
-
 impl<T> Deserialize for Vec<T> where T: Deserialize {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
         let type_id = try!(buf.read_u16::<BigEndian>());
         if type_id != TYPE_ARRAY {
             return Err(io::Error::new(io::ErrorKind::Other, "unexpected type"));
         }
 
         let length = try!(buf.read_u32::<BigEndian>());
-
-        let mut out = Vec::with_capacity(length as usize);
+        let mut out = Vec::with_capacity(cmp::min(length as usize, PREALLOCATE_LIMIT));
         for _ in 0..length {
             out.push(try!(Deserialize::read(buf)));
         }
@@ -199,7 +248,7 @@ impl<T> Deserialize for Vec<T> where T: Deserialize {
 }
 
 impl Deserialize for () {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
         let type_id = try!(buf.read_u16::<BigEndian>());
         if type_id != TYPE_VOID {
             return Err(io::Error::new(io::ErrorKind::Other, "unexpected type"));
@@ -210,7 +259,7 @@ impl Deserialize for () {
 }
 
 impl Deserialize for u16 {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
         let type_id = try!(buf.read_u16::<BigEndian>());
         if type_id != TYPE_U16 {
             return Err(io::Error::new(io::ErrorKind::Other, "unexpected type"));
@@ -223,7 +272,7 @@ impl Deserialize for u16 {
 
 
 impl Deserialize for u32 {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
         let type_id = try!(buf.read_u16::<BigEndian>());
         if type_id != TYPE_U32 {
             return Err(io::Error::new(io::ErrorKind::Other, "unexpected type"));
@@ -236,7 +285,7 @@ impl Deserialize for u32 {
 
 
 impl Deserialize for u64 {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
         let type_id = try!(buf.read_u16::<BigEndian>());
         if type_id != TYPE_U64 {
             return Err(io::Error::new(io::ErrorKind::Other, "unexpected type"));
@@ -248,7 +297,7 @@ impl Deserialize for u64 {
 }
 
 impl Deserialize for i64 {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
         let type_id = try!(buf.read_u16::<BigEndian>());
         if type_id != TYPE_I64 {
             return Err(io::Error::new(io::ErrorKind::Other, "unexpected type"));
@@ -260,7 +309,7 @@ impl Deserialize for i64 {
 }
 
 impl Deserialize for String {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
         let type_id = try!(buf.read_u16::<BigEndian>());
         if type_id != TYPE_STRING {
             return Err(io::Error::new(io::ErrorKind::Other,
@@ -268,12 +317,8 @@ impl Deserialize for String {
         }
 
         let length = try!(buf.read_u32::<BigEndian>());
-
-        let mut out = Vec::with_capacity(length as usize);
-        {
-            let mut limit_reader = Read::by_ref(buf).take(length as u64);
-            try!(limit_reader.read_to_end(&mut out));
-        }
+        let mut out = Vec::with_capacity(cmp::min(length as usize, PREALLOCATE_LIMIT));
+        try!(copy_blob(buf, length, &mut out));
 
         String::from_utf8(out).map_err(|inv| {
             io::Error::new(io::ErrorKind::Other,
@@ -282,8 +327,71 @@ impl Deserialize for String {
     }
 }
 
+impl Deserialize for bool {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
+        try!(expect_type(buf, TYPE_BOOL));
+        match try!(buf.read_u8()) {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(io::Error::new(io::ErrorKind::Other, "invalid bool")),
+        }
+    }
+}
+
+impl Deserialize for f64 {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
+        try!(expect_type(buf, TYPE_F64));
+        let value = try!(buf.read_f64::<BigEndian>());
+        Ok(value)
+    }
+}
+
+impl<T> Deserialize for Option<T> where T: Deserialize {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
+        try!(expect_type(buf, TYPE_OPTION));
+        match try!(buf.read_u8()) {
+            0 => Ok(None),
+            1 => Ok(Some(try!(Deserialize::read(buf)))),
+            _ => Err(io::Error::new(io::ErrorKind::Other, "invalid option")),
+        }
+    }
+}
+
+// Reads a map's pairs, refusing duplicate keys, which would otherwise
+// have the last of them silently win.
+fn read_map<R, K, V, F>(buf: &mut R, mut insert: F) -> io::Result<()>
+    where R: Read, K: Deserialize, V: Deserialize, F: FnMut(K, V) -> bool
+{
+    try!(expect_type(buf, TYPE_MAP));
+    let length = try!(buf.read_u32::<BigEndian>());
+    for _ in 0..length {
+        let key = try!(Deserialize::read(buf));
+        let value = try!(Deserialize::read(buf));
+        if !insert(key, value) {
+            return Err(io::Error::new(io::ErrorKind::Other, "duplicate map key"));
+        }
+    }
+    Ok(())
+}
+
+impl<K, V> Deserialize for BTreeMap<K, V> where K: Deserialize + Ord, V: Deserialize {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
+        let mut out = BTreeMap::new();
+        try!(read_map(buf, |key, value| out.insert(key, value).is_none()));
+        Ok(out)
+    }
+}
+
+impl<K, V> Deserialize for HashMap<K, V> where K: Deserialize + Eq + Hash, V: Deserialize {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
+        let mut out = HashMap::new();
+        try!(read_map(buf, |key, value| out.insert(key, value).is_none()));
+        Ok(out)
+    }
+}
+
 impl<V, E> Deserialize for Result<V, E> where V: Deserialize, E: Deserialize {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
         let type_id = try!(buf.read_u16::<BigEndian>());
         match type_id {
             TYPE_RESULT_OK => {
@@ -304,6 +412,41 @@ impl<V, E> Deserialize for Result<V, E> where V: Deserialize, E: Deserialize {
 }
 
 
+macro_rules! tuple_impls {
+    ($($length:expr => ($($index:tt $name:ident)+))+) => {
+        $(
+            impl<$($name),+> Serialize for ($($name,)+) where $($name: Serialize),+ {
+                fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
+                    try!(buf.write_u16::<BigEndian>(TYPE_TUPLE));
+                    try!(buf.write_u32::<BigEndian>($length));
+                    $(
+                        try!(Serialize::write(&self.$index, buf));
+                    )+
+                    Ok(())
+                }
+            }
+
+            impl<$($name),+> Deserialize for ($($name,)+) where $($name: Deserialize),+ {
+                fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
+                    try!(expect_type(buf, TYPE_TUPLE));
+                    let length = try!(buf.read_u32::<BigEndian>());
+                    if length != $length {
+                        return Err(io::Error::new(io::ErrorKind::Other, "unexpected length"));
+                    }
+
+                    Ok(($(try!(<$name as Deserialize>::read(buf)),)+))
+                }
+            }
+        )+
+    }
+}
+
+tuple_impls! {
+    2 => (0 A 1 B)
+    3 => (0 A 1 B 2 C)
+    4 => (0 A 1 B 2 C 3 D)
+}
+
 pub fn null_read<R>(buf: &mut R, len: u64) -> io::Result<()> where R: Read {
     for byte in buf.by_ref().take(len).bytes() {
         try!(byte);
@@ -311,14 +454,21 @@ pub fn null_read<R>(buf: &mut R, len: u64) -> io::Result<()> where R: Read {
     Ok(())
 }
 
-pub fn skip_entity(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
+pub fn skip_entity<R>(buf: &mut R) -> io::Result<()> where R: Read {
+    skip_nested(buf, 0)
+}
+
+fn skip_nested<R>(buf: &mut R, depth: u32) -> io::Result<()> where R: Read {
+    if MAX_SKIP_DEPTH < depth {
+        return Err(io::Error::new(io::ErrorKind::Other, "entities nested too deeply"));
+    }
     let type_id = try!(buf.read_u16::<BigEndian>());
 
     match type_id {
         TYPE_ARRAY => {
             let length = try!(buf.read_u32::<BigEndian>());
             for _ in 0..length {
-                try!(skip_entity(buf));
+                try!(skip_nested(buf, depth + 1));
             }
             Ok(())
         }
@@ -330,8 +480,8 @@ pub fn skip_entity(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
         TYPE_STRUCT => {
             let length = try!(buf.read_u32::<BigEndian>());
             for _ in 0..length {
-                try!(skip_entity(buf));
-                try!(skip_entity(buf));
+                try!(skip_nested(buf, depth + 1));
+                try!(skip_nested(buf, depth + 1));
             }
             Ok(())
         }
@@ -368,11 +518,49 @@ pub fn skip_entity(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
             Ok(())
         }
         TYPE_RESULT_OK => {
-            try!(skip_entity(buf));
+            try!(skip_nested(buf, depth + 1));
             Ok(())
         }
         TYPE_RESULT_ERR => {
-            try!(skip_entity(buf));
+            try!(skip_nested(buf, depth + 1));
+            Ok(())
+        }
+        TYPE_PROTOCOL_ERROR => {
+            try!(skip_nested(buf, depth + 1));
+            Ok(())
+        }
+        TYPE_TUPLE => {
+            let length = try!(buf.read_u32::<BigEndian>());
+            for _ in 0..length {
+                try!(skip_nested(buf, depth + 1));
+            }
+            Ok(())
+        }
+        TYPE_BOOL => {
+            try!(buf.read_u8());
+            Ok(())
+        }
+        TYPE_F64 => {
+            try!(buf.read_f64::<BigEndian>());
+            Ok(())
+        }
+        TYPE_OPTION => {
+            if try!(buf.read_u8()) != 0 {
+                try!(skip_nested(buf, depth + 1));
+            }
+            Ok(())
+        }
+        TYPE_MAP => {
+            let length = try!(buf.read_u32::<BigEndian>());
+            for _ in 0..length {
+                try!(skip_nested(buf, depth + 1));
+                try!(skip_nested(buf, depth + 1));
+            }
+            Ok(())
+        }
+        TYPE_ENUM => {
+            try!(buf.read_u32::<BigEndian>());
+            try!(skip_nested(buf, depth + 1));
             Ok(())
         }
         _ => {
@@ -381,7 +569,7 @@ pub fn skip_entity(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
     }
 }
 
-pub fn expect_type(buf: &mut io::Cursor<Vec<u8>>, type_id: u16) -> io::Result<()> {
+pub fn expect_type<R>(buf: &mut R, type_id: u16) -> io::Result<()> where R: Read {
     let got_type_id = try!(buf.read_u16::<BigEndian>());
     if got_type_id != type_id {
         return Err(io::Error::new(io::ErrorKind::Other, "unexpected type"));
@@ -395,7 +583,7 @@ pub fn write_empty_struct(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
     Ok(())
 }
 
-pub fn read_empty_struct(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
+pub fn read_empty_struct<R>(buf: &mut R) -> io::Result<()> where R: Read {
     try!(expect_type(buf, TYPE_STRUCT));
     let field_count = try!(buf.read_u32::<BigEndian>());
     for _ in 0..field_count {
@@ -406,8 +594,84 @@ pub fn read_empty_struct(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
     Ok(())
 }
 
-pub fn deserialize<T>(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<T>
-    where T: Deserialize
+pub fn write_struct_header(buf: &mut io::Cursor<Vec<u8>>, field_count: u32) -> io::Result<()> {
+    try!(buf.write_u16::<BigEndian>(TYPE_STRUCT));
+    try!(buf.write_u32::<BigEndian>(field_count));
+    Ok(())
+}
+
+/// Reads the start of a struct, returning its field count.
+pub fn read_struct_header<R>(buf: &mut R) -> io::Result<u32> where R: Read {
+    try!(expect_type(buf, TYPE_STRUCT));
+    let field_count = try!(buf.read_u32::<BigEndian>());
+    Ok(field_count)
+}
+
+pub fn write_enum_header(buf: &mut io::Cursor<Vec<u8>>, kind: u32) -> io::Result<()> {
+    try!(buf.write_u16::<BigEndian>(TYPE_ENUM));
+    try!(buf.write_u32::<BigEndian>(kind));
+    Ok(())
+}
+
+/// Reads the start of a tagged enum, returning its discriminant.  The
+/// variant's payload follows.
+pub fn read_enum_header<R>(buf: &mut R) -> io::Result<u32> where R: Read {
+    try!(expect_type(buf, TYPE_ENUM));
+    let kind = try!(buf.read_u32::<BigEndian>());
+    Ok(kind)
+}
+
+/// Reads the type of the next entity, leaving its value to be read.
+pub fn read_type<R>(buf: &mut R) -> io::Result<u16> where R: Read {
+    let type_id = try!(buf.read_u16::<BigEndian>());
+    Ok(type_id)
+}
+
+/// Reads the value of a u32 whose type has already been read.  A struct's
+/// field count is read the same way.
+pub fn read_u32_value<R>(buf: &mut R) -> io::Result<u32> where R: Read {
+    let value = try!(buf.read_u32::<BigEndian>());
+    Ok(value)
+}
+
+/// Reads the start of a blob, returning its length.  That many bytes
+/// follow, which may be read with `copy_blob`.
+pub fn read_blob_header<R>(buf: &mut R) -> io::Result<u32> where R: Read {
+    try!(expect_type(buf, TYPE_BLOB));
+    let length = try!(buf.read_u32::<BigEndian>());
+    Ok(length)
+}
+
+/// Copies `length` bytes of a blob or string from `buf` to `out` as they
+/// arrive, so they needn't all be held in memory.  Fails if `buf` ends
+/// first.
+pub fn copy_blob<R, W>(buf: &mut R, length: u32, out: &mut W) -> io::Result<()>
+    where R: Read, W: Write
+{
+    let copied = try!(io::copy(&mut buf.by_ref().take(length as u64), out));
+    if copied != length as u64 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+            format!("length {} runs past the end of the message", length)));
+    }
+    Ok(())
+}
+
+/// Reads a blob from `buf` into `out`, returning its length.  `out` might
+/// be a track's buffer or a spool file.
+pub fn read_blob_into<R, W>(buf: &mut R, out: &mut W) -> io::Result<u32>
+    where R: Read, W: Write
+{
+    let length = try!(read_blob_header(buf));
+    try!(copy_blob(buf, length, out));
+    Ok(length)
+}
+
+pub fn missing_field(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("missing field: {}", name))
+}
+
+pub fn deserialize<T, R>(buf: &mut R) -> io::Result<T>
+    where T: Deserialize, R: Read
 {
     Deserialize::read(buf)
 }
@@ -419,3 +683,321 @@ pub fn serialize<T>(item: &T) -> io::Result<Vec<u8>>
     try!(Serialize::write(item, &mut buf));
     Ok(buf.into_inner())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::{self, Read};
+
+    use byteorder::{BigEndian, WriteBytesExt};
+
+    use super::{
+        deserialize,
+        read_blob_into,
+        serialize,
+        skip_entity,
+        write_enum_header,
+        Serialize,
+        TYPE_ARRAY,
+        TYPE_BLOB,
+        TYPE_MAP,
+        TYPE_STRING,
+        TYPE_U64,
+    };
+
+    fn cursor(buf: Vec<u8>) -> io::Cursor<Vec<u8>> {
+        io::Cursor::new(buf)
+    }
+
+    #[derive(Debug, PartialEq, ProtoSerialize, ProtoDeserialize)]
+    struct Old {
+        name: String,
+    }
+
+    #[derive(Debug, PartialEq, ProtoSerialize, ProtoDeserialize)]
+    struct New {
+        name: String,
+        nickname: Option<String>,
+        #[proto(default)]
+        ages: Vec<u64>,
+        #[proto(rename = "count")]
+        number: u32,
+    }
+
+    #[derive(Debug, PartialEq, ProtoSerialize, ProtoDeserialize)]
+    enum Shape {
+        #[proto(bare)]
+        Point,
+        Circle { radius: u64 },
+        #[proto(kind = 5)]
+        Square(#[proto(rename = "side")] u64),
+        Rectangle(u64, u64),
+    }
+
+    #[derive(Debug, PartialEq, ProtoSerialize, ProtoDeserialize)]
+    enum Colour {
+        Red = 1,
+        Green,
+        Blue = 7,
+    }
+
+    #[derive(Debug, PartialEq, ProtoSerialize, ProtoDeserialize)]
+    #[proto(tagged)]
+    enum Placement {
+        Next,
+        Last,
+        At(u32),
+        After { handle: u64, gap: Option<u64> },
+    }
+
+    #[derive(Debug, PartialEq, ProtoSerialize, ProtoDeserialize)]
+    struct Rich {
+        name: String,
+        loud: bool,
+        gain: f64,
+        pair: (u32, Option<u64>),
+        tags: BTreeMap<String, u64>,
+        placement: Placement,
+    }
+
+    // hands out a byte at a time, as a slow socket might
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    fn roundtrip<T>(item: &T) -> T where T: super::Serialize + super::Deserialize {
+        deserialize(&mut cursor(serialize(item).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn test_lengths_past_the_end() {
+        let mut buf = Vec::new();
+        buf.write_u16::<BigEndian>(TYPE_BLOB).unwrap();
+        buf.write_u32::<BigEndian>(0xFFFFFFFF).unwrap();
+        assert!(deserialize::<Vec<u8>, _>(&mut cursor(buf.clone())).is_err());
+
+        buf[1] = TYPE_STRING as u8;
+        assert!(deserialize::<String, _>(&mut cursor(buf.clone())).is_err());
+
+        buf[1] = TYPE_ARRAY as u8;
+        assert!(deserialize::<Vec<u64>, _>(&mut cursor(buf.clone())).is_err());
+        assert!(skip_entity(&mut cursor(buf)).is_err());
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let mut buf = Vec::new();
+        for _ in 0..100000 {
+            buf.write_u16::<BigEndian>(TYPE_ARRAY).unwrap();
+            buf.write_u32::<BigEndian>(1).unwrap();
+        }
+        buf.write_u16::<BigEndian>(TYPE_U64).unwrap();
+        buf.write_u64::<BigEndian>(0).unwrap();
+        assert!(skip_entity(&mut cursor(buf)).is_err());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut buf = Vec::new();
+        buf.write_u16::<BigEndian>(TYPE_U64).unwrap();
+        buf.write_u16::<BigEndian>(0).unwrap();
+        assert!(deserialize::<u64, _>(&mut cursor(buf)).is_err());
+        assert!(deserialize::<u64, _>(&mut cursor(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn test_derive_struct() {
+        let new = New {
+            name: "ireul".to_string(),
+            nickname: None,
+            ages: vec![1, 2],
+            number: 3,
+        };
+        assert_eq!(roundtrip(&new), new);
+
+        let new = New { nickname: Some("angel".to_string()), ..new };
+        assert_eq!(roundtrip(&new), new);
+
+        // unknown fields are skipped
+        let old: Old = deserialize(&mut cursor(serialize(&new).unwrap())).unwrap();
+        assert_eq!(old, Old { name: "ireul".to_string() });
+
+        // missing fields are an error, unless they may be left out
+        let buf = serialize(&old).unwrap();
+        assert!(deserialize::<New, _>(&mut cursor(buf)).is_err());
+    }
+
+    #[test]
+    fn test_derive_enum() {
+        let shapes = [
+            Shape::Point,
+            Shape::Circle { radius: 2 },
+            Shape::Square(3),
+            Shape::Rectangle(4, 5),
+        ];
+        for shape in shapes.iter() {
+            assert_eq!(&roundtrip(shape), shape);
+        }
+        assert_eq!(serialize(&Shape::Point).unwrap(), serialize(&0_u32).unwrap());
+
+        let colours = [Colour::Red, Colour::Green, Colour::Blue];
+        for (colour, value) in colours.iter().zip(&[1_u32, 2, 7]) {
+            assert_eq!(serialize(colour).unwrap(), serialize(value).unwrap());
+            assert_eq!(&roundtrip(colour), colour);
+        }
+        let buf = serialize(&3_u32).unwrap();
+        assert!(deserialize::<Colour, _>(&mut cursor(buf)).is_err());
+    }
+
+    #[test]
+    fn test_bool_and_f64() {
+        assert_eq!(serialize(&true).unwrap(), vec![0x00, 0x8c, 0x01]);
+        assert_eq!(serialize(&false).unwrap(), vec![0x00, 0x8c, 0x00]);
+        assert_eq!(serialize(&-1.5_f64).unwrap(), vec![
+            0x00, 0x8d,
+            0xbf, 0xf8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ]);
+        assert_eq!(roundtrip(&true), true);
+        assert_eq!(roundtrip(&0.25_f64), 0.25);
+        assert!(deserialize::<bool, _>(&mut cursor(vec![0x00, 0x8c, 0x02])).is_err());
+    }
+
+    #[test]
+    fn test_option() {
+        assert_eq!(serialize(&Some(7_u32)).unwrap(), vec![
+            0x00, 0x8e, 0x01,
+            0x00, 0x82, 0x00, 0x00, 0x00, 0x07,
+        ]);
+        assert_eq!(serialize(&None::<u32>).unwrap(), vec![0x00, 0x8e, 0x00]);
+        assert_eq!(roundtrip(&Some(Some(7_u32))), Some(Some(7)));
+        assert_eq!(roundtrip(&Some(None::<u32>)), Some(None));
+    }
+
+    #[test]
+    fn test_tuple() {
+        let tuple = (1_u16, "a".to_string(), true);
+        assert_eq!(serialize(&tuple).unwrap(), vec![
+            // tuple type, 3 items
+            0x00, 0x87, 0x00, 0x00, 0x00, 0x03,
+            0x00, 0x81, 0x00, 0x01,
+            0x00, 0x84, 0x00, 0x00, 0x00, 0x01, b'a',
+            0x00, 0x8c, 0x01,
+        ]);
+        assert_eq!(roundtrip(&tuple), tuple);
+
+        let buf = serialize(&(1_u16, 2_u16)).unwrap();
+        assert!(deserialize::<(u16, u16, u16), _>(&mut cursor(buf)).is_err());
+    }
+
+    #[test]
+    fn test_map() {
+        let mut map = BTreeMap::new();
+        map.insert("b".to_string(), 2_u32);
+        map.insert("a".to_string(), 1_u32);
+        assert_eq!(serialize(&map).unwrap(), vec![
+            // map type, 2 pairs
+            0x00, 0x8f, 0x00, 0x00, 0x00, 0x02,
+            0x00, 0x84, 0x00, 0x00, 0x00, 0x01, b'a',
+            0x00, 0x82, 0x00, 0x00, 0x00, 0x01,
+            0x00, 0x84, 0x00, 0x00, 0x00, 0x01, b'b',
+            0x00, 0x82, 0x00, 0x00, 0x00, 0x02,
+        ]);
+        assert_eq!(roundtrip(&map), map);
+
+        let mut buf = Vec::new();
+        buf.write_u16::<BigEndian>(TYPE_MAP).unwrap();
+        buf.write_u32::<BigEndian>(2).unwrap();
+        for _ in 0..2 {
+            buf.extend(serialize(&"a".to_string()).unwrap());
+            buf.extend(serialize(&1_u32).unwrap());
+        }
+        assert!(deserialize::<BTreeMap<String, u32>, _>(&mut cursor(buf)).is_err());
+    }
+
+    #[test]
+    fn test_tagged_enum() {
+        assert_eq!(serialize(&Placement::Last).unwrap(), vec![
+            // enum type, variant 1
+            0x00, 0x90, 0x00, 0x00, 0x00, 0x01,
+            0x00, 0x80,
+        ]);
+        assert_eq!(serialize(&Placement::At(3)).unwrap(), vec![
+            0x00, 0x90, 0x00, 0x00, 0x00, 0x02,
+            0x00, 0x82, 0x00, 0x00, 0x00, 0x03,
+        ]);
+        assert_eq!(serialize(&Placement::After { handle: 9, gap: None }).unwrap(), vec![
+            0x00, 0x90, 0x00, 0x00, 0x00, 0x03,
+            // struct type, 1 field
+            0x00, 0x05, 0x00, 0x00, 0x00, 0x01,
+            0x00, 0x84, 0x00, 0x00, 0x00, 0x06, b'h', b'a', b'n', b'd', b'l', b'e',
+            0x00, 0x83, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x09,
+        ]);
+
+        let placements = [
+            Placement::Next,
+            Placement::Last,
+            Placement::At(3),
+            Placement::After { handle: 9, gap: Some(48000) },
+        ];
+        for placement in placements.iter() {
+            assert_eq!(&roundtrip(placement), placement);
+        }
+
+        let mut buf = cursor(Vec::new());
+        write_enum_header(&mut buf, 4).unwrap();
+        Serialize::write(&(), &mut buf).unwrap();
+        assert!(deserialize::<Placement, _>(&mut cursor(buf.into_inner())).is_err());
+    }
+
+    #[test]
+    fn test_skip_new_types() {
+        let mut tags = BTreeMap::new();
+        tags.insert("plays".to_string(), 12);
+        let rich = Rich {
+            name: "ireul".to_string(),
+            loud: true,
+            gain: -3.5,
+            pair: (1, Some(2)),
+            tags: tags,
+            placement: Placement::After { handle: 9, gap: Some(1) },
+        };
+        assert_eq!(roundtrip(&rich), rich);
+
+        let old: Old = deserialize(&mut cursor(serialize(&rich).unwrap())).unwrap();
+        assert_eq!(old, Old { name: "ireul".to_string() });
+    }
+
+    #[test]
+    fn test_streaming() {
+        let new = New {
+            name: "ireul".to_string(),
+            nickname: Some("angel".to_string()),
+            ages: vec![1, 2],
+            number: 3,
+        };
+        let buf = serialize(&new).unwrap();
+        assert_eq!(deserialize::<New, _>(&mut Trickle(&buf)).unwrap(), new);
+        assert_eq!(deserialize::<New, _>(&mut &buf[..]).unwrap(), new);
+
+        let buf = serialize(&Shape::Point).unwrap();
+        assert_eq!(deserialize::<Shape, _>(&mut Trickle(&buf)).unwrap(), Shape::Point);
+
+        let mut buf = serialize(&(b"blob"[..]).to_vec()).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(read_blob_into(&mut Trickle(&buf), &mut out).unwrap(), 4);
+        assert_eq!(&out[..], b"blob");
+
+        buf.pop();
+        let err = read_blob_into(&mut Trickle(&buf), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
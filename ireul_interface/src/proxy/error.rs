@@ -0,0 +1,143 @@
+use std::io::{self, Read};
+
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+
+use ::proto::{self, Deserialize, Serialize};
+
+/// Why a request got a `ProtocolError` instead of an answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// A code this version doesn't know about
+    Unknown = 0,
+
+    /// The request didn't start with `PROTOCOL_VERSION`.  The server
+    /// hangs up after this, as it can't find the next request.
+    BadVersion = 1,
+
+    /// The op code isn't one the server serves
+    UnknownOpCode = 2,
+
+    /// The request was larger than `proto::MESSAGE_SIZE_LIMIT`.  The
+    /// server hangs up after this.
+    TooLarge = 3,
+
+    /// The request didn't decode as the op code's request type
+    MalformedRequest = 4,
+
+    /// The connection isn't logged in as a user who may do this
+    PermissionDenied = 5,
+
+    /// The server couldn't get an answer from the core
+    Unavailable = 6,
+}
+
+impl ErrorCode {
+    pub fn to_u32(&self) -> u32 {
+        *self as u32
+    }
+
+    /// Codes from newer servers become `Unknown`.
+    pub fn from_u32(val: u32) -> ErrorCode {
+        match val {
+            1 => ErrorCode::BadVersion,
+            2 => ErrorCode::UnknownOpCode,
+            3 => ErrorCode::TooLarge,
+            4 => ErrorCode::MalformedRequest,
+            5 => ErrorCode::PermissionDenied,
+            6 => ErrorCode::Unavailable,
+            _ => ErrorCode::Unknown,
+        }
+    }
+}
+
+/// Sent in place of a response when a request can't be served at all.
+/// Unlike a request's own error type, this can answer any request.
+#[derive(Debug, Clone)]
+pub struct ProtocolError {
+    pub code: ErrorCode,
+
+    /// What went wrong, for people
+    pub message: String,
+
+    /// Which request this answers: the id it was tagged with, or on a
+    /// lock-step connection its number, counting from 1.  This is 0 if
+    /// it isn't about any one request.
+    pub request_id: u64,
+}
+
+impl ProtocolError {
+    pub fn new(code: ErrorCode, message: String) -> ProtocolError {
+        ProtocolError {
+            code: code,
+            message: message,
+            request_id: 0,
+        }
+    }
+
+    /// Whether the response `frame` is a ProtocolError rather than the
+    /// response asked for.
+    pub fn is_error_frame(frame: &[u8]) -> bool {
+        2 <= frame.len() && BigEndian::read_u16(frame) == proto::TYPE_PROTOCOL_ERROR
+    }
+}
+
+// What follows the TYPE_PROTOCOL_ERROR prefix.  The code stays a u32 here
+// so that codes from newer servers can become `ErrorCode::Unknown`.
+#[derive(ProtoSerialize, ProtoDeserialize)]
+struct ProtocolErrorBody {
+    code: u32,
+    #[proto(default)]
+    message: String,
+    #[proto(default)]
+    request_id: u64,
+}
+
+impl Deserialize for ProtocolError {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
+        try!(proto::expect_type(buf, proto::TYPE_PROTOCOL_ERROR));
+        let body: ProtocolErrorBody = try!(Deserialize::read(buf));
+        Ok(ProtocolError {
+            code: ErrorCode::from_u32(body.code),
+            message: body.message,
+            request_id: body.request_id,
+        })
+    }
+}
+
+impl Serialize for ProtocolError {
+    fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
+        try!(buf.write_u16::<BigEndian>(proto::TYPE_PROTOCOL_ERROR));
+        Serialize::write(&ProtocolErrorBody {
+            code: self.code.to_u32(),
+            message: self.message.clone(),
+            request_id: self.request_id,
+        }, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{ErrorCode, ProtocolError};
+    use ::proto::{self, Deserialize};
+    use ::proxy::StatusResult;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut err = ProtocolError::new(ErrorCode::UnknownOpCode, "unknown op-code 9".to_string());
+        err.request_id = 3;
+        let buffer = proto::serialize(&err).unwrap();
+        assert!(ProtocolError::is_error_frame(&buffer));
+
+        let decoded: ProtocolError = Deserialize::read(&mut io::Cursor::new(buffer.clone())).unwrap();
+        assert_eq!(decoded.code, ErrorCode::UnknownOpCode);
+        assert_eq!(decoded.message, "unknown op-code 9");
+        assert_eq!(decoded.request_id, 3);
+
+        // it isn't mistaken for a response
+        assert!(proto::deserialize::<StatusResult, _>(&mut io::Cursor::new(buffer)).is_err());
+        assert!(!ProtocolError::is_error_frame(&proto::serialize(&Ok::<(), ()>(())).unwrap()));
+        assert_eq!(ErrorCode::from_u32(1000), ErrorCode::Unknown);
+    }
+}
@@ -0,0 +1,80 @@
+use super::{RequestType, Request};
+
+/// Asks the server what it is and what it can do.  This may be sent
+/// before logging in.
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
+pub struct HelloRequest;
+
+impl Request for HelloRequest {
+    type Value = Capabilities;
+    type Error = HelloError;
+
+    fn req_type(&self) -> RequestType {
+        RequestType::Hello
+    }
+}
+
+/// What a server supports.  Fields this version doesn't know about are
+/// skipped, so more can be added as the protocol grows.
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
+pub struct Capabilities {
+    /// The version of the ireul server, such as "0.1.0"
+    pub server_version: String,
+
+    /// The highest version byte the server understands in a request
+    pub protocol_version: u32,
+
+    /// Every op code the server serves
+    pub op_codes: Vec<u32>,
+
+    /// Codecs tracks may be encoded with, such as "vorbis"
+    pub codecs: Vec<String>,
+
+    /// Tracks must have this sample rate
+    pub sample_rate: u32,
+
+    /// The largest request or response frame, in bytes
+    pub message_size_limit: u64,
+
+    /// How many tracks may wait in the queue
+    pub queue_capacity: u32,
+}
+
+impl Capabilities {
+    pub fn supports(&self, req_type: RequestType) -> bool {
+        self.op_codes.contains(&req_type.to_op_code())
+    }
+}
+
+pub type HelloResult = Result<Capabilities, HelloError>;
+
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
+pub struct HelloError;
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::Capabilities;
+    use super::super::RequestType;
+    use ::proto::{self, Deserialize};
+
+    #[test]
+    fn test_roundtrip() {
+        let buffer = proto::serialize(&Capabilities {
+            server_version: "0.1.0".to_string(),
+            protocol_version: 0,
+            op_codes: vec![RequestType::QueueStatus.to_op_code()],
+            codecs: vec!["vorbis".to_string()],
+            sample_rate: 48000,
+            message_size_limit: 1 << 20,
+            queue_capacity: 40,
+        }).unwrap();
+        let mut cursor = io::Cursor::new(buffer);
+        let caps: Capabilities = Deserialize::read(&mut cursor).unwrap();
+        assert_eq!(caps.server_version, "0.1.0");
+        assert_eq!(caps.codecs, vec!["vorbis".to_string()]);
+        assert!(caps.supports(RequestType::QueueStatus));
+        assert!(!caps.supports(RequestType::Interrupt));
+    }
+}
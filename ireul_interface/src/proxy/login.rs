@@ -0,0 +1,28 @@
+use super::{RequestType, Request};
+
+/// Identifies the connection as one of the users in the server's config.
+/// A server with users configured refuses everything else until this has
+/// succeeded, and hangs up on a failed login.
+///
+/// Logins are handled by the server, never by a Core.
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
+pub struct LoginRequest {
+    pub user: String,
+    pub token: String,
+}
+
+impl Request for LoginRequest {
+    type Value = ();
+    type Error = LoginError;
+
+    fn req_type(&self) -> RequestType {
+        RequestType::Login
+    }
+}
+
+pub type LoginResult = Result<(), LoginError>;
+
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
+pub enum LoginError {
+    BadCredentials = 1,
+}
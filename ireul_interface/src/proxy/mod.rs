@@ -1,14 +1,45 @@
 extern crate time;
 
+use std::io::{self, Read};
+use std::time::Duration;
 use std::sync::mpsc::{
     self,
     TrySendError,
     RecvError
 };
 
+use ogg::{IndexedOggTrack, OggTrackBuf};
+
+use ::proto::{self, Deserialize, Serialize};
+use ::oggutil;
+
 pub mod track;
+mod login;
+mod error;
+mod hello;
+
+pub use self::error::{
+    ErrorCode,
+    ProtocolError,
+};
+
+pub use self::hello::{
+    HelloRequest,
+    HelloResult,
+    HelloError,
+    Capabilities,
+};
+
+pub use self::login::{
+    LoginRequest,
+    LoginResult,
+    LoginError,
+};
 
 pub use self::track::{
+    StatusRequest,
+    StatusResult,
+    StatusError,
     EnqueueTrackRequest,
     EnqueueTrackResult,
     EnqueueTrackError,
@@ -19,6 +50,19 @@ pub use self::track::{
     ReplaceFallbackRequest,
     ReplaceFallbackResult,
     ReplaceFallbackError,
+    InterruptRequest,
+    InterruptResult,
+    InterruptError,
+    Event,
+    SubscribeRequest,
+    SubscribeResult,
+    SubscribeError,
+    RemoveTrackRequest,
+    RemoveTrackResult,
+    RemoveTrackError,
+    MoveTrackRequest,
+    MoveTrackResult,
+    MoveTrackError,
 };
 
 // pub const SIZE_LIMIT: bincode::SizeLimit = bincode::SizeLimit::Bounded(20 * 1 << 20);
@@ -27,14 +71,54 @@ pub const OP_ENQUEUE_TRACK: u32 = 0x1000;
 pub const OP_FAST_FORWARD: u32 = 0x1001;
 pub const OP_QUEUE_STATUS: u32 = 0x1002;
 pub const OP_REPLACE_FALLBACK: u32 = 0x1003;
+pub const OP_INTERRUPT: u32 = 0x1004;
+pub const OP_SUBSCRIBE: u32 = 0x1005;
+pub const OP_REMOVE_TRACK: u32 = 0x1006;
+pub const OP_MOVE_TRACK: u32 = 0x1007;
+pub const OP_LOGIN: u32 = 0x1008;
+pub const OP_HELLO: u32 = 0x1009;
+
+/// The version byte which starts every request on a connection answered
+/// in lock-step, one request at a time.
+pub const PROTOCOL_VERSION: u8 = 0;
+
+/// The version byte which starts every request on a connection whose
+/// requests carry ids, so that several may be in flight at once.  A
+/// connection sticks with the version its first request used.
+pub const PROTOCOL_VERSION_TAGGED: u8 = 1;
+
+// events a subscriber hasn't picked up yet; falling further behind than
+// this gets it dropped.
+pub const EVENT_QUEUE_SIZE: usize = 64;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RequestType {
     EnqueueTrack,
     FastForward,
     QueueStatus,
     ReplaceFallback,
+    Interrupt,
+    Subscribe,
+    RemoveTrack,
+    MoveTrack,
+    Login,
+    Hello,
 }
 
+/// Every request a server built from this crate serves.
+pub const REQUEST_TYPES: &'static [RequestType] = &[
+    RequestType::EnqueueTrack,
+    RequestType::FastForward,
+    RequestType::QueueStatus,
+    RequestType::ReplaceFallback,
+    RequestType::Interrupt,
+    RequestType::Subscribe,
+    RequestType::RemoveTrack,
+    RequestType::MoveTrack,
+    RequestType::Login,
+    RequestType::Hello,
+];
+
 impl RequestType {
     pub fn from_op_code(op_code: u32) -> Result<RequestType, ()> {
         match op_code {
@@ -42,6 +126,12 @@ impl RequestType {
             OP_FAST_FORWARD => Ok(RequestType::FastForward),
             OP_QUEUE_STATUS => Ok(RequestType::QueueStatus),
             OP_REPLACE_FALLBACK => Ok(RequestType::ReplaceFallback),
+            OP_INTERRUPT => Ok(RequestType::Interrupt),
+            OP_SUBSCRIBE => Ok(RequestType::Subscribe),
+            OP_REMOVE_TRACK => Ok(RequestType::RemoveTrack),
+            OP_MOVE_TRACK => Ok(RequestType::MoveTrack),
+            OP_LOGIN => Ok(RequestType::Login),
+            OP_HELLO => Ok(RequestType::Hello),
             _ => Err(())
         }
     }
@@ -52,16 +142,276 @@ impl RequestType {
             RequestType::FastForward => OP_FAST_FORWARD,
             RequestType::QueueStatus => OP_QUEUE_STATUS,
             RequestType::ReplaceFallback => OP_REPLACE_FALLBACK,
+            RequestType::Interrupt => OP_INTERRUPT,
+            RequestType::Subscribe => OP_SUBSCRIBE,
+            RequestType::RemoveTrack => OP_REMOVE_TRACK,
+            RequestType::MoveTrack => OP_MOVE_TRACK,
+            RequestType::Login => OP_LOGIN,
+            RequestType::Hello => OP_HELLO,
+        }
+    }
+
+    /// Whether the request is one with a track, which `CoreProxy` checks
+    /// before the Core sees it.
+    pub fn carries_track(&self) -> bool {
+        match *self {
+            RequestType::EnqueueTrack |
+            RequestType::Interrupt |
+            RequestType::ReplaceFallback => true,
+            _ => false,
         }
     }
 }
 
 
+/// What a request brings the Core.
+pub enum RequestBody {
+    /// The request in its serialized representation
+    Raw(Vec<u8>),
+
+    /// The track of an enqueue, interrupt or fallback request, already
+    /// checked and rewritten by `oggutil::prepare_track`
+    Track(IndexedOggTrack),
+}
+
 pub struct RequestWrapper {
-    // the Vec<u8>s are a bincode serialized representation
+    // the Vec<u8>s are a bincode serialized representation.  A subscriber
+    // keeps receiving Events on its response_queue after the response.
     pub response_queue: mpsc::SyncSender<Vec<u8>>,
     pub req_type: RequestType,
-    pub req_buf: Vec<u8>,
+    pub body: RequestBody,
+}
+
+/// The sending half of a Core's request queue, with a typed method per
+/// request.  Responses cross the channel in their wire representation,
+/// as do requests other than those carrying tracks: those are prepared on
+/// the caller's thread, so the Core has only to queue them.
+#[derive(Clone)]
+pub struct CoreProxy {
+    sender: mpsc::SyncSender<RequestWrapper>,
+    // the sample rate tracks must have to be accepted
+    sample_rate: u32,
+}
+
+impl CoreProxy {
+    pub fn new(sender: mpsc::SyncSender<RequestWrapper>, sample_rate: u32) -> CoreProxy {
+        CoreProxy {
+            sender: sender,
+            sample_rate: sample_rate,
+        }
+    }
+
+    /// Sends an already serialized request and waits for the serialized
+    /// response.
+    pub fn call_raw(&self, req_type: RequestType, req_buf: Vec<u8>) -> Result<Vec<u8>, RpcError> {
+        let responses = try!(self.start_call_raw(req_type, req_buf));
+        Ok(try!(responses.recv()))
+    }
+
+    /// Sends an already serialized request without waiting for the
+    /// response, which arrives on the returned receiver.  The Core serves
+    /// requests in the order they're sent.
+    pub fn start_call_raw(&self, req_type: RequestType, req_buf: Vec<u8>) -> Result<mpsc::Receiver<Vec<u8>>, RpcError> {
+        if req_type.carries_track() {
+            return self.start_track_call_from(req_type, &mut io::Cursor::new(req_buf));
+        }
+        self.send_raw(req_type, RequestBody::Raw(req_buf), 1)
+    }
+
+    /// As `start_call_raw`, for a request which carries a track, decoding
+    /// it as it's read from `body`: there's no sense in buffering a track
+    /// twice.  A request which doesn't decode is answered with a
+    /// `ProtocolError`.
+    pub fn start_track_call_from<R: Read>(&self, req_type: RequestType, body: &mut R) -> Result<mpsc::Receiver<Vec<u8>>, RpcError> {
+        let decoded = match req_type {
+            RequestType::EnqueueTrack => {
+                proto::deserialize(body).map(|req: EnqueueTrackRequest| (req.track, req.metadata))
+            },
+            RequestType::Interrupt => {
+                proto::deserialize(body).map(|req: InterruptRequest| (req.track, req.metadata))
+            },
+            RequestType::ReplaceFallback => {
+                proto::deserialize(body).map(|req: ReplaceFallbackRequest| (req.track, req.metadata))
+            },
+            _ => Err(io::Error::new(io::ErrorKind::Other, "no track to read")),
+        };
+        match decoded {
+            Ok((track, metadata)) => self.start_track_call(req_type, track, metadata),
+            Err(err) => {
+                let message = format!("bad {:?} request: {}", req_type, err);
+                answered(&ProtocolError::new(ErrorCode::MalformedRequest, message))
+            },
+        }
+    }
+
+    // Prepares the track here, sending the Core only one it can play.
+    fn start_track_call(&self, req_type: RequestType, track: OggTrackBuf, metadata: Option<Vec<(String, String)>>)
+        -> Result<mpsc::Receiver<Vec<u8>>, RpcError>
+    {
+        let err = match oggutil::prepare_track(track, metadata, self.sample_rate) {
+            Ok(track) => return self.send_raw(req_type, RequestBody::Track(track), 1),
+            Err(err) => err,
+        };
+        match req_type {
+            RequestType::EnqueueTrack => {
+                let result: EnqueueTrackResult = Err(EnqueueTrackError::from(err));
+                answered(&result)
+            },
+            RequestType::Interrupt => {
+                let result: InterruptResult = Err(InterruptError::from(err));
+                answered(&result)
+            },
+            _ => {
+                let result: ReplaceFallbackResult = Err(ReplaceFallbackError::from(err));
+                answered(&result)
+            },
+        }
+    }
+
+    /// Sends an already serialized `SubscribeRequest`, returning the
+    /// serialized response and the subscription it may have started.
+    pub fn subscribe_raw(&self, req_buf: Vec<u8>) -> Result<(Vec<u8>, Subscription), RpcError> {
+        let responses = try!(self.send_raw(RequestType::Subscribe, RequestBody::Raw(req_buf), EVENT_QUEUE_SIZE));
+        let response = try!(responses.recv());
+        Ok((response, Subscription { events: responses }))
+    }
+
+    fn send_raw(&self, req_type: RequestType, body: RequestBody, queue_size: usize) -> Result<mpsc::Receiver<Vec<u8>>, RpcError> {
+        let (response_queue, responses) = mpsc::sync_channel(queue_size);
+        try!(self.sender.send(RequestWrapper {
+            response_queue: response_queue,
+            req_type: req_type,
+            body: body,
+        }).map_err(|_| RpcError::SendError(TrySendError::Disconnected(()))));
+
+        Ok(responses)
+    }
+
+    pub fn call<R>(&self, req: &R) -> ProxyResult<R::Value, R::Error>
+        where
+            R: Request + Serialize,
+            R::Value: Deserialize,
+            R::Error: Deserialize
+    {
+        let req_buf = try!(proto::serialize(req)
+            .map_err(|_| RpcError::SerializeError));
+        let resp_buf = try!(self.call_raw(req.req_type(), req_buf));
+        decode_response(resp_buf)
+    }
+
+    pub fn enqueue_track(&self, req: EnqueueTrackRequest) -> ProxyResult<track::model::Handle, EnqueueTrackError> {
+        let responses = try!(self.start_track_call(RequestType::EnqueueTrack, req.track, req.metadata));
+        decode_response(try!(responses.recv().map_err(RpcError::from)))
+    }
+
+    pub fn fast_forward(&self, req: FastForwardRequest) -> ProxyResult<(), FastForwardError> {
+        self.call(&req)
+    }
+
+    pub fn queue_status(&self, req: StatusRequest) -> ProxyResult<track::model::Queue, StatusError> {
+        self.call(&req)
+    }
+
+    pub fn replace_fallback(&self, req: ReplaceFallbackRequest) -> ProxyResult<(), ReplaceFallbackError> {
+        let responses = try!(self.start_track_call(RequestType::ReplaceFallback, req.track, req.metadata));
+        decode_response(try!(responses.recv().map_err(RpcError::from)))
+    }
+
+    pub fn interrupt(&self, req: InterruptRequest) -> ProxyResult<track::model::Handle, InterruptError> {
+        let responses = try!(self.start_track_call(RequestType::Interrupt, req.track, req.metadata));
+        decode_response(try!(responses.recv().map_err(RpcError::from)))
+    }
+
+    pub fn hello(&self, req: HelloRequest) -> ProxyResult<Capabilities, HelloError> {
+        self.call(&req)
+    }
+
+    pub fn remove_track(&self, req: RemoveTrackRequest) -> ProxyResult<(), RemoveTrackError> {
+        self.call(&req)
+    }
+
+    pub fn move_track(&self, req: MoveTrackRequest) -> ProxyResult<(), MoveTrackError> {
+        self.call(&req)
+    }
+
+    pub fn subscribe(&self, req: SubscribeRequest) -> ProxyResult<Subscription, SubscribeError> {
+        let req_buf = try!(proto::serialize(&req)
+            .map_err(|_| RpcError::SerializeError));
+        let (resp_buf, subscription) = try!(self.subscribe_raw(req_buf));
+        try!(check_error_frame(&resp_buf));
+
+        let mut cursor = io::Cursor::new(resp_buf);
+        let resp: SubscribeResult = try!(proto::deserialize(&mut cursor)
+            .map_err(|_| RpcError::DeserializeError));
+        try!(resp.map_err(ProxyError::CallError));
+        Ok(subscription)
+    }
+}
+
+// A response which doesn't need the Core, waiting to be received.
+fn answered<T: Serialize>(response: &T) -> Result<mpsc::Receiver<Vec<u8>>, RpcError> {
+    let response = try!(proto::serialize(response)
+        .map_err(|_| RpcError::SerializeError));
+    let (response_queue, responses) = mpsc::sync_channel(1);
+    try!(response_queue.send(response)
+        .map_err(|_| RpcError::SendError(TrySendError::Disconnected(()))));
+    Ok(responses)
+}
+
+fn decode_response<T, E>(resp_buf: Vec<u8>) -> ProxyResult<T, E>
+    where
+        T: Deserialize,
+        E: Deserialize
+{
+    try!(check_error_frame(&resp_buf));
+
+    let mut cursor = io::Cursor::new(resp_buf);
+    let resp: Result<T, E> = try!(proto::deserialize(&mut cursor)
+        .map_err(|_| RpcError::DeserializeError));
+    resp.map_err(ProxyError::CallError)
+}
+
+// Turns a ProtocolError sent in place of a response into an RpcError.
+fn check_error_frame(resp_buf: &[u8]) -> Result<(), RpcError> {
+    if !ProtocolError::is_error_frame(resp_buf) {
+        return Ok(());
+    }
+    let mut cursor = io::Cursor::new(resp_buf.to_vec());
+    let err: ProtocolError = try!(proto::deserialize(&mut cursor)
+        .map_err(|_| RpcError::DeserializeError));
+    Err(RpcError::Protocol(err))
+}
+
+/// Events from a Core, in the order they happened.  The Core drops
+/// subscribers which fall `EVENT_QUEUE_SIZE` events behind, after which
+/// `recv` fails.
+pub struct Subscription {
+    events: mpsc::Receiver<Vec<u8>>,
+}
+
+impl Subscription {
+    /// Waits for the next event, serialized.
+    pub fn recv_raw(&self) -> Result<Vec<u8>, RpcError> {
+        Ok(try!(self.events.recv()))
+    }
+
+    pub fn recv(&self) -> Result<Event, RpcError> {
+        let event_buf = try!(self.recv_raw());
+        let mut cursor = io::Cursor::new(event_buf);
+        proto::deserialize(&mut cursor).map_err(|_| RpcError::DeserializeError)
+    }
+
+    /// Waits up to `timeout` for the next event, giving None if there
+    /// wasn't one.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<Event>, RpcError> {
+        let event_buf = match self.events.recv_timeout(timeout) {
+            Ok(event_buf) => event_buf,
+            Err(mpsc::RecvTimeoutError::Timeout) => return Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Err(RpcError::RecvError(RecvError)),
+        };
+        let mut cursor = io::Cursor::new(event_buf);
+        proto::deserialize(&mut cursor).map(Some).map_err(|_| RpcError::DeserializeError)
+    }
 }
 
 pub type BinderResult<T, E> = Result<T, BinderError<E>>;
@@ -87,6 +437,7 @@ impl<T> From<ProxyError<T>> for BinderError<T> {
 
 pub type ProxyResult<T, E> = Result<T, ProxyError<E>>;
 
+#[derive(Debug)]
 pub enum ProxyError<T> {
     CallError(T),
     StubImplementation,
@@ -110,11 +461,14 @@ impl<T> From<RpcError> for ProxyError<T> {
     }
 }
 
+#[derive(Debug)]
 pub enum RpcError {
     SendError(TrySendError<()>),
     RecvError(RecvError),
     SerializeError,
     DeserializeError,
+    /// The Core couldn't serve the request at all
+    Protocol(ProtocolError),
 }
 
 impl<T> From<TrySendError<T>> for RpcError {
@@ -142,3 +496,106 @@ pub trait Request: Sized {
 
     fn req_type(&self) -> RequestType;
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use ogg::OggTrackBuf;
+
+    use ::proto;
+    use super::{
+        CoreProxy,
+        EnqueueTrackError,
+        EnqueueTrackRequest,
+        FastForward,
+        FastForwardError,
+        FastForwardRequest,
+        FastForwardResult,
+        ProxyError,
+        RequestBody,
+        RequestType,
+        RequestWrapper,
+    };
+
+    #[test]
+    fn test_call() {
+        let (sender, requests) = mpsc::sync_channel::<RequestWrapper>(1);
+        let proxy = CoreProxy::new(sender, 48000);
+
+        let core = thread::spawn(move || {
+            for req in requests.iter() {
+                assert_eq!(req.req_type, RequestType::FastForward);
+                let mut cursor = match req.body {
+                    RequestBody::Raw(req_buf) => io::Cursor::new(req_buf),
+                    RequestBody::Track(_) => panic!("a fast-forward with a track"),
+                };
+                let ff_req: FastForwardRequest = proto::deserialize(&mut cursor).unwrap();
+                let resp: FastForwardResult = match ff_req.kind {
+                    FastForward::TrackBoundary => Ok(()),
+                    _ => Err(FastForwardError),
+                };
+                req.response_queue.send(proto::serialize(&resp).unwrap()).unwrap();
+            }
+        });
+
+        let req = FastForwardRequest { kind: FastForward::TrackBoundary };
+        assert!(proxy.fast_forward(req).is_ok());
+
+        let req = FastForwardRequest { kind: FastForward::Seek { position: 48000 } };
+        match proxy.fast_forward(req) {
+            Err(ProxyError::CallError(FastForwardError)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        drop(proxy);
+        core.join().unwrap();
+    }
+
+    #[test]
+    fn test_call_without_response() {
+        let (sender, requests) = mpsc::sync_channel::<RequestWrapper>(1);
+        let proxy = CoreProxy::new(sender, 48000);
+
+        // the core drops requests it can't understand.
+        let core = thread::spawn(move || {
+            for _ in requests.iter() {}
+        });
+
+        let req = FastForwardRequest { kind: FastForward::TrackBoundary };
+        match proxy.fast_forward(req) {
+            Err(ProxyError::RpcError(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        drop(proxy);
+        core.join().unwrap();
+    }
+
+    #[test]
+    fn test_invalid_track() {
+        let (sender, requests) = mpsc::sync_channel::<RequestWrapper>(1);
+        let proxy = CoreProxy::new(sender, 48000);
+
+        // tracks are checked before they get this far
+        let core = thread::spawn(move || {
+            for _ in requests.iter() {
+                panic!("the core was sent an invalid track");
+            }
+        });
+
+        let req = EnqueueTrackRequest {
+            track: OggTrackBuf::new(Vec::new()).unwrap(),
+            metadata: None,
+        };
+        match proxy.enqueue_track(req) {
+            Err(ProxyError::CallError(EnqueueTrackError::InvalidTrack)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        drop(proxy);
+        core.join().unwrap();
+    }
+}
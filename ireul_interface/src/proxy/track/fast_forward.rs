@@ -1,89 +1,32 @@
-use std::io;
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use super::super::{RequestType, Request};
-use ::proto::{self, Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+// TrackBoundary is written as a bare u32 as it always has been.  The seek
+// variants carry a payload, so they're written as a struct with the same
+// discriminant in its `kind` field.
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
 pub enum FastForward {
-    TrackBoundary = 0,
-}
+    /// Skips to the end of the currently playing track
+    #[proto(bare)]
+    TrackBoundary,
 
-impl Deserialize for FastForward {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
-        let num: u32 = try!(Deserialize::read(buf));
-        FastForward::from_u32(num)
-            .ok_or_else(|| {
-                io::Error::new(io::ErrorKind::Other, "unexpected FastForward value")
-            })
-    }
-}
+    /// Jumps to the given sample within the currently playing track
+    Seek { position: u64 },
 
-impl Serialize for FastForward {
-    fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
-        try!(buf.write_u16::<BigEndian>(proto::TYPE_U32));
-        try!(buf.write_u32::<BigEndian>(self.clone() as u32));
-        Ok(())
-    }
-}
+    /// Jumps ahead by the given number of samples
+    SeekForward { samples: u64 },
 
-impl FastForward {
-    pub fn from_u32(n: u32) -> Option<FastForward> {
-        match n {
-            0 => Some(FastForward::TrackBoundary),
-            _ => None,
-        }
-    }
+    /// Jumps back by the given number of samples
+    SeekBackward { samples: u64 },
 }
 
-const REQUEST_FIELD_COUNT: u32 = 1;
-
-/// Skips to the end of the currently playing track
-#[derive(Debug, Clone)]
+/// Skips to the end of, or seeks within, the currently playing track
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
 pub struct FastForwardRequest {
     pub kind: FastForward,
 }
 
-impl Deserialize for FastForwardRequest {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
-        try!(proto::expect_type(buf, proto::TYPE_STRUCT));
-        let field_count = try!(buf.read_u32::<BigEndian>());
-
-        let mut kind: Option<FastForward> = None;
-        for _ in 0..field_count {
-            let field_name: String = try!(Deserialize::read(buf));
-            match &field_name[..] {
-                "kind" => {
-                    kind = Some(try!(Deserialize::read(buf)));
-                },
-                _ => try!(proto::skip_entity(buf)),
-            }
-        }
-
-        let kind = match kind {
-            Some(kind) => kind,
-            None => return Err(io::Error::new(io::ErrorKind::Other, "missing field: kind")),
-        };
-
-        Ok(FastForwardRequest {
-            kind: kind,
-        })
-    }
-}
-
-impl Serialize for FastForwardRequest {
-    fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
-        try!(buf.write_u16::<BigEndian>(proto::TYPE_STRUCT));
-        try!(buf.write_u32::<BigEndian>(REQUEST_FIELD_COUNT));
-
-        try!(Serialize::write("kind", buf));
-        try!(Serialize::write(&self.kind, buf));
-
-        Ok(())
-    }
-}
-
 impl Request for FastForwardRequest {
     type Value = ();
     type Error = FastForwardError;
@@ -95,19 +38,82 @@ impl Request for FastForwardRequest {
 
 pub type FastForwardResult = Result<(), FastForwardError>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
 pub struct FastForwardError;
 
-impl Deserialize for FastForwardError {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
-        try!(proto::read_empty_struct(buf));
-        Ok(FastForwardError)
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::FastForward;
+    use ::proto::{self, Serialize, Deserialize};
+
+    fn roundtrip(item: &FastForward) -> (Vec<u8>, FastForward) {
+        let buffer = proto::serialize(item).unwrap();
+        let mut cursor = io::Cursor::new(buffer.clone());
+        let out: FastForward = Deserialize::read(&mut cursor).unwrap();
+        (buffer, out)
+    }
+
+    #[test]
+    fn test_track_boundary() {
+        let (buffer, out) = roundtrip(&FastForward::TrackBoundary);
+        assert_eq!(&buffer[..], &[
+            // u32 type
+            0x00, 0x82,
+            // value of 0_u32
+            0x00, 0x00, 0x00, 0x00,
+        ]);
+        match out {
+            FastForward::TrackBoundary => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_seek() {
+        let (buffer, out) = roundtrip(&FastForward::Seek { position: 0x10000 });
+        assert_eq!(&buffer[..], &[
+            // struct type, 2 fields
+            0x00, 0x05, 0x00, 0x00, 0x00, 0x02,
+            // "kind": 1_u32
+            0x00, 0x84, 0x00, 0x00, 0x00, 0x04, b'k', b'i', b'n', b'd',
+            0x00, 0x82, 0x00, 0x00, 0x00, 0x01,
+            // "position": 0x10000_u64
+            0x00, 0x84, 0x00, 0x00, 0x00, 0x08,
+            b'p', b'o', b's', b'i', b't', b'i', b'o', b'n',
+            0x00, 0x83, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+        ]);
+        match out {
+            FastForward::Seek { position: 0x10000 } => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_seek_relative() {
+        match roundtrip(&FastForward::SeekForward { samples: 48000 }).1 {
+            FastForward::SeekForward { samples: 48000 } => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match roundtrip(&FastForward::SeekBackward { samples: 96000 }).1 {
+            FastForward::SeekBackward { samples: 96000 } => (),
+            other => panic!("unexpected {:?}", other),
+        }
     }
-}
 
-impl Serialize for FastForwardError {
-    fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
-        try!(proto::write_empty_struct(buf));
-        Ok(())
+    #[test]
+    fn test_missing_field() {
+        let mut buffer = io::Cursor::new(Vec::new());
+        proto::write_empty_struct(&mut buffer).unwrap();
+        let mut cursor = io::Cursor::new(buffer.into_inner());
+        let out: io::Result<FastForward> = Deserialize::read(&mut cursor);
+        assert!(out.is_err());
+
+        let mut buffer = io::Cursor::new(Vec::new());
+        Serialize::write(&5_u32, &mut buffer).unwrap();
+        let mut cursor = io::Cursor::new(buffer.into_inner());
+        let out: io::Result<FastForward> = Deserialize::read(&mut cursor);
+        assert!(out.is_err());
     }
 }
@@ -0,0 +1,41 @@
+use ogg::{OggTrackBuf};
+
+use oggutil::TrackError;
+
+use super::super::{RequestType, Request};
+use super::model::Handle;
+
+/// Breaks into the currently playing track with the given track.  The
+/// interrupted track resumes from where it stopped afterwards.
+#[derive(Clone, ProtoSerialize, ProtoDeserialize)]
+pub struct InterruptRequest {
+    pub track: OggTrackBuf,
+    pub metadata: Option<Vec<(String, String)>>,
+}
+
+impl Request for InterruptRequest {
+    type Value = Handle;
+    type Error = InterruptError;
+
+    fn req_type(&self) -> RequestType {
+        RequestType::Interrupt
+    }
+}
+
+pub type InterruptResult = Result<Handle, InterruptError>;
+
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
+pub enum InterruptError {
+    InvalidTrack = 1,
+
+    BadSampleRate = 2,
+}
+
+impl From<TrackError> for InterruptError {
+    fn from(err: TrackError) -> InterruptError {
+        match err {
+            TrackError::Invalid => InterruptError::InvalidTrack,
+            TrackError::BadSampleRate => InterruptError::BadSampleRate,
+        }
+    }
+}
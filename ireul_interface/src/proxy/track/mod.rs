@@ -3,6 +3,10 @@ mod fast_forward;
 pub mod model;
 mod status;
 mod replace_fallback;
+mod interrupt;
+mod subscribe;
+mod remove;
+mod move_track;
 
 pub use self::enqueue::{
     EnqueueTrackRequest,
@@ -28,3 +32,28 @@ pub use self::replace_fallback::{
     ReplaceFallbackResult,
     ReplaceFallbackError,
 };
+
+pub use self::interrupt::{
+    InterruptRequest,
+    InterruptResult,
+    InterruptError,
+};
+
+pub use self::subscribe::{
+    Event,
+    SubscribeRequest,
+    SubscribeResult,
+    SubscribeError,
+};
+
+pub use self::remove::{
+    RemoveTrackRequest,
+    RemoveTrackResult,
+    RemoveTrackError,
+};
+
+pub use self::move_track::{
+    MoveTrackRequest,
+    MoveTrackResult,
+    MoveTrackError,
+};
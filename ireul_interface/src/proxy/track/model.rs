@@ -1,29 +1,13 @@
-use std::io;
+use std::io::{self, Read};
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use ogg::OggTrackBuf;
 
-use ::proto::{self, Serialize, Deserialize};
+use ::proto::{Serialize, Deserialize};
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, ProtoSerialize, ProtoDeserialize)]
 pub struct Handle(pub u64);
 
-impl Deserialize for Handle {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
-        let val: u64 = try!(Deserialize::read(buf));
-        Ok(Handle(val))
-    }
-}
-
-impl Serialize for Handle {
-    fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
-        try!(Serialize::write(&self.0, buf));
-        Ok(())
-    }
-}
-
-const TRACK_INFO_FIELD_COUNT: u32 = 8;
-
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, ProtoSerialize, ProtoDeserialize)]
 pub struct TrackInfo {
     pub handle: Handle,
     pub started_at: Option<i64>,
@@ -39,201 +23,29 @@ pub struct TrackInfo {
     pub metadata: Vec<(String, String)>,
 }
 
-impl Deserialize for TrackInfo {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
-        try!(proto::expect_type(buf, proto::TYPE_STRUCT));
-        let field_count = try!(buf.read_u32::<BigEndian>());
-
-        let mut handle: Option<Handle> = None;
-        let mut started_at: Option<i64> = None;
-        let mut artist: Option<String> = None;
-        let mut album: Option<String> = None;
-        let mut title: Option<String> = None;
-        let mut sample_rate: Option<u64> = None;
-        let mut sample_count: Option<u64> = None;
-        let mut sample_position: Option<u64> = None;
-        let mut metadata: Option<Vec<(String, String)>> = None;
-
-        for _ in 0..field_count {
-            let field_name: String = try!(Deserialize::read(buf));
-            match &field_name[..] {
-                "handle" => {
-                    handle = Some(try!(Deserialize::read(buf)));
-                },
-                "started_at" => {
-                    started_at = Some(try!(Deserialize::read(buf)));
-                }
-                "artist" => {
-                    artist = Some(try!(Deserialize::read(buf)));
-                },
-                "album" => {
-                    album = Some(try!(Deserialize::read(buf)));
-                },
-                "title" => {
-                    title = Some(try!(Deserialize::read(buf)));
-                },
-                "sample_rate" => {
-                    sample_rate = Some(try!(Deserialize::read(buf)));
-                },
-                "sample_count" => {
-                    sample_count = Some(try!(Deserialize::read(buf)));
-                },
-                "sample_position" => {
-                    sample_position = Some(try!(Deserialize::read(buf)));
-                },
-                "metadata" => {
-                    metadata = Some(try!(Deserialize::read(buf)));
-                },
-                _ => try!(proto::skip_entity(buf)),
-            }
-        }
-
-        let handle = match handle {
-            Some(handle) => handle,
-            None => return Err(io::Error::new(io::ErrorKind::Other, "missing field: handle")),
-        };
-        let artist = match artist {
-            Some(artist) => artist,
-            None => return Err(io::Error::new(io::ErrorKind::Other, "missing field: artist")),
-        };
-        let album = match album {
-            Some(album) => album,
-            None => return Err(io::Error::new(io::ErrorKind::Other, "missing field: album")),
-        };
-        let title = match title {
-            Some(title) => title,
-            None => return Err(io::Error::new(io::ErrorKind::Other, "missing field: title")),
-        };
-        let sample_rate = match sample_rate {
-            Some(sample_rate) => sample_rate,
-            None => return Err(io::Error::new(io::ErrorKind::Other, "missing field: sample_rate")),
-        };
-        let sample_count = match sample_count {
-            Some(sample_count) => sample_count,
-            None => return Err(io::Error::new(io::ErrorKind::Other, "missing field: sample_count")),
-        };
-        let sample_position = match sample_position {
-            Some(sample_position) => sample_position,
-            None => return Err(io::Error::new(io::ErrorKind::Other, "missing field: sample_position")),
-        };
-        let metadata = match metadata {
-            Some(metadata) => metadata,
-            None => return Err(io::Error::new(io::ErrorKind::Other, "missing field: metadata")),
-        };
-
-        Ok(TrackInfo {
-            handle: handle,
-            started_at: started_at,
-            artist: artist,
-            album: album,
-            title: title,
-            sample_rate: sample_rate,
-            sample_count: sample_count,
-            sample_position: sample_position,
-            metadata: metadata,
-        })
-    }
-}
-
-impl Serialize for TrackInfo {
-    fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
-        try!(buf.write_u16::<BigEndian>(proto::TYPE_STRUCT));
-
-        let mut field_count = TRACK_INFO_FIELD_COUNT;
-        if self.started_at.is_some() {
-            field_count += 1;
-        }
-
-        try!(buf.write_u32::<BigEndian>(field_count));
-
-        try!(Serialize::write("handle", buf));
-        try!(Serialize::write(&self.handle, buf));
-
-        if let Some(started_at) = self.started_at {
-            try!(Serialize::write("started_at", buf));
-            try!(Serialize::write(&started_at, buf));
-        }
-
-        try!(Serialize::write("artist", buf));
-        try!(Serialize::write(&*self.artist, buf));
-
-        try!(Serialize::write("album", buf));
-        try!(Serialize::write(&*self.album, buf));
-
-        try!(Serialize::write("title", buf));
-        try!(Serialize::write(&*self.title, buf));
-
-        try!(Serialize::write("sample_rate", buf));
-        try!(Serialize::write(&self.sample_rate, buf));
-
-        try!(Serialize::write("sample_count", buf));
-        try!(Serialize::write(&self.sample_count, buf));
-
-        try!(Serialize::write("sample_position", buf));
-        try!(Serialize::write(&self.sample_position, buf));
-
-        try!(Serialize::write("metadata", buf));
-        try!(Serialize::write(&self.metadata[..], buf));
-
-        Ok(())
-    }
-}
-
-const QUEUE_FIELD_COUNT: u32 = 2;
-
-#[derive(Debug)]
+#[derive(Debug, ProtoSerialize, ProtoDeserialize)]
 pub struct Queue {
+    // the track on air, unless it's the fallback track.  It's also the
+    // first of `upcoming`, as it always has been.
+    pub playing: Option<TrackInfo>,
     // We'll just include the currently playing song
     // in here.
     pub upcoming: Vec<TrackInfo>,
+    #[proto(default)]
     pub history: Vec<TrackInfo>,
 }
 
-impl Deserialize for Queue {
-    fn read(buf: &mut io::Cursor<Vec<u8>>) -> io::Result<Self> {
-        try!(proto::expect_type(buf, proto::TYPE_STRUCT));
-        let field_count = try!(buf.read_u32::<BigEndian>());
-
-        let mut upcoming: Option<Vec<TrackInfo>> = None;
-        let mut history: Option<Vec<TrackInfo>> = None;
-
-        for _ in 0..field_count {
-            let field_name: String = try!(Deserialize::read(buf));
-            match &field_name[..] {
-                "upcoming" => {
-                    upcoming = Some(try!(Deserialize::read(buf)));
-                },
-                "history" => {
-                    history = Some(try!(Deserialize::read(buf)));
-                }
-                _ => try!(proto::skip_entity(buf)),
-            }
-        }
-
-        let upcoming = match upcoming {
-            Some(upcoming) => upcoming,
-            None => return Err(io::Error::new(io::ErrorKind::Other, "missing field: upcoming")),
-        };
-        let history = history.unwrap_or_else(Vec::new);
-
-        Ok(Queue {
-            upcoming: upcoming,
-            history: history,
-        })
+// Tracks go over the wire as a blob of their ogg pages.
+impl Deserialize for OggTrackBuf {
+    fn read<R>(buf: &mut R) -> io::Result<Self> where R: Read {
+        let track: Vec<u8> = try!(Deserialize::read(buf));
+        OggTrackBuf::new(track)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "invalid ogg"))
     }
 }
 
-impl Serialize for Queue {
+impl Serialize for OggTrackBuf {
     fn write(&self, buf: &mut io::Cursor<Vec<u8>>) -> io::Result<()> {
-        try!(buf.write_u16::<BigEndian>(proto::TYPE_STRUCT));
-        try!(buf.write_u32::<BigEndian>(QUEUE_FIELD_COUNT));
-
-        try!(Serialize::write("upcoming", buf));
-        try!(Serialize::write(&self.upcoming[..], buf));
-
-        try!(Serialize::write("history", buf));
-        try!(Serialize::write(&self.history[..], buf));
-
-        Ok(())
+        Serialize::write(self.as_u8_slice(), buf)
     }
 }
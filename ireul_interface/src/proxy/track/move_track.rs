@@ -0,0 +1,47 @@
+use super::super::{RequestType, Request};
+use super::model::Handle;
+
+/// Moves a queued track to `position` in the queue, 0 being the next to
+/// play.  Positions past the end move it to the end.
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
+pub struct MoveTrackRequest {
+    pub handle: Handle,
+    pub position: u32,
+}
+
+impl Request for MoveTrackRequest {
+    type Value = ();
+    type Error = MoveTrackError;
+
+    fn req_type(&self) -> RequestType {
+        RequestType::MoveTrack
+    }
+}
+
+pub type MoveTrackResult = Result<(), MoveTrackError>;
+
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
+pub enum MoveTrackError {
+    NotFound = 1,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::MoveTrackRequest;
+    use super::super::model::Handle;
+    use ::proto::{self, Deserialize};
+
+    #[test]
+    fn test_roundtrip() {
+        let buffer = proto::serialize(&MoveTrackRequest {
+            handle: Handle(0xABCD),
+            position: 2,
+        }).unwrap();
+        let mut cursor = io::Cursor::new(buffer);
+        let req: MoveTrackRequest = Deserialize::read(&mut cursor).unwrap();
+        assert_eq!(req.handle, Handle(0xABCD));
+        assert_eq!(req.position, 2);
+    }
+}
@@ -0,0 +1,25 @@
+use super::super::{RequestType, Request};
+use super::model::Handle;
+
+/// Takes a track off the queue before it plays.  The track on air isn't
+/// in the queue; use `FastForward::TrackBoundary` for that.
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
+pub struct RemoveTrackRequest {
+    pub handle: Handle,
+}
+
+impl Request for RemoveTrackRequest {
+    type Value = ();
+    type Error = RemoveTrackError;
+
+    fn req_type(&self) -> RequestType {
+        RequestType::RemoveTrack
+    }
+}
+
+pub type RemoveTrackResult = Result<(), RemoveTrackError>;
+
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
+pub enum RemoveTrackError {
+    NotFound = 1,
+}
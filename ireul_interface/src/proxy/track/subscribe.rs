@@ -0,0 +1,117 @@
+use super::super::{RequestType, Request};
+use super::model::TrackInfo;
+
+// Written as a struct with a `kind` discriminant, and a `track` field for
+// the events which are about a track.
+/// Something which happened on the station, as pushed to subscribers.
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
+pub enum Event {
+    /// A track from the queue went on air
+    #[proto(kind = 1)]
+    TrackStarted(#[proto(rename = "track")] TrackInfo),
+
+    /// A track from the queue finished, was skipped or was interrupted
+    TrackEnded(#[proto(rename = "track")] TrackInfo),
+
+    /// Tracks were added to or taken off the queue
+    QueueChanged,
+
+    /// The queue ran dry, so the fallback track is playing
+    FallbackEngaged,
+
+    /// Writing to the output failed; nobody is hearing anything
+    OutputDisconnected,
+
+    /// The output is back
+    OutputReconnected,
+}
+
+impl Event {
+    /// A name for the kind of event, such as "track_started".
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Event::TrackStarted(_) => "track_started",
+            Event::TrackEnded(_) => "track_ended",
+            Event::QueueChanged => "queue_changed",
+            Event::FallbackEngaged => "fallback_engaged",
+            Event::OutputDisconnected => "output_disconnected",
+            Event::OutputReconnected => "output_reconnected",
+        }
+    }
+}
+
+/// Turns the connection into a stream of `Event`s.  Once the request has
+/// been answered with an Ok, every further frame from the server is an
+/// Event, until the connection is closed.
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
+pub struct SubscribeRequest;
+
+impl Request for SubscribeRequest {
+    type Value = ();
+    type Error = SubscribeError;
+
+    fn req_type(&self) -> RequestType {
+        RequestType::Subscribe
+    }
+}
+
+pub type SubscribeResult = Result<(), SubscribeError>;
+
+#[derive(Debug, Clone, ProtoSerialize, ProtoDeserialize)]
+pub enum SubscribeError {
+    TooManySubscribers = 1,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::Event;
+    use super::super::model::{Handle, TrackInfo};
+    use ::proto::{self, Deserialize};
+
+    fn roundtrip(item: &Event) -> Event {
+        let buffer = proto::serialize(item).unwrap();
+        let mut cursor = io::Cursor::new(buffer);
+        Deserialize::read(&mut cursor).unwrap()
+    }
+
+    #[test]
+    fn test_track_event() {
+        let tinfo = TrackInfo {
+            handle: Handle(7),
+            started_at: Some(1450000000),
+            artist: "artist".to_string(),
+            album: "album".to_string(),
+            title: "title".to_string(),
+            sample_rate: 48000,
+            sample_count: 480000,
+            sample_position: 0,
+            metadata: Vec::new(),
+        };
+        match roundtrip(&Event::TrackStarted(tinfo.clone())) {
+            Event::TrackStarted(ref track) if track.handle == Handle(7) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match roundtrip(&Event::TrackEnded(tinfo)) {
+            Event::TrackEnded(ref track) if track.title == "title" => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_bare_event() {
+        let buffer = proto::serialize(&Event::QueueChanged).unwrap();
+        assert_eq!(&buffer[..], &[
+            // struct type, 1 field
+            0x00, 0x05, 0x00, 0x00, 0x00, 0x01,
+            // "kind": 3_u32
+            0x00, 0x84, 0x00, 0x00, 0x00, 0x04, b'k', b'i', b'n', b'd',
+            0x00, 0x82, 0x00, 0x00, 0x00, 0x03,
+        ]);
+        match roundtrip(&Event::OutputReconnected) {
+            Event::OutputReconnected => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
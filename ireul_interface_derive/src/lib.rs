@@ -0,0 +1,577 @@
+//! `#[derive(ProtoSerialize, ProtoDeserialize)]` for the messages in
+//! `ireul_interface`, writing the same encodings we used to write by hand.
+//! The generated code refers to `::proto`, so these only work within
+//! `ireul_interface`.
+//!
+//! * A struct with named fields is a `TYPE_STRUCT` of its fields by name.
+//!   `Option` fields are left out when they're `None`, and are `None`
+//!   when they're missing.  Other missing fields are an error, unless
+//!   marked `#[proto(default)]`.  Unknown fields are skipped.
+//! * A unit struct is an empty struct.
+//! * A struct with one unnamed field is written as that field.
+//! * An enum of unit variants is a u32: the variant's discriminant.
+//! * Any other enum is a struct with the variant's discriminant in a
+//!   `kind` field alongside the variant's fields.  A variant's `kind` is
+//!   set with `#[proto(kind = N)]`, and otherwise follows on from the
+//!   previous one, starting at 0.  A unit variant marked `#[proto(bare)]`
+//!   is written as a bare u32 instead; either form is read.
+//! * An enum marked `#[proto(tagged)]` is a `TYPE_ENUM` of the variant's
+//!   discriminant and its payload: void for a unit variant, the field of a
+//!   variant with one unnamed field, and otherwise a struct of its fields.
+//!
+//! `#[proto(rename = "name")]` gives a field another name on the wire,
+//! and names unnamed fields, which are otherwise named by their index.
+
+extern crate proc_macro;
+extern crate syn;
+#[macro_use]
+extern crate quote;
+
+use proc_macro::TokenStream;
+use quote::Tokens;
+use syn::{
+    Attribute,
+    Body,
+    ConstExpr,
+    DeriveInput,
+    Field,
+    Ident,
+    Lit,
+    MetaItem,
+    NestedMetaItem,
+    Ty,
+    Variant,
+    VariantData,
+};
+
+#[proc_macro_derive(ProtoSerialize, attributes(proto))]
+pub fn derive_serialize(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_derive_input(&input.to_string()).unwrap();
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let body = match ast.body {
+        Body::Struct(ref data) => serialize_struct(&ast, data),
+        Body::Enum(ref variants) => serialize_enum(&ast, variants),
+    };
+
+    let expanded = quote! {
+        impl #impl_generics ::proto::Serialize for #name #ty_generics #where_clause {
+            fn write(&self, buf: &mut ::std::io::Cursor<Vec<u8>>) -> ::std::io::Result<()> {
+                #body
+                Ok(())
+            }
+        }
+    };
+    expanded.parse().unwrap()
+}
+
+#[proc_macro_derive(ProtoDeserialize, attributes(proto))]
+pub fn derive_deserialize(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_derive_input(&input.to_string()).unwrap();
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let body = match ast.body {
+        Body::Struct(ref data) => deserialize_struct(&ast, data),
+        Body::Enum(ref variants) => deserialize_enum(&ast, variants),
+    };
+
+    let expanded = quote! {
+        impl #impl_generics ::proto::Deserialize for #name #ty_generics #where_clause {
+            fn read<__R>(buf: &mut __R) -> ::std::io::Result<Self> where __R: ::std::io::Read {
+                #body
+            }
+        }
+    };
+    expanded.parse().unwrap()
+}
+
+/// A field as it goes over the wire.
+struct WireField<'a> {
+    field: &'a Field,
+    /// the name on the wire
+    name: String,
+    /// what the field is bound to while reading or writing
+    var: Ident,
+    optional: bool,
+    default: bool,
+}
+
+fn wire_fields<'a>(data: &'a VariantData) -> Vec<WireField<'a>> {
+    data.fields().iter().enumerate().map(|(index, field)| {
+        let mut rename = None;
+        let mut default = false;
+        for item in proto_items(&field.attrs) {
+            match *item {
+                MetaItem::NameValue(ref key, Lit::Str(ref value, _)) if key == "rename" => {
+                    rename = Some(value.clone());
+                },
+                MetaItem::Word(ref key) if key == "default" => default = true,
+                ref other => panic!("unknown proto attribute on a field: {:?}", other),
+            }
+        }
+
+        let name = match (rename, field.ident.as_ref()) {
+            (Some(rename), _) => rename,
+            (None, Some(ident)) => ident.to_string(),
+            (None, None) => index.to_string(),
+        };
+        WireField {
+            field: field,
+            name: name,
+            var: Ident::new(format!("__field{}", index)),
+            optional: is_option(&field.ty),
+            default: default,
+        }
+    }).collect()
+}
+
+// The contents of every #[proto(...)] attribute.
+fn proto_items(attrs: &[Attribute]) -> Vec<&MetaItem> {
+    let mut items = Vec::new();
+    for attr in attrs {
+        if let MetaItem::List(ref name, ref nested) = attr.value {
+            if name == "proto" {
+                for item in nested {
+                    match *item {
+                        NestedMetaItem::MetaItem(ref item) => items.push(item),
+                        NestedMetaItem::Literal(ref lit) => {
+                            panic!("unexpected literal in proto attribute: {:?}", lit);
+                        },
+                    }
+                }
+            }
+        }
+    }
+    items
+}
+
+fn is_option(ty: &Ty) -> bool {
+    match *ty {
+        Ty::Path(None, ref path) => {
+            path.segments.last().map(|seg| seg.ident == "Option").unwrap_or(false)
+        },
+        _ => false,
+    }
+}
+
+/// Writes a TYPE_STRUCT of `fields`, each bound to a reference named by
+/// its `var`, after a `kind` field if there is one.
+fn write_struct(fields: &[WireField], kind: Option<u32>) -> Tokens {
+    let required = fields.iter().filter(|f| !f.optional).count() as u32
+        + if kind.is_some() { 1 } else { 0 };
+
+    let mut body = Tokens::new();
+    for field in fields.iter().filter(|f| f.optional) {
+        let var = &field.var;
+        body.append_all(&[quote! {
+            if #var.is_some() {
+                field_count += 1;
+            }
+        }]);
+    }
+    body.append_all(&[quote! {
+        try!(::proto::write_struct_header(buf, field_count));
+    }]);
+    if let Some(kind) = kind {
+        body.append_all(&[quote! {
+            try!(::proto::Serialize::write("kind", buf));
+            try!(::proto::Serialize::write(&#kind, buf));
+        }]);
+    }
+    for field in fields {
+        let var = &field.var;
+        let name = &field.name;
+        if field.optional {
+            body.append_all(&[quote! {
+                if let Some(ref value) = *#var {
+                    try!(::proto::Serialize::write(#name, buf));
+                    try!(::proto::Serialize::write(value, buf));
+                }
+            }]);
+        } else {
+            body.append_all(&[quote! {
+                try!(::proto::Serialize::write(#name, buf));
+                try!(::proto::Serialize::write(#var, buf));
+            }]);
+        }
+    }
+
+    if fields.iter().any(|f| f.optional) {
+        quote! {
+            let mut field_count: u32 = #required;
+            #body
+        }
+    } else {
+        quote! {
+            let field_count: u32 = #required;
+            #body
+        }
+    }
+}
+
+/// Reads the fields of a TYPE_STRUCT, whose `field_count` has been read,
+/// into a `var` per field, each an Option, and into `kind` if
+/// `with_kind`.  `fields` may repeat names, which then share a var.
+fn read_struct(fields: &[WireField], with_kind: bool) -> Tokens {
+    let mut decls = Tokens::new();
+    let mut arms = Tokens::new();
+    let mut seen: Vec<&str> = Vec::new();
+    if with_kind {
+        decls.append_all(&[quote! { let mut kind: Option<u32> = None; }]);
+        arms.append_all(&[quote! {
+            "kind" => {
+                kind = Some(try!(::proto::Deserialize::read(buf)));
+            },
+        }]);
+    }
+    for field in fields {
+        if seen.contains(&&field.name[..]) {
+            continue;
+        }
+        seen.push(&field.name);
+
+        let var = &field.var;
+        let name = &field.name;
+        let ty = &field.field.ty;
+        let decl = if field.optional {
+            quote! { let mut #var: #ty = None; }
+        } else {
+            quote! { let mut #var: Option<#ty> = None; }
+        };
+        decls.append_all(&[decl]);
+        arms.append_all(&[quote! {
+            #name => {
+                #var = Some(try!(::proto::Deserialize::read(buf)));
+            },
+        }]);
+    }
+
+    quote! {
+        #decls
+        for _ in 0..field_count {
+            let field_name: String = try!(::proto::Deserialize::read(buf));
+            match &field_name[..] {
+                #arms
+                _ => try!(::proto::skip_entity(buf)),
+            }
+        }
+    }
+}
+
+/// Builds `path` from the vars `read_struct` filled in.
+fn construct(path: &Tokens, data: &VariantData, fields: &[WireField]) -> Tokens {
+    let values: Vec<Tokens> = fields.iter().map(|field| {
+        let name = &field.name;
+        // fields sharing a name share the var of the first of them
+        let var = &fields.iter().find(|f| f.name == field.name).unwrap().var;
+        if field.optional {
+            quote! { #var }
+        } else if field.default {
+            quote! { #var.unwrap_or_else(Default::default) }
+        } else {
+            quote! {
+                match #var {
+                    Some(value) => value,
+                    None => return Err(::proto::missing_field(#name)),
+                }
+            }
+        }
+    }).collect();
+
+    match *data {
+        VariantData::Struct(_) => {
+            let idents: Vec<&Ident> = fields.iter()
+                .map(|f| f.field.ident.as_ref().unwrap())
+                .collect();
+            quote! { #path { #(#idents: #values),* } }
+        },
+        VariantData::Tuple(_) => quote! { #path(#(#values),*) },
+        VariantData::Unit => quote! { #path },
+    }
+}
+
+/// The pattern binding each of `fields` by reference to its var.
+fn pattern(path: &Tokens, data: &VariantData, fields: &[WireField]) -> Tokens {
+    let vars: Vec<&Ident> = fields.iter().map(|f| &f.var).collect();
+    match *data {
+        VariantData::Struct(_) => {
+            let idents: Vec<&Ident> = fields.iter()
+                .map(|f| f.field.ident.as_ref().unwrap())
+                .collect();
+            quote! { #path { #(#idents: ref #vars),* } }
+        },
+        VariantData::Tuple(_) => quote! { #path(#(ref #vars),*) },
+        VariantData::Unit => quote! { #path },
+    }
+}
+
+fn is_newtype(data: &VariantData) -> bool {
+    match *data {
+        VariantData::Tuple(ref fields) => fields.len() == 1,
+        _ => false,
+    }
+}
+
+fn serialize_struct(ast: &DeriveInput, data: &VariantData) -> Tokens {
+    let name = &ast.ident;
+    let path = quote! { #name };
+    if is_newtype(data) {
+        return quote! {
+            try!(::proto::Serialize::write(&self.0, buf));
+        };
+    }
+
+    let fields = wire_fields(data);
+    let pattern = pattern(&path, data, &fields);
+    let write = write_struct(&fields, None);
+    quote! {
+        let #pattern = *self;
+        #write
+    }
+}
+
+fn deserialize_struct(ast: &DeriveInput, data: &VariantData) -> Tokens {
+    let name = &ast.ident;
+    let path = quote! { #name };
+    if is_newtype(data) {
+        return quote! {
+            Ok(#name(try!(::proto::Deserialize::read(buf))))
+        };
+    }
+
+    let fields = wire_fields(data);
+    let read = read_struct(&fields, false);
+    let value = construct(&path, data, &fields);
+    quote! {
+        let field_count = try!(::proto::read_struct_header(buf));
+        #read
+        Ok(#value)
+    }
+}
+
+/// Each variant with its discriminant.
+fn kinds(variants: &[Variant]) -> Vec<(&Variant, u32)> {
+    let mut next = 0;
+    variants.iter().map(|variant| {
+        let mut kind = match variant.discriminant {
+            Some(ConstExpr::Lit(Lit::Int(value, _))) => value as u32,
+            Some(ref other) => panic!("unsupported discriminant: {:?}", other),
+            None => next,
+        };
+        for item in proto_items(&variant.attrs) {
+            match *item {
+                MetaItem::NameValue(ref key, Lit::Int(value, _)) if key == "kind" => {
+                    kind = value as u32;
+                },
+                MetaItem::Word(ref key) if key == "bare" => (),
+                ref other => panic!("unknown proto attribute on a variant: {:?}", other),
+            }
+        }
+        next = kind + 1;
+        (variant, kind)
+    }).collect()
+}
+
+fn is_bare(variant: &Variant) -> bool {
+    proto_items(&variant.attrs).iter().any(|item| match **item {
+        MetaItem::Word(ref key) => key == "bare",
+        _ => false,
+    })
+}
+
+fn all_unit(variants: &[Variant]) -> bool {
+    variants.iter().all(|v| v.data == VariantData::Unit)
+}
+
+fn is_tagged(ast: &DeriveInput) -> bool {
+    proto_items(&ast.attrs).iter().any(|item| match **item {
+        MetaItem::Word(ref key) if key == "tagged" => true,
+        ref other => panic!("unknown proto attribute on a type: {:?}", other),
+    })
+}
+
+fn serialize_enum(ast: &DeriveInput, variants: &[Variant]) -> Tokens {
+    let name = &ast.ident;
+    if is_tagged(ast) {
+        return serialize_tagged(ast, variants);
+    }
+    let mut arms = Tokens::new();
+    for (variant, kind) in kinds(variants) {
+        let ident = &variant.ident;
+        let path = quote! { #name::#ident };
+        let fields = wire_fields(&variant.data);
+        let pattern = pattern(&path, &variant.data, &fields);
+        let write = if all_unit(variants) || is_bare(variant) {
+            quote! { try!(::proto::Serialize::write(&#kind, buf)); }
+        } else {
+            write_struct(&fields, Some(kind))
+        };
+        arms.append_all(&[quote! {
+            #pattern => {
+                #write
+            },
+        }]);
+    }
+
+    quote! {
+        match *self {
+            #arms
+        }
+    }
+}
+
+fn deserialize_enum(ast: &DeriveInput, variants: &[Variant]) -> Tokens {
+    let name = &ast.ident;
+    if is_tagged(ast) {
+        return deserialize_tagged(ast, variants);
+    }
+    let unexpected = format!("unexpected {} value", name);
+    let kinds = kinds(variants);
+
+    // the variants a bare u32 may stand for
+    let mut bare_arms = Tokens::new();
+    for &(variant, kind) in kinds.iter() {
+        if variant.data == VariantData::Unit {
+            let ident = &variant.ident;
+            bare_arms.append_all(&[quote! { #kind => Ok(#name::#ident), }]);
+        }
+    }
+    let match_bare = quote! {
+        match kind {
+            #bare_arms
+            _ => Err(::std::io::Error::new(::std::io::ErrorKind::Other, #unexpected)),
+        }
+    };
+    if all_unit(variants) {
+        return quote! {
+            let kind: u32 = try!(::proto::Deserialize::read(buf));
+            #match_bare
+        };
+    }
+
+    // every variant's fields, with vars numbered across the whole enum
+    let mut all_fields = Vec::new();
+    for &(variant, _) in kinds.iter() {
+        all_fields.extend(wire_fields(&variant.data));
+    }
+    for (index, field) in all_fields.iter_mut().enumerate() {
+        field.var = Ident::new(format!("__field{}", index));
+    }
+    let read = read_struct(&all_fields, true);
+
+    let mut arms = Tokens::new();
+    for &(variant, kind) in kinds.iter() {
+        let ident = &variant.ident;
+        let path = quote! { #name::#ident };
+        let fields = wire_fields(&variant.data);
+        // construct() must use the vars read_struct declared
+        let fields: Vec<WireField> = fields.into_iter().map(|mut field| {
+            field.var = all_fields.iter().find(|f| f.name == field.name).unwrap().var.clone();
+            field
+        }).collect();
+        let value = construct(&path, &variant.data, &fields);
+        arms.append_all(&[quote! { Some(#kind) => Ok(#value), }]);
+    }
+
+    let match_struct = quote! {
+        #read
+        match kind {
+            #arms
+            Some(_) => Err(::std::io::Error::new(::std::io::ErrorKind::Other, #unexpected)),
+            None => Err(::proto::missing_field("kind")),
+        }
+    };
+
+    if !kinds.iter().any(|&(variant, _)| is_bare(variant)) {
+        return quote! {
+            let field_count = try!(::proto::read_struct_header(buf));
+            #match_struct
+        };
+    }
+    // a u32 and a struct's field count are read alike
+    quote! {
+        let type_id = try!(::proto::read_type(buf));
+        let value = try!(::proto::read_u32_value(buf));
+        if type_id == ::proto::TYPE_U32 {
+            let kind = value;
+            #match_bare
+        } else if type_id == ::proto::TYPE_STRUCT {
+            let field_count = value;
+            #match_struct
+        } else {
+            Err(::std::io::Error::new(::std::io::ErrorKind::Other, "unexpected type"))
+        }
+    }
+}
+
+fn serialize_tagged(ast: &DeriveInput, variants: &[Variant]) -> Tokens {
+    let name = &ast.ident;
+    let mut arms = Tokens::new();
+    for (variant, kind) in kinds(variants) {
+        let ident = &variant.ident;
+        let path = quote! { #name::#ident };
+        let fields = wire_fields(&variant.data);
+        let pattern = pattern(&path, &variant.data, &fields);
+        let payload = if variant.data == VariantData::Unit {
+            quote! { try!(::proto::Serialize::write(&(), buf)); }
+        } else if is_newtype(&variant.data) {
+            let var = &fields[0].var;
+            quote! { try!(::proto::Serialize::write(#var, buf)); }
+        } else {
+            write_struct(&fields, None)
+        };
+        arms.append_all(&[quote! {
+            #pattern => {
+                try!(::proto::write_enum_header(buf, #kind));
+                #payload
+            },
+        }]);
+    }
+
+    quote! {
+        match *self {
+            #arms
+        }
+    }
+}
+
+fn deserialize_tagged(ast: &DeriveInput, variants: &[Variant]) -> Tokens {
+    let name = &ast.ident;
+    let unexpected = format!("unexpected {} value", name);
+    let mut arms = Tokens::new();
+    for (variant, kind) in kinds(variants) {
+        let ident = &variant.ident;
+        let path = quote! { #name::#ident };
+        let read = if variant.data == VariantData::Unit {
+            quote! {
+                try!(<() as ::proto::Deserialize>::read(buf));
+                Ok(#path)
+            }
+        } else if is_newtype(&variant.data) {
+            quote! { Ok(#path(try!(::proto::Deserialize::read(buf)))) }
+        } else {
+            let fields = wire_fields(&variant.data);
+            let read = read_struct(&fields, false);
+            let value = construct(&path, &variant.data, &fields);
+            quote! {
+                let field_count = try!(::proto::read_struct_header(buf));
+                #read
+                Ok(#value)
+            }
+        };
+        arms.append_all(&[quote! {
+            #kind => {
+                #read
+            },
+        }]);
+    }
+
+    quote! {
+        match try!(::proto::read_enum_header(buf)) {
+            #arms
+            _ => Err(::std::io::Error::new(::std::io::ErrorKind::Other, #unexpected)),
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::ops;
+use std::mem;
+
+use {OggPage, OggTrack, OggTrackBuf, OggPageCheckError, NO_POSITION};
+
+#[derive(Clone, Copy, Debug)]
+struct PageEntry {
+    offset: usize,
+    length: usize,
+
+    // The granule position of the page, or that of the closest preceding
+    // page which had one.  Monotonic for well-formed tracks, so we can
+    // binary search over it.
+    position: u64,
+}
+
+/// An ogg track which has had its pages validated once and their offsets
+/// recorded.  Page access is O(1) and iterating the pages does not
+/// re-validate their checksums.
+#[derive(Clone)]
+pub struct IndexedOggTrack {
+    track: OggTrackBuf,
+    pages: Vec<PageEntry>,
+}
+
+impl IndexedOggTrack {
+    /// Validates every page in `buf` exactly once and builds the index.
+    pub fn new(buf: Vec<u8>) -> Result<IndexedOggTrack, OggPageCheckError> {
+        let pages = try!(build_index(&buf, true));
+        Ok(IndexedOggTrack {
+            track: OggTrackBuf { inner: buf },
+            pages: pages,
+        })
+    }
+
+    /// Indexes an already-validated track without checking the pages again.
+    pub fn from_track(track: OggTrackBuf) -> IndexedOggTrack {
+        let pages = build_index(track.as_u8_slice(), false)
+            .ok().expect("OggTrackBuf invariant violated");
+        IndexedOggTrack {
+            track: track,
+            pages: pages,
+        }
+    }
+
+    pub fn into_inner(self) -> OggTrackBuf {
+        self.track
+    }
+
+    pub fn as_u8_slice(&self) -> &[u8] {
+        self.track.as_u8_slice()
+    }
+
+    /// The number of pages in the track
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// Gets the page at the given index
+    pub fn page(&self, idx: usize) -> Option<&OggPage> {
+        self.pages.get(idx).map(|entry| {
+            let data = self.track.as_u8_slice();
+            OggPage::from_u8_slice_unchecked(&data[entry.offset..][..entry.length])
+        })
+    }
+
+    /// The final granule position of the track, which is the number of
+    /// samples it contains.
+    pub fn final_position(&self) -> u64 {
+        self.pages.last().map(|entry| entry.position).unwrap_or(0)
+    }
+
+    /// Finds the index of the first page which completes the sample at
+    /// `position`.  Returns None if the track ends before `position`.
+    pub fn find_position(&self, position: u64) -> Option<usize> {
+        let idx = match self.pages.binary_search_by(|entry| entry.position.cmp(&position)) {
+            Ok(mut idx) => {
+                // several pages can share a position; we want the first.
+                while 0 < idx && self.pages[idx - 1].position == position {
+                    idx -= 1;
+                }
+                idx
+            },
+            Err(idx) => idx,
+        };
+
+        if idx < self.pages.len() {
+            Some(idx)
+        } else {
+            None
+        }
+    }
+
+    pub fn pages(&self) -> IndexedPageIter {
+        IndexedPageIter {
+            data: self.track.as_u8_slice(),
+            entries: self.pages.iter(),
+        }
+    }
+
+    /// Mutably iterate over the pages.  Callers must not change the
+    /// length of any page, which `OggPage`'s API doesn't allow anyway.
+    pub fn pages_mut(&mut self) -> IndexedPageIterMut {
+        IndexedPageIterMut {
+            data: self.track.as_mut().as_u8_slice_mut(),
+            entries: self.pages.iter(),
+        }
+    }
+}
+
+impl ops::Deref for IndexedOggTrack {
+    type Target = OggTrack;
+
+    fn deref(&self) -> &OggTrack {
+        &self.track
+    }
+}
+
+impl AsRef<OggTrack> for IndexedOggTrack {
+    fn as_ref(&self) -> &OggTrack {
+        &self.track
+    }
+}
+
+fn build_index(buf: &[u8], check: bool) -> Result<Vec<PageEntry>, OggPageCheckError> {
+    let mut pages = Vec::new();
+    let mut offset = 0;
+    let mut position = 0;
+
+    while offset < buf.len() {
+        let page = if check {
+            try!(OggPage::new(&buf[offset..]))
+        } else {
+            OggPage::from_u8_slice_unchecked(try!(OggPage::measure_whole(&buf[offset..])))
+        };
+
+        let length = page.as_u8_slice().len();
+        if page.position() != NO_POSITION {
+            position = page.position();
+        }
+        pages.push(PageEntry {
+            offset: offset,
+            length: length,
+            position: position,
+        });
+        offset += length;
+    }
+
+    Ok(pages)
+}
+
+pub struct IndexedPageIter<'a> {
+    data: &'a [u8],
+    entries: ::std::slice::Iter<'a, PageEntry>,
+}
+
+impl<'a> Iterator for IndexedPageIter<'a> {
+    type Item = &'a OggPage;
+
+    fn next(&mut self) -> Option<&'a OggPage> {
+        self.entries.next().map(|entry| {
+            OggPage::from_u8_slice_unchecked(&self.data[entry.offset..][..entry.length])
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+pub struct IndexedPageIterMut<'a> {
+    data: &'a mut [u8],
+    entries: ::std::slice::Iter<'a, PageEntry>,
+}
+
+impl<'a> Iterator for IndexedPageIterMut<'a> {
+    type Item = &'a mut OggPage;
+
+    fn next(&mut self) -> Option<&'a mut OggPage> {
+        let entry = match self.entries.next() {
+            Some(entry) => entry,
+            None => return None,
+        };
+
+        // pages are contiguous, so the next page is always at the front.
+        let data = mem::replace(&mut self.data, &mut []);
+        let (page_data, rest) = data.split_at_mut(entry.length);
+        self.data = rest;
+        Some(OggPage::from_u8_slice_unchecked_mut(page_data))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {OggTrack, OggBuilder, NO_POSITION};
+    use super::IndexedOggTrack;
+
+    static SAMPLE_OGG: &'static [u8] = include_bytes!("../testdata/Hydrate-Kenny_Beltrey.ogg");
+
+    #[test]
+    fn test_pages_match_track() {
+        let track = OggTrack::new(SAMPLE_OGG).unwrap();
+        let indexed = IndexedOggTrack::new(SAMPLE_OGG.to_vec()).unwrap();
+
+        assert_eq!(track.pages().count(), indexed.len());
+        for (idx, (expected, got)) in track.pages().zip(indexed.pages()).enumerate() {
+            assert_eq!(expected.as_u8_slice(), got.as_u8_slice());
+            assert_eq!(expected.as_u8_slice(), indexed.page(idx).unwrap().as_u8_slice());
+        }
+        assert!(indexed.page(indexed.len()).is_none());
+    }
+
+    #[test]
+    fn test_bad_crc() {
+        let mut buf = SAMPLE_OGG.to_vec();
+        let last = buf.len() - 1;
+        buf[last] ^= 0xFF;
+        assert!(IndexedOggTrack::new(buf).is_err());
+    }
+
+    fn build_track(positions: &[u64]) -> IndexedOggTrack {
+        let mut buf = Vec::new();
+        for (idx, &position) in positions.iter().enumerate() {
+            let mut builder = OggBuilder::new();
+            builder.add_packet(&[idx as u8; 16]);
+            let mut page = builder.build().unwrap();
+            {
+                let mut tx = page.as_mut().begin();
+                tx.set_position(position);
+                tx.set_sequence(idx as u32);
+            }
+            buf.extend(page.as_u8_slice());
+        }
+        IndexedOggTrack::new(buf).unwrap()
+    }
+
+    #[test]
+    fn test_find_position() {
+        let indexed = build_track(&[0, 0, 1024, 2048, NO_POSITION, 4096, 8192]);
+        assert_eq!(indexed.final_position(), 8192);
+
+        assert_eq!(indexed.find_position(0), Some(0));
+        assert_eq!(indexed.find_position(1), Some(2));
+        assert_eq!(indexed.find_position(1024), Some(2));
+        assert_eq!(indexed.find_position(1025), Some(3));
+        // the page without a position is treated as being at 2048
+        assert_eq!(indexed.find_position(2048), Some(3));
+        assert_eq!(indexed.find_position(2049), Some(5));
+        assert_eq!(indexed.find_position(8192), Some(6));
+        assert_eq!(indexed.find_position(8193), None);
+    }
+
+    #[test]
+    fn test_pages_mut() {
+        let mut indexed = IndexedOggTrack::new(SAMPLE_OGG.to_vec()).unwrap();
+        for page in indexed.pages_mut() {
+            page.set_serial(0x1234);
+        }
+
+        let reindexed = IndexedOggTrack::new(indexed.as_u8_slice().to_vec()).unwrap();
+        assert_eq!(reindexed.len(), indexed.len());
+        for page in reindexed.pages() {
+            assert_eq!(page.serial(), 0x1234);
+        }
+    }
+}
@@ -15,15 +15,21 @@ mod reader;
 mod slice;
 pub mod vorbis;
 mod crc;
+mod index;
 
 use slice::Slice;
 
+pub use index::{IndexedOggTrack, IndexedPageIter, IndexedPageIterMut};
+
 const OGG_PAGE_CAPTURE: &'static [u8] = b"OggS";
 const POSITION_OFFSET: usize = 6;
 const SERIAL_OFFSET: usize = 14;
 const SEQUENCE_OFFSET: usize = 18;
 const CHECKSUM_OFFSET: usize = 22;
 
+/// Granule position of a page on which no packet finishes.
+pub const NO_POSITION: u64 = 0xFFFFFFFFFFFFFFFF;
+
 #[derive(Debug)]
 pub enum OggPageCheckError {
     TooShort,
@@ -87,6 +93,16 @@ impl OggTrackBuf {
         Ok(OggTrackBuf { inner: buf })
     }
 
+    pub fn empty() -> OggTrackBuf {
+        OggTrackBuf { inner: Vec::new() }
+    }
+
+    /// Appends a page.  Pages are only ever valid, so the track stays
+    /// valid without checking anything again.
+    pub fn push_page(&mut self, page: &OggPage) {
+        self.inner.extend(page.as_u8_slice());
+    }
+
     pub fn into_inner(self) -> Vec<u8> {
         self.inner
     }
@@ -694,7 +710,7 @@ impl Recapture {
 
 #[cfg(test)]
 mod tests {
-    use super::{OggTrack, OggPageBuf, Recapture};
+    use super::{OggTrack, OggTrackBuf, OggPageBuf, Recapture};
 
     static SAMPLE_OGG: &'static [u8] = include_bytes!("../testdata/Hydrate-Kenny_Beltrey.ogg");
 
@@ -747,4 +763,14 @@ mod tests {
     fn test_ogg_page_buf() {
         let _ = OggPageBuf::empty();
     }
+
+    #[test]
+    fn test_push_page() {
+        let track = OggTrack::new(SAMPLE_OGG).unwrap();
+        let mut rebuilt = OggTrackBuf::empty();
+        for page in track.pages() {
+            rebuilt.push_page(page);
+        }
+        assert_eq!(rebuilt.as_u8_slice(), SAMPLE_OGG);
+    }
 }
@@ -40,7 +40,9 @@ impl AudioClock {
 pub struct OggClock {
     clock: AudioClock,
     last_pos: u64,
-    base_pos: u64,
+
+    // signed, since a rebase may move a stream's positions backwards.
+    base_pos: i64,
 }
 
 impl OggClock {
@@ -64,25 +66,29 @@ impl OggClock {
         self.clock.sample_rate()
     }
 
-    pub fn wait_duration(&mut self, page: &OggPage) -> Duration {
-        let new_pos = page.position();
-        if self.base_pos + new_pos < self.last_pos {
-            self.base_pos = self.last_pos;
+    /// Treat a page at `position` as directly following the last page we
+    /// were given, e.g. after seeking within a track.
+    pub fn rebase(&mut self, position: u64) {
+        self.base_pos = self.last_pos as i64 - position as i64;
+    }
+
+    fn advance(&mut self, new_pos: u64) -> u64 {
+        if ((self.base_pos + new_pos as i64) as u64) < self.last_pos {
+            self.base_pos = self.last_pos as i64;
         }
 
-        let abs_pos = self.base_pos + new_pos;
+        let abs_pos = (self.base_pos + new_pos as i64) as u64;
         self.last_pos = abs_pos;
+        abs_pos
+    }
+
+    pub fn wait_duration(&mut self, page: &OggPage) -> Duration {
+        let abs_pos = self.advance(page.position());
         self.clock.wait_delay(SteadyTime::now(), abs_pos)
     }
 
     pub fn wait(&mut self, page: &OggPage) -> Result<(), ()> {
-        let new_pos = page.position();
-        if self.base_pos + new_pos < self.last_pos {
-            self.base_pos = self.last_pos;
-        }
-
-        let abs_pos = self.base_pos + new_pos;
-        self.last_pos = abs_pos;
+        let abs_pos = self.advance(page.position());
         let sleep_dur = self.clock.wait_delay(SteadyTime::now(), abs_pos);
 
         if Duration::zero() < sleep_dur {
@@ -92,3 +98,36 @@ impl OggClock {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::OggClock;
+
+    #[test]
+    fn test_track_boundary() {
+        let mut clock = OggClock::new(48000);
+        assert_eq!(clock.advance(0), 0);
+        assert_eq!(clock.advance(48000), 48000);
+
+        // a new track starts over at zero
+        assert_eq!(clock.advance(0), 48000);
+        assert_eq!(clock.advance(1024), 49024);
+    }
+
+    #[test]
+    fn test_rebase() {
+        let mut clock = OggClock::new(48000);
+        assert_eq!(clock.advance(0), 0);
+        assert_eq!(clock.advance(48000), 48000);
+
+        // seek forward
+        clock.rebase(480000);
+        assert_eq!(clock.advance(480000), 48000);
+        assert_eq!(clock.advance(481024), 49024);
+
+        // seek backward
+        clock.rebase(1024);
+        assert_eq!(clock.advance(1024), 49024);
+        assert_eq!(clock.advance(2048), 50048);
+    }
+}
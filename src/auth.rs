@@ -0,0 +1,139 @@
+//! Who may do what to the station, over any of its protocols.
+
+use ireul_interface::proxy::{LoginRequest, RequestType};
+
+/// What a user is allowed to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Everything
+    Admin,
+
+    /// Runs the show: everything but replacing the fallback track
+    Dj,
+
+    /// May only add tracks and see the queue
+    Requester,
+}
+
+impl Role {
+    /// "admin", "dj" or "requester"
+    pub fn from_name(name: &str) -> Option<Role> {
+        match name {
+            "admin" => Some(Role::Admin),
+            "dj" => Some(Role::Dj),
+            "requester" => Some(Role::Requester),
+            _ => None,
+        }
+    }
+
+    pub fn allows(&self, req_type: RequestType) -> bool {
+        if before_login(req_type) {
+            return true;
+        }
+        match (*self, req_type) {
+            (Role::Admin, _) => true,
+            (Role::Dj, RequestType::ReplaceFallback) => false,
+            (Role::Dj, _) => true,
+            (Role::Requester, RequestType::EnqueueTrack) => true,
+            (Role::Requester, RequestType::QueueStatus) => true,
+            (Role::Requester, RequestType::Subscribe) => true,
+            (Role::Requester, _) => false,
+        }
+    }
+}
+
+/// Whether anyone may send `req_type`, logged in or not.
+pub fn before_login(req_type: RequestType) -> bool {
+    match req_type {
+        RequestType::Login | RequestType::Hello => true,
+        _ => false,
+    }
+}
+
+/// Someone who may log in to the control protocol, HTTP API or MPD.
+#[derive(Clone, Debug)]
+pub struct User {
+    name: String,
+    token: String,
+    role: Role,
+}
+
+impl User {
+    pub fn new(name: &str, token: &str, role: Role) -> User {
+        User {
+            name: name.to_string(),
+            token: token.to_string(),
+            role: role,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+}
+
+// takes as long for a wrong first byte as for a wrong last one
+fn tokens_match(expected: &str, given: &str) -> bool {
+    if expected.len() != given.len() {
+        return false;
+    }
+    expected.bytes().zip(given.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// The user `req` logs in as, if the token is right.
+pub fn authenticate<'a>(users: &'a [User], req: &LoginRequest) -> Option<&'a User> {
+    authenticate_as(users, &req.user, &req.token)
+}
+
+/// The user called `name`, if `token` is theirs.
+pub fn authenticate_as<'a>(users: &'a [User], name: &str, token: &str) -> Option<&'a User> {
+    users.iter().find(|user| user.name == name && tokens_match(&user.token, token))
+}
+
+/// The user whose token is `token`, for protocols with only a password.
+pub fn authenticate_token<'a>(users: &'a [User], token: &str) -> Option<&'a User> {
+    users.iter().find(|user| tokens_match(&user.token, token))
+}
+
+#[cfg(test)]
+mod tests {
+    use ireul_interface::proxy::{LoginRequest, RequestType};
+
+    use super::{authenticate, authenticate_token, Role, User};
+
+    #[test]
+    fn test_roles() {
+        assert!(Role::Admin.allows(RequestType::ReplaceFallback));
+        assert!(!Role::Dj.allows(RequestType::ReplaceFallback));
+        assert!(Role::Dj.allows(RequestType::Interrupt));
+        assert!(Role::Requester.allows(RequestType::EnqueueTrack));
+        assert!(!Role::Requester.allows(RequestType::FastForward));
+        assert!(Role::Requester.allows(RequestType::Login));
+        assert!(Role::Requester.allows(RequestType::Hello));
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let users = vec![
+            User::new("ann", "secret", Role::Dj),
+            User::new("bob", "hunter2", Role::Requester),
+        ];
+        let login = |user: &str, token: &str| {
+            authenticate(&users, &LoginRequest {
+                user: user.to_string(),
+                token: token.to_string(),
+            }).map(|user| user.name().to_string())
+        };
+        assert_eq!(login("bob", "hunter2"), Some("bob".to_string()));
+        assert_eq!(login("bob", "secret"), None);
+        assert_eq!(login("bob", "hunter"), None);
+        assert_eq!(login("carol", ""), None);
+
+        assert_eq!(authenticate_token(&users, "hunter2").map(User::name), Some("bob"));
+        assert!(authenticate_token(&users, "hunter").is_none());
+    }
+}
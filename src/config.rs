@@ -0,0 +1,342 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use toml;
+
+use icecastwriter::{IceCastWriterOptions, SourceMethod};
+use core::Backoff;
+use hooks::{self, Hook};
+use auth::{Role, User};
+
+#[derive(RustcDecodable, Debug)]
+struct MetadataConfig {
+    public: Option<bool>,
+    private: Option<bool>,
+    name: Option<String>,
+    description: Option<String>,
+    url: Option<String>,
+    genre: Option<String>,
+    // the audio parameters default to those of the fallback track
+    bitrate: Option<u32>,
+    samplerate: Option<u32>,
+    channels: Option<u8>,
+    quality: Option<String>,
+}
+
+#[derive(RustcDecodable, Debug)]
+struct IcecastConfig {
+    // "source" (the default) or "put"
+    method: Option<String>,
+    chunked: Option<bool>,
+    // PEM file of certificates to trust for https:// outputs
+    ca_file: Option<String>,
+    // skip certificate verification for https:// outputs
+    insecure: Option<bool>,
+}
+
+#[derive(RustcDecodable, Debug)]
+struct ReconnectConfig {
+    // seconds to wait after the first failed attempt, doubling each time
+    wait: Option<u64>,
+    max_wait: Option<u64>,
+    // seconds of the current track to replay after reconnecting
+    rewind: Option<u64>,
+}
+
+#[derive(RustcDecodable, Debug)]
+struct HookConfig {
+    // event names; every event if unset
+    events: Option<Vec<String>>,
+    // POST the event as JSON here
+    url: Option<String>,
+    // or run this, with the event in the environment
+    command: Option<String>,
+    args: Option<Vec<String>>,
+}
+
+#[derive(RustcDecodable, Debug)]
+struct UserConfig {
+    name: String,
+    token: String,
+    // "admin", "dj" or "requester"
+    role: String,
+}
+
+/// The contents of an ireul config file.  See example-config.toml.
+#[derive(RustcDecodable, Debug)]
+pub struct Config {
+    // one of `file:`, `pipe:`, `null:` or `icecast://`.  See output::open
+    output_url: Option<String>,
+    // deprecated alias for an http:// output_url
+    icecast_url: Option<String>,
+    icecast: Option<IcecastConfig>,
+    metadata: Option<MetadataConfig>,
+    fallback_track: Option<String>,
+    listen_addr: Option<String>,
+    // the control protocol on a Unix socket, with its permissions in octal
+    unix_listen_path: Option<String>,
+    unix_listen_mode: Option<String>,
+    http_listen_addr: Option<String>,
+    mpd_listen_addr: Option<String>,
+    // where MPD clients add tracks from
+    media_root: Option<String>,
+    reconnect: Option<ReconnectConfig>,
+    hooks: Option<Vec<HookConfig>>,
+    users: Option<Vec<UserConfig>>,
+}
+
+impl Config {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config, String> {
+        let mut reader = try!(File::open(path)
+            .map_err(|err| format!("failed to open config file: {}", err)));
+        let mut config_buf = String::new();
+        try!(reader.read_to_string(&mut config_buf)
+            .map_err(|err| format!("failed to read config: {}", err)));
+        Config::from_toml(&config_buf)
+    }
+
+    pub fn from_toml(config: &str) -> Result<Config, String> {
+        toml::decode_str(config).ok_or_else(|| "invalid config file".to_string())
+    }
+
+    /// Where to serve the control protocol over TCP.  Unless it's set,
+    /// that's [::]:3001 if there's no Unix socket, and nowhere if there
+    /// is.
+    pub fn listen_addr(&self) -> Option<&str> {
+        match (self.listen_addr.as_ref(), self.unix_listen_path.as_ref()) {
+            (Some(addr), _) => Some(addr),
+            (None, Some(_)) => None,
+            (None, None) => Some("[::]:3001"),
+        }
+    }
+
+    pub fn unix_listen_path(&self) -> Option<&str> {
+        self.unix_listen_path.as_ref().map(|path| &path[..])
+    }
+
+    /// The Unix socket's permissions, such as 0o660.
+    pub fn unix_listen_mode(&self) -> Result<Option<u32>, String> {
+        match self.unix_listen_mode {
+            Some(ref mode) => u32::from_str_radix(mode, 8)
+                .map(Some)
+                .map_err(|_| format!("unix_listen_mode should be octal, such as \"660\": {}", mode)),
+            None => Ok(None),
+        }
+    }
+
+    /// Where to serve the HTTP API, if anywhere.
+    pub fn http_listen_addr(&self) -> Option<&str> {
+        self.http_listen_addr.as_ref().map(|addr| &addr[..])
+    }
+
+    /// Where to serve the MPD protocol, if anywhere.
+    pub fn mpd_listen_addr(&self) -> Option<&str> {
+        self.mpd_listen_addr.as_ref().map(|addr| &addr[..])
+    }
+
+    pub fn media_root(&self) -> Option<&str> {
+        self.media_root.as_ref().map(|path| &path[..])
+    }
+
+    pub fn fallback_track(&self) -> Option<&str> {
+        self.fallback_track.as_ref().map(|path| &path[..])
+    }
+
+    pub fn output_url(&self) -> Result<&str, String> {
+        match (self.output_url.as_ref(), self.icecast_url.as_ref()) {
+            (Some(url), _) => Ok(url),
+            (None, Some(url)) => Ok(url),
+            (None, None) => Err("missing output_url".to_string()),
+        }
+    }
+
+    pub fn backoff(&self) -> Backoff {
+        let mut backoff = Backoff::default();
+        if let Some(ref reconnect) = self.reconnect {
+            if let Some(wait) = reconnect.wait {
+                backoff.wait = wait;
+            }
+            if let Some(max_wait) = reconnect.max_wait {
+                backoff.max_wait = max_wait;
+            }
+        }
+        backoff
+    }
+
+    pub fn rewind_seconds(&self) -> u64 {
+        self.reconnect.as_ref().and_then(|r| r.rewind).unwrap_or(0)
+    }
+
+    pub fn hooks(&self) -> Result<Vec<Hook>, String> {
+        let mut hooks = Vec::new();
+        for hook_config in self.hooks.iter().flat_map(|hooks| hooks.iter()) {
+            let mut hook = match (hook_config.url.as_ref(), hook_config.command.as_ref()) {
+                (Some(url), None) => try!(Hook::post(url)),
+                (None, Some(command)) => {
+                    let args = hook_config.args.as_ref().map(|args| &args[..]).unwrap_or(&[]);
+                    Hook::exec(command, args)
+                },
+                _ => return Err("each hook needs either a url or a command".to_string()),
+            };
+            if let Some(ref events) = hook_config.events {
+                if let Some(name) = events.iter().find(|name| !hooks::EVENT_NAMES.contains(&&name[..])) {
+                    return Err(format!("Unknown hook event: {}", name));
+                }
+                hook.set_events(events);
+            }
+            hooks.push(hook);
+        }
+        Ok(hooks)
+    }
+
+    /// Who may log in to the control protocol.  Nobody needs to if this is
+    /// empty.
+    pub fn users(&self) -> Result<Vec<User>, String> {
+        let mut users: Vec<User> = Vec::new();
+        for user_config in self.users.iter().flat_map(|users| users.iter()) {
+            let role = try!(Role::from_name(&user_config.role)
+                .ok_or_else(|| format!("Unknown role for user {}: {}", user_config.name, user_config.role)));
+            if users.iter().any(|user| user.name() == user_config.name) {
+                return Err(format!("User {} is configured twice", user_config.name));
+            }
+            if user_config.token.is_empty() {
+                return Err(format!("User {} needs a token", user_config.name));
+            }
+            users.push(User::new(&user_config.name, &user_config.token, role));
+        }
+        Ok(users)
+    }
+
+    pub fn icecast_writer_opts(&self) -> Result<IceCastWriterOptions, String> {
+        let mut opts = IceCastWriterOptions::default();
+        if let Some(ref icecast) = self.icecast {
+            match icecast.method.as_ref().map(|m| &m[..]) {
+                None | Some("source") => (),
+                Some("put") => {
+                    opts.set_method(SourceMethod::Put);
+                },
+                Some(other) => return Err(format!("Unknown icecast method: {}", other)),
+            }
+            if let Some(chunked) = icecast.chunked {
+                opts.set_chunked(chunked);
+            }
+            if let Some(ref ca_file) = icecast.ca_file {
+                opts.set_ca_file(ca_file);
+            }
+            if let Some(insecure) = icecast.insecure {
+                opts.set_tls_insecure(insecure);
+            }
+        }
+        if let Some(ref metadata) = self.metadata {
+            if let Some(public) = metadata.public {
+                opts.set_public(public);
+            }
+            if let Some(private) = metadata.private {
+                opts.set_private(private);
+            }
+            if let Some(ref name) = metadata.name {
+                opts.set_name(name);
+            }
+            if let Some(ref description) = metadata.description {
+                opts.set_description(description);
+            }
+            if let Some(ref url) = metadata.url {
+                opts.set_url(url);
+            }
+            if let Some(ref genre) = metadata.genre {
+                opts.set_genre(genre);
+            }
+            if let Some(bitrate) = metadata.bitrate {
+                opts.set_bitrate(bitrate);
+            }
+            if let Some(samplerate) = metadata.samplerate {
+                opts.set_samplerate(samplerate);
+            }
+            if let Some(channels) = metadata.channels {
+                opts.set_channels(channels);
+            }
+            if let Some(ref quality) = metadata.quality {
+                opts.set_quality(quality);
+            }
+        }
+
+        Ok(opts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use auth::Role;
+    use super::Config;
+
+    #[test]
+    fn test_hooks() {
+        let config = Config::from_toml(r#"
+            output_url = "null:"
+
+            [[hooks]]
+            events = ["track_started"]
+            url = "http://localhost:8080/now-playing"
+
+            [[hooks]]
+            command = "/bin/true"
+        "#).unwrap();
+        assert_eq!(config.hooks().unwrap().len(), 2);
+
+        let config = Config::from_toml(r#"
+            [[hooks]]
+            events = ["track_stopped"]
+            command = "/bin/true"
+        "#).unwrap();
+        assert!(config.hooks().is_err());
+
+        let config = Config::from_toml(r#"
+            [[hooks]]
+            events = ["track_started"]
+        "#).unwrap();
+        assert!(config.hooks().is_err());
+    }
+
+    #[test]
+    fn test_listeners() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config.listen_addr(), Some("[::]:3001"));
+
+        let config = Config::from_toml(r#"
+            unix_listen_path = "/run/ireul/control.sock"
+            unix_listen_mode = "660"
+        "#).unwrap();
+        assert_eq!(config.listen_addr(), None);
+        assert_eq!(config.unix_listen_mode(), Ok(Some(0o660)));
+
+        let config = Config::from_toml(r#"
+            listen_addr = "127.0.0.1:3001"
+            unix_listen_path = "/run/ireul/control.sock"
+            unix_listen_mode = "rw-rw----"
+        "#).unwrap();
+        assert_eq!(config.listen_addr(), Some("127.0.0.1:3001"));
+        assert!(config.unix_listen_mode().is_err());
+    }
+
+    #[test]
+    fn test_users() {
+        let config = Config::from_toml(r#"
+            [[users]]
+            name = "ann"
+            token = "secret"
+            role = "dj"
+        "#).unwrap();
+        let users = config.users().unwrap();
+        assert_eq!(users[0].name(), "ann");
+        assert_eq!(users[0].role(), Role::Dj);
+
+        let config = Config::from_toml(r#"
+            [[users]]
+            name = "ann"
+            token = "secret"
+            role = "boss"
+        "#).unwrap();
+        assert!(config.users().is_err());
+    }
+}
@@ -0,0 +1,902 @@
+use std::mem;
+use std::sync::mpsc;
+use std::collections::VecDeque;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use time::{self, SteadyTime};
+
+use ogg::{IndexedOggTrack, OggPageBuf, NO_POSITION};
+use ogg::vorbis::VorbisPacket;
+use ogg_clock::OggClock;
+
+use ireul_interface::proto;
+use ireul_interface::proxy::track::model::{self, Handle};
+use ireul_interface::proxy::{
+    RequestBody,
+    RequestType,
+    RequestWrapper,
+    EnqueueTrackError,
+    EnqueueTrackResult,
+    FastForward,
+    FastForwardRequest,
+    FastForwardResult,
+    FastForwardError,
+    ReplaceFallbackResult,
+    InterruptResult,
+    InterruptError,
+    StatusRequest,
+    StatusResult,
+    Event,
+    SubscribeRequest,
+    SubscribeError,
+    SubscribeResult,
+    RemoveTrackRequest,
+    RemoveTrackResult,
+    RemoveTrackError,
+    MoveTrackRequest,
+    MoveTrackResult,
+    MoveTrackError,
+    HelloRequest,
+    HelloResult,
+    ErrorCode,
+    ProtocolError,
+    Capabilities,
+    PROTOCOL_VERSION_TAGGED,
+    REQUEST_TYPES,
+};
+
+use queue::{self, PlayQueue, PlayQueueError};
+use icecastwriter::HandshakeError;
+use output::OutputSink;
+
+// in seconds
+pub const DEFAULT_RECONNECT_WAIT: u64 = 5;
+pub const DEFAULT_MAX_RECONNECT_WAIT: u64 = 120;
+
+const MAX_SUBSCRIBERS: usize = 64;
+
+// how often the core looks in on a reconnect while serving requests
+const RECONNECT_POLL_MILLIS: i64 = 100;
+
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    // in seconds
+    pub wait: u64,
+    pub max_wait: u64,
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff {
+            wait: DEFAULT_RECONNECT_WAIT,
+            max_wait: DEFAULT_MAX_RECONNECT_WAIT,
+        }
+    }
+}
+
+/// Plays out pages and serves requests from clients in between, until
+/// every `CoreProxy` has gone away.  Nothing else touches the Core, so
+/// nothing can hold up the playout clock.  Reconnects, which can take as
+/// long as the server likes, happen on a thread of their own, with the
+/// output handed back once they're done.
+pub fn run_core(mut core: Core, output: Box<OutputSink>, backoff: Backoff, requests: mpsc::Receiver<RequestWrapper>) {
+    let mut next_tick = SteadyTime::now();
+    let mut wait = backoff.wait;
+    let mut attempt: u64 = 1;
+
+    // None while a reconnect has it
+    let mut output = Some(output);
+    let mut reconnecting: Option<mpsc::Receiver<(Box<OutputSink>, io::Result<()>)>> = None;
+
+    loop {
+        let reconnected = match reconnecting {
+            Some(ref finished) => finished.try_recv(),
+            None => Err(mpsc::TryRecvError::Empty),
+        };
+        match reconnected {
+            Ok((sink, Ok(()))) => {
+                info!("reconnected to output after {} attempts", attempt);
+                core.output_reconnected();
+                output = Some(sink);
+                reconnecting = None;
+                next_tick = SteadyTime::now();
+            },
+            Ok((sink, Err(err))) => {
+                error!("error reconnecting [attempt={}]: {}", attempt, err);
+                let retryable = HandshakeError::from_io_error(&err)
+                    .map(HandshakeError::is_retryable)
+                    .unwrap_or(true);
+                if !retryable {
+                    // hammering the server won't fix the config
+                    wait = backoff.max_wait;
+                }
+                next_tick = SteadyTime::now() + time::Duration::seconds(wait as i64);
+
+                wait = ::std::cmp::min(wait + wait, backoff.max_wait);
+                attempt += 1;
+                output = Some(sink);
+                reconnecting = None;
+            },
+            Err(mpsc::TryRecvError::Empty) => (),
+            Err(mpsc::TryRecvError::Disconnected) => {
+                error!("the output was lost while reconnecting");
+                return;
+            },
+        }
+
+        let now = SteadyTime::now();
+        if now < next_tick {
+            let timeout = Duration::from_millis((next_tick - now).num_milliseconds() as u64);
+            match requests.recv_timeout(timeout) {
+                Ok(req) => core.handle_request(req),
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+            continue;
+        }
+
+        let mut sink = match output.take() {
+            Some(sink) => sink,
+            None => {
+                next_tick = now + time::Duration::milliseconds(RECONNECT_POLL_MILLIS);
+                continue;
+            },
+        };
+
+        if sink.is_healthy() {
+            match core.tick(&mut *sink) {
+                Ok(deadline) => next_tick = deadline,
+                Err(err) => {
+                    // try to reconnect straight away, then back off.
+                    error!("Disconnected from output: {:?}", err);
+                    core.output_disconnected();
+                    wait = backoff.wait;
+                    attempt = 1;
+                },
+            }
+            output = Some(sink);
+            continue;
+        }
+
+        let (finished_tx, finished) = mpsc::channel();
+        thread::spawn(move || {
+            let result = sink.reconnect();
+            let _ = finished_tx.send((sink, result));
+        });
+        reconnecting = Some(finished);
+        next_tick = now + time::Duration::milliseconds(RECONNECT_POLL_MILLIS);
+    }
+}
+
+// header pages are the only ones at position zero.
+fn header_page_count(track: &IndexedOggTrack) -> usize {
+    track.find_position(1).unwrap_or(track.len())
+}
+
+/// The page to continue `track` from to play `position`, skipping the
+/// headers.  None if the track ends before then.
+fn first_page_after_headers(track: &IndexedOggTrack, position: u64) -> Option<usize> {
+    let target = match track.find_position(position) {
+        Some(target) => ::std::cmp::max(target, header_page_count(track)),
+        None => return None,
+    };
+    if target < track.len() {
+        Some(target)
+    } else {
+        None
+    }
+}
+
+fn update_serial(serial: u32, track: &mut IndexedOggTrack) {
+    for page in track.pages_mut() {
+        page.set_serial(serial);
+    }
+}
+
+/// Holds references to streamable content.
+pub struct Core {
+    cur_serial: u32,
+    clock: OggClock,
+
+    playing_offline: bool,
+    buffer: VecDeque<OggPageBuf>,
+
+    prev_ogg_granule_pos: u64,
+    prev_ogg_serial: u32,
+    prev_ogg_sequence: u32,
+
+    play_queue: PlayQueue,
+    offline_track: queue::Track,
+    playing: Option<model::TrackInfo>,
+
+    // the track behind `buffer`, kept around for seeking.
+    playing_track: Option<IndexedOggTrack>,
+
+    // Set while the tail of an interrupted track is still in `buffer`: the
+    // position it stops at, after which the queue resumes it.
+    interrupted_at: Option<u64>,
+
+    // (serial, sequence) of the first page after a seek, at which point
+    // the clock needs to be rebased.
+    clock_rebase_at: Option<(u32, u32)>,
+
+    // in samples; how far to back up the current track after the output
+    // has been reconnected.
+    resume_rewind: u64,
+
+    history: Vec<model::TrackInfo>,
+
+    subscribers: Vec<mpsc::SyncSender<Vec<u8>>>,
+}
+
+impl Core {
+    /// `resume_rewind` is in samples.
+    pub fn new(offline_track: IndexedOggTrack, sample_rate: u32, queue_size: usize, resume_rewind: u64) -> Core {
+        Core {
+            cur_serial: 0,
+            clock: OggClock::new(sample_rate),
+            playing_offline: false,
+            buffer: VecDeque::new(),
+
+            prev_ogg_granule_pos: 0,
+            prev_ogg_serial: 0,
+            prev_ogg_sequence: 0,
+
+            play_queue: PlayQueue::new(queue_size),
+            offline_track: queue::Track::from_ogg_track(Handle(0), offline_track),
+            playing: None,
+            playing_track: None,
+            interrupted_at: None,
+            clock_rebase_at: None,
+            resume_rewind: resume_rewind,
+            history: Vec::new(),
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Serves one request from a `CoreProxy`.  A request which can't be
+    /// understood is answered with a `ProtocolError`.
+    fn handle_request(&mut self, req: RequestWrapper) {
+        let RequestWrapper { response_queue, req_type, body } = req;
+
+        let result = match body {
+            RequestBody::Track(track) => self.dispatch_track(req_type, track),
+            RequestBody::Raw(req_buf) => {
+                let mut cursor = io::Cursor::new(req_buf);
+                if req_type == RequestType::Subscribe {
+                    match proto::deserialize(&mut cursor) {
+                        Ok(req) => return self.subscribe(req, response_queue),
+                        Err(err) => Err(err),
+                    }
+                } else {
+                    self.dispatch(req_type, &mut cursor)
+                }
+            },
+        };
+
+        let response = match result {
+            Ok(response) => response,
+            Err(err) => {
+                info!("bad {:?} request: {:?}", req_type, err);
+                let message = format!("bad {:?} request: {}", req_type, err);
+                match proto::serialize(&ProtocolError::new(ErrorCode::MalformedRequest, message)) {
+                    Ok(response) => response,
+                    Err(_) => return,
+                }
+            }
+        };
+        // the client may have given up waiting, which is fine.
+        let _ = response_queue.try_send(response);
+    }
+
+    fn dispatch_track(&mut self, req_type: RequestType, track: IndexedOggTrack) -> io::Result<Vec<u8>> {
+        match req_type {
+            RequestType::EnqueueTrack => proto::serialize(&self.enqueue_track(track)),
+            RequestType::Interrupt => proto::serialize(&self.interrupt(track)),
+            RequestType::ReplaceFallback => proto::serialize(&self.replace_fallback(track)),
+            _ => Err(io::Error::new(io::ErrorKind::Other, "only track requests carry tracks")),
+        }
+    }
+
+    fn dispatch(&mut self, req_type: RequestType, cursor: &mut io::Cursor<Vec<u8>>) -> io::Result<Vec<u8>> {
+        match req_type {
+            RequestType::EnqueueTrack | RequestType::Interrupt | RequestType::ReplaceFallback => {
+                Err(io::Error::new(io::ErrorKind::Other, "tracks are prepared before they reach the core"))
+            },
+            RequestType::FastForward => {
+                let req = try!(proto::deserialize(cursor));
+                proto::serialize(&self.fast_forward(req))
+            },
+            RequestType::QueueStatus => {
+                let req = try!(proto::deserialize(cursor));
+                proto::serialize(&self.queue_status(req))
+            },
+            RequestType::RemoveTrack => {
+                let req = try!(proto::deserialize(cursor));
+                proto::serialize(&self.remove_track(req))
+            },
+            RequestType::MoveTrack => {
+                let req = try!(proto::deserialize(cursor));
+                proto::serialize(&self.move_track(req))
+            },
+            RequestType::Subscribe => {
+                Err(io::Error::new(io::ErrorKind::Other, "subscriptions aren't calls"))
+            },
+            RequestType::Hello => {
+                let req = try!(proto::deserialize(cursor));
+                proto::serialize(&self.hello(req))
+            },
+            RequestType::Login => {
+                Err(io::Error::new(io::ErrorKind::Other, "logins are for the server"))
+            },
+        }
+    }
+
+    fn fill_buffer(&mut self) {
+        if let Some(tinfo) = self.playing.take() {
+            self.publish(Event::TrackEnded(tinfo.clone()));
+            self.history.push(tinfo);
+            self.history_cleanup();
+        }
+
+        let track: queue::Track = match self.play_queue.pop_track() {
+            Some(track) => {
+                self.playing_offline = false;
+                let mut tinfo = track.get_track_info();
+                if tinfo.started_at.is_none() {
+                    tinfo.started_at = Some(time::get_time().sec);
+                }
+                self.publish(Event::QueueChanged);
+                self.publish(Event::TrackStarted(tinfo.clone()));
+                self.playing = Some(tinfo);
+                track
+            },
+            None => {
+                if !self.playing_offline {
+                    self.publish(Event::FallbackEngaged);
+                }
+                self.playing_offline = true;
+                self.playing = None;
+                self.offline_track.clone()
+            }
+        };
+
+        let resume_position = track.resume_position();
+        let mut track = track.into_inner();
+        match resume_position {
+            Some(position) => {
+                self.buffer_track_from(&track, position + 1, None);
+            },
+            None => {
+                update_serial(self.cur_serial, &mut track);
+                self.cur_serial = self.cur_serial.wrapping_add(1);
+
+                self.buffer.extend(track.pages().map(|x| x.to_owned()));
+            }
+        }
+        self.playing_track = Some(track);
+        self.interrupted_at = None;
+    }
+
+    fn get_next_page(&mut self) -> OggPageBuf {
+        // a resumed track may have had nothing left to play
+        while self.buffer.is_empty() {
+            self.fill_buffer();
+        }
+        self.buffer.pop_front().unwrap()
+    }
+
+    /// Buffers `track` as a new logical stream, headers first, continuing
+    /// from the page containing `position`.  The stream ends after the page
+    /// containing `until`, if given.
+    fn buffer_track_from(&mut self, track: &IndexedOggTrack, position: u64, until: Option<u64>) {
+        let header_pages = header_page_count(track);
+        let target = match first_page_after_headers(track, position) {
+            Some(target) => target,
+            None => return,
+        };
+        let end = until.and_then(|until| track.find_position(until))
+            .map(|idx| idx + 1)
+            .unwrap_or(track.len());
+
+        if end <= target {
+            return;
+        }
+
+        let serial = self.cur_serial;
+        self.cur_serial = self.cur_serial.wrapping_add(1);
+
+        let pages = (0..header_pages).chain(target..end);
+        for (sequence, page_idx) in pages.enumerate() {
+            let mut page = track.page(page_idx).unwrap().to_owned();
+            {
+                let mut tx = page.as_mut().begin();
+                tx.set_serial(serial);
+                tx.set_sequence(sequence as u32);
+                if page_idx + 1 == end {
+                    tx.set_eos(true);
+                }
+            }
+            self.buffer.push_back(page);
+        }
+
+        self.clock_rebase_at = Some((serial, header_pages as u32));
+    }
+
+    fn fast_forward_track_boundary(&mut self) -> FastForwardResult {
+        let old_buffer = mem::replace(&mut self.buffer, VecDeque::new());
+
+        let mut page_iter = old_buffer.into_iter();
+
+        while let Some(page) = page_iter.next() {
+            debug!("checking buffer for non-continued page...");
+            if page.as_ref().continued() {
+                debug!("checking buffer for non-continued page... continued; kept");
+                self.buffer.push_back(page);
+            } else {
+                debug!("checking buffer for non-continued page...found page-aligned packet!");
+                break;
+            }
+        }
+        while let Some(mut page) = page_iter.next() {
+            // debug!("checking page for EOS...");
+            if page.as_ref().eos() {
+                {
+                    let mut tx = page.as_mut().begin();
+                    tx.set_position(self.prev_ogg_granule_pos);
+                    tx.set_serial(self.prev_ogg_serial);
+                    tx.set_sequence(self.prev_ogg_sequence + 1);
+                }
+                debug!("checking page for EOS... found it!");
+                self.buffer.push_back(page);
+                break;
+            }
+        }
+
+        self.buffer.extend(page_iter);
+        Ok(())
+    }
+
+    // **
+    fn enqueue_track(&mut self, track: IndexedOggTrack) -> EnqueueTrackResult {
+        info!("a client sent {} samples in {} pages", track.final_position(), track.len());
+
+        let handle = self.play_queue.add_track(track)
+            .map_err(|err| match err {
+                PlayQueueError::Full => EnqueueTrackError::Full,
+            });
+
+        if handle.is_ok() {
+            self.publish(Event::QueueChanged);
+        }
+
+        if self.playing_offline {
+            self.fast_forward_track_boundary().unwrap();
+        }
+
+        handle
+    }
+
+    /// Ends the current logical stream and restarts the playing track as a
+    /// new one, headers first, from the page containing `position`.
+    fn seek(&mut self, position: u64) -> FastForwardResult {
+        if self.interrupted_at.is_some() {
+            // what's left of an interrupted track isn't playing as such
+            return Err(FastForwardError);
+        }
+        let track = try!(self.playing_track.take().ok_or(FastForwardError));
+        let result = self.seek_track(&track, position);
+        self.playing_track = Some(track);
+        result
+    }
+
+    fn seek_track(&mut self, track: &IndexedOggTrack, position: u64) -> FastForwardResult {
+        // nothing to play from there, so leave the stream alone
+        if first_page_after_headers(track, position).is_none() {
+            return Err(FastForwardError);
+        }
+
+        try!(self.fast_forward_track_boundary());
+        self.buffer_track_from(track, position, None);
+        Ok(())
+    }
+
+    fn fast_forward(&mut self, req: FastForwardRequest) -> FastForwardResult {
+        match req.kind {
+            FastForward::TrackBoundary => {
+                try!(self.fast_forward_track_boundary());
+                Ok(())
+            },
+            FastForward::Seek { position } => {
+                self.seek(position)
+            },
+            FastForward::SeekForward { samples } => {
+                let position = self.prev_ogg_granule_pos.saturating_add(samples);
+                self.seek(position)
+            },
+            FastForward::SeekBackward { samples } => {
+                let position = self.prev_ogg_granule_pos.saturating_sub(samples);
+                self.seek(position)
+            },
+        }
+    }
+
+    fn queue_status(&mut self, _req: StatusRequest) -> StatusResult {
+        let mut upcoming: Vec<model::TrackInfo> = Vec::new();
+        if let Some(ref playing) = self.playing {
+            upcoming.push(playing.clone());
+        }
+        upcoming.extend(self.play_queue.track_infos().into_iter());
+
+        Ok(model::Queue {
+            playing: self.playing.clone(),
+            upcoming: upcoming,
+            history: self.history.clone(),
+        })
+    }
+
+    fn replace_fallback(&mut self, track: IndexedOggTrack) -> ReplaceFallbackResult {
+        info!("a client sent {} samples in {} pages", track.final_position(), track.len());
+
+        self.offline_track = queue::Track::from_ogg_track(Handle(0), track);
+
+        Ok(())
+    }
+
+    fn interrupt(&mut self, track: IndexedOggTrack) -> InterruptResult {
+        info!("a client sent {} samples in {} pages", track.final_position(), track.len());
+
+        try!(self.fast_forward_track_boundary()
+            .map_err(|_| InterruptError::InvalidTrack));
+
+        // The pages left in the buffer finish off the current stream, so
+        // we resume after the last of those.
+        let position = self.buffer.iter()
+            .map(|page| page.position())
+            .filter(|&position| position != NO_POSITION)
+            .fold(self.prev_ogg_granule_pos, ::std::cmp::max);
+
+        // The fallback track isn't resumed, it'll just start over.  The
+        // interrupted track stays in `playing_track` until its tail has
+        // gone out, in case it needs restarting after a reconnect.
+        let mut resume = None;
+        if !self.playing_offline {
+            if let (Some(tinfo), Some(data)) = (self.playing.take(), self.playing_track.clone()) {
+                info!("interrupting {:?} at sample {}", tinfo.handle, position);
+                // it's off air until the queue gets back round to it
+                self.publish(Event::TrackEnded(tinfo.clone()));
+                let track = queue::Track::from_ogg_track(tinfo.handle, data)
+                    .resume_from(tinfo.started_at, position);
+                resume = Some(track);
+                self.interrupted_at = Some(position);
+            }
+        }
+
+        let handle = self.play_queue.add_interrupt(track, resume);
+        self.publish(Event::QueueChanged);
+        Ok(handle)
+    }
+
+    fn hello(&mut self, _req: HelloRequest) -> HelloResult {
+        Ok(Capabilities {
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol_version: PROTOCOL_VERSION_TAGGED as u32,
+            op_codes: REQUEST_TYPES.iter().map(RequestType::to_op_code).collect(),
+            codecs: vec!["vorbis".to_string()],
+            sample_rate: self.clock.sample_rate(),
+            message_size_limit: proto::MESSAGE_SIZE_LIMIT as u64,
+            queue_capacity: self.play_queue.capacity() as u32,
+        })
+    }
+
+    fn remove_track(&mut self, req: RemoveTrackRequest) -> RemoveTrackResult {
+        try!(self.play_queue.remove_by_handle(req.handle)
+            .map_err(|()| RemoveTrackError::NotFound));
+        self.publish(Event::QueueChanged);
+        Ok(())
+    }
+
+    fn move_track(&mut self, req: MoveTrackRequest) -> MoveTrackResult {
+        let mut order = self.play_queue.handles();
+        let index = try!(order.iter().position(|&handle| handle == req.handle)
+            .ok_or(MoveTrackError::NotFound));
+        order.remove(index);
+
+        let position = ::std::cmp::min(req.position as usize, order.len());
+        order.insert(position, req.handle);
+        self.play_queue.reorder(&order);
+
+        self.publish(Event::QueueChanged);
+        Ok(())
+    }
+
+    fn subscribe(&mut self, _req: SubscribeRequest, response_queue: mpsc::SyncSender<Vec<u8>>) {
+        let result: SubscribeResult = if self.subscribers.len() < MAX_SUBSCRIBERS {
+            Ok(())
+        } else {
+            Err(SubscribeError::TooManySubscribers)
+        };
+
+        let response = proto::serialize(&result).unwrap();
+        if response_queue.try_send(response).is_ok() && result.is_ok() {
+            self.subscribers.push(response_queue);
+        }
+    }
+
+    /// Hands `event` to every subscriber.  Subscribers which have gone
+    /// away or aren't keeping up are dropped, as playout can't wait on
+    /// them.
+    fn publish(&mut self, event: Event) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+
+        let event_buf = match proto::serialize(&event) {
+            Ok(event_buf) => event_buf,
+            Err(err) => {
+                error!("error serializing {:?}: {:?}", event, err);
+                return;
+            }
+        };
+
+        let old_subscribers = mem::replace(&mut self.subscribers, Vec::new());
+        for subscriber in old_subscribers.into_iter() {
+            match subscriber.try_send(event_buf.clone()) {
+                Ok(()) => self.subscribers.push(subscriber),
+                Err(mpsc::TrySendError::Full(_)) => {
+                    info!("dropping a subscriber which fell behind");
+                },
+                Err(mpsc::TrySendError::Disconnected(_)) => (),
+            }
+        }
+    }
+
+    /// Restarts the logical stream which was cut off by a failed write to
+    /// the output, since whoever is listening on the other end of a new
+    /// connection needs its headers again.
+    fn buffer_resume(&mut self, failed: OggPageBuf) {
+        if failed.serial() != self.prev_ogg_serial {
+            // nothing of this stream made it out yet, so it can start over
+            // as it is.
+            self.buffer.push_front(failed);
+            return;
+        }
+        if failed.eos() {
+            // the stream was over anyway
+            return;
+        }
+
+        let track = match self.playing_track.take() {
+            Some(track) => track,
+            None => return,
+        };
+        let position = self.prev_ogg_granule_pos.saturating_sub(self.resume_rewind);
+        info!("resuming the current track at sample {} after reconnecting", position);
+
+        // an interrupted track only plays up to where the queue picks it
+        // up again.
+        let until = self.interrupted_at;
+        self.buffer.clear();
+        self.buffer_track_from(&track, position + 1, until);
+        self.playing_track = Some(track);
+    }
+
+    /// `tick` failed; nothing goes out until `output_reconnected`.
+    fn output_disconnected(&mut self) {
+        self.publish(Event::OutputDisconnected);
+    }
+
+    /// The output is back after `tick` failed.
+    fn output_reconnected(&mut self) {
+        // time has passed without us sending anything, so don't try to
+        // catch up.
+        self.clock = OggClock::new(self.clock.sample_rate());
+        self.publish(Event::OutputReconnected);
+    }
+
+    // copy a page and tells us up to when we have no work to do
+    fn tick(&mut self, output: &mut OutputSink) -> io::Result<SteadyTime> {
+        let page = self.get_next_page();
+
+        if let Err(err) = output.send_ogg_page(&page) {
+            self.buffer_resume(page);
+            return Err(err);
+        }
+
+        if page.position() != NO_POSITION {
+            self.prev_ogg_granule_pos = page.position();
+        }
+        self.prev_ogg_serial = page.serial();
+        self.prev_ogg_sequence = page.sequence();
+
+        if let Some(playing) = self.playing.as_mut() {
+            playing.sample_position = self.prev_ogg_granule_pos;
+        }
+
+        debug!("copied page :: granule_pos = {:?}; serial = {:?}; sequence = {:?}; bos = {:?}; eos = {:?}",
+            page.position(),
+            page.serial(),
+            page.sequence(),
+            page.bos(),
+            page.eos());
+
+        let vhdr = page.raw_packets().nth(0)
+            .and_then(|packet| VorbisPacket::new(packet).ok())
+            .and_then(|vhdr| vhdr.identification_header());
+
+        if let Some(vhdr) = vhdr {
+            debug!("            :: {:?}", vhdr);
+        }
+
+        if self.clock_rebase_at == Some((page.serial(), page.sequence())) {
+            self.clock.rebase(page.position());
+            self.clock_rebase_at = None;
+        }
+
+        Ok(SteadyTime::now() + self.clock.wait_duration(&page))
+    }
+
+    fn history_cleanup(&mut self) {
+        let old_hist = std::mem::replace(&mut self.history, Vec::new());
+        let mut old_hist: VecDeque<_> = old_hist.into_iter().collect();
+        while 20 < old_hist.len() {
+            let old_item = old_hist.pop_front().unwrap();
+            if let Err(()) = self.play_queue.dispose(&old_item) {
+                error!("error disposing history: {:?}", old_item);
+            }
+            debug!("discard history: {:?}", old_item);
+        }
+        self.history.extend(old_hist.into_iter())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use ogg::{IndexedOggTrack, OggPage, OggTrackBuf, NO_POSITION};
+
+    use ireul_interface::oggutil;
+    use ireul_interface::proto;
+    use ireul_interface::proxy::{CoreProxy, Event, FastForward, FastForwardRequest, StatusRequest};
+
+    use output::{NullSink, OutputSink};
+    use super::{run_core, Backoff, Core};
+
+    const DEAD_AIR: &'static [u8] = include_bytes!("deadair.ogg");
+
+    fn dead_air() -> OggTrackBuf {
+        OggTrackBuf::new(DEAD_AIR.to_vec()).unwrap()
+    }
+
+    fn prepared() -> IndexedOggTrack {
+        oggutil::prepare_track(dead_air(), None, 48000).unwrap()
+    }
+
+    // `resume_rewind` is in samples.
+    fn playing_core(resume_rewind: u64) -> Core {
+        let offline = IndexedOggTrack::new(DEAD_AIR.to_vec()).unwrap();
+        let mut core = Core::new(offline, 48000, 8, resume_rewind);
+        core.enqueue_track(prepared()).ok().unwrap();
+        // past the headers of the enqueued track
+        while core.playing.is_none() || core.prev_ogg_granule_pos == 0 {
+            core.tick(&mut NullSink).unwrap();
+        }
+        core
+    }
+
+    #[test]
+    fn test_resume_interrupted() {
+        let mut core = playing_core(48000 * 60);
+        core.interrupt(prepared()).ok().unwrap();
+        let until = core.interrupted_at.unwrap();
+
+        // the output fails partway through what's left of the interrupted
+        // track, which has to be restarted with its headers.
+        let track = IndexedOggTrack::from_track(dead_air());
+        let mut failed = track.page(track.len() - 2).unwrap().to_owned();
+        failed.as_mut().begin().set_serial(core.prev_ogg_serial);
+        core.buffer_resume(failed);
+
+        assert!(core.buffer.front().unwrap().bos());
+        assert!(core.buffer.back().unwrap().eos());
+        // and stops where the queue resumes it
+        assert!(core.buffer.iter()
+            .map(|page| page.position())
+            .filter(|&position| position != NO_POSITION)
+            .all(|position| position <= until));
+    }
+
+    #[test]
+    fn test_interrupt_ends_track() {
+        let mut core = playing_core(0);
+        let (tx, rx) = mpsc::sync_channel(4);
+        core.subscribers.push(tx);
+        let handle = core.playing.as_ref().unwrap().handle;
+
+        core.interrupt(prepared()).ok().unwrap();
+        let event: Event = proto::deserialize(&mut io::Cursor::new(rx.try_recv().unwrap())).unwrap();
+        match event {
+            Event::TrackEnded(ref track) if track.handle == handle => (),
+            other => panic!("expected the interrupted track to end, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_seek_past_end() {
+        let mut core = playing_core(0);
+        let final_position = core.playing_track.as_ref().unwrap().final_position();
+        let buffered = core.buffer.len();
+
+        let seek = FastForward::Seek { position: final_position + 1 };
+        assert!(core.fast_forward(FastForwardRequest { kind: seek }).is_err());
+        let seek = FastForward::SeekForward { samples: final_position };
+        assert!(core.fast_forward(FastForwardRequest { kind: seek }).is_err());
+        // the stream carries on as it was
+        assert_eq!(core.buffer.len(), buffered);
+
+        let seek = FastForward::Seek { position: final_position };
+        assert!(core.fast_forward(FastForwardRequest { kind: seek }).is_ok());
+    }
+
+    #[test]
+    fn test_seek_headers_only() {
+        let mut core = playing_core(0);
+        // a track with no audio has nowhere to seek to
+        let headers: Vec<u8> = {
+            let track = core.playing_track.as_ref().unwrap();
+            track.pages()
+                .take_while(|page| page.position() == 0)
+                .flat_map(|page| page.as_u8_slice().iter().cloned())
+                .collect()
+        };
+        core.playing_track = Some(IndexedOggTrack::new(headers).unwrap());
+
+        let seek = FastForward::Seek { position: 0 };
+        assert!(core.fast_forward(FastForwardRequest { kind: seek }).is_err());
+    }
+
+    /// Lost from the start, and slow to come back: a reconnect waits
+    /// until `release` is dropped.
+    struct SlowSink {
+        release: mpsc::Receiver<()>,
+        healthy: bool,
+    }
+
+    impl OutputSink for SlowSink {
+        fn send_ogg_page(&mut self, _page: &OggPage) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn reconnect(&mut self) -> io::Result<()> {
+            let _ = self.release.recv();
+            self.healthy = true;
+            Ok(())
+        }
+
+        fn is_healthy(&self) -> bool {
+            self.healthy
+        }
+    }
+
+    #[test]
+    fn test_serve_while_reconnecting() {
+        let (release, stalled) = mpsc::channel();
+        let sink = SlowSink { release: stalled, healthy: false };
+        let offline = IndexedOggTrack::new(DEAD_AIR.to_vec()).unwrap();
+        let core = Core::new(offline, 48000, 8, 0);
+        let (sender, requests) = mpsc::sync_channel(8);
+        thread::spawn(move || run_core(core, Box::new(sink), Backoff::default(), requests));
+        let proxy = CoreProxy::new(sender, 48000);
+
+        // the reconnect hasn't finished, but requests are still answered
+        assert!(proxy.queue_status(StatusRequest).is_ok());
+        drop(release);
+        assert!(proxy.queue_status(StatusRequest).is_ok());
+    }
+}
@@ -0,0 +1,325 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use time::SteadyTime;
+
+use url;
+
+use ireul_interface::proxy::{CoreProxy, Event, ProxyError, SubscribeRequest, Subscription};
+
+use json::event_json;
+
+// how long a webhook's server gets to answer
+const POST_TIMEOUT_SECS: u64 = 10;
+
+// how long a command gets before it's killed
+const EXEC_TIMEOUT_SECS: i64 = 30;
+
+// how often we check whether a command has finished
+const EXEC_POLL_MILLIS: u64 = 50;
+
+// events waiting for a slow hook beyond this are dropped
+const HOOK_QUEUE_SIZE: usize = 16;
+
+/// Every name `Event::name` gives.
+pub const EVENT_NAMES: &'static [&'static str] = &[
+    "track_started",
+    "track_ended",
+    "queue_changed",
+    "fallback_engaged",
+    "output_disconnected",
+    "output_reconnected",
+];
+
+#[derive(Clone, Debug)]
+enum Action {
+    Post(url::Url),
+    Exec { command: String, args: Vec<String> },
+}
+
+/// Something to do whenever certain events happen on the station.
+#[derive(Clone, Debug)]
+pub struct Hook {
+    // event names, as from `Event::name`; empty for every event
+    events: Vec<String>,
+    action: Action,
+}
+
+impl Hook {
+    /// POSTs each event to `url`, which must be plain `http://`, as a
+    /// JSON object.
+    pub fn post(url: &str) -> Result<Hook, String> {
+        let scheme = url.split(':').next().unwrap().to_lowercase();
+        if scheme != "http" {
+            return Err(format!("hook urls must be http://, not {}", url));
+        }
+        let url = try!(url::Url::parse(url)
+            .map_err(|err| format!("invalid hook url {}: {:?}", url, err)));
+        Ok(Hook {
+            events: Vec::new(),
+            action: Action::Post(url),
+        })
+    }
+
+    /// Runs `command` for each event, with what happened in `IREUL_*`
+    /// environment variables.  It's killed if it takes too long.
+    pub fn exec(command: &str, args: &[String]) -> Hook {
+        Hook {
+            events: Vec::new(),
+            action: Action::Exec {
+                command: command.to_string(),
+                args: args.to_vec(),
+            },
+        }
+    }
+
+    /// Only fires for the named events, such as "track_started".
+    pub fn set_events(&mut self, events: &[String]) -> &mut Self {
+        self.events = events.to_vec();
+        self
+    }
+
+    fn wants(&self, event: &Event) -> bool {
+        self.events.is_empty() || self.events.iter().any(|name| name == event.name())
+    }
+
+    fn fire(&self, event: &Event) -> io::Result<()> {
+        match self.action {
+            Action::Post(ref url) => post(url, &event_json(event).to_string()),
+            Action::Exec { ref command, ref args } => exec(command, args, event),
+        }
+    }
+}
+
+/// Fires `hooks` for `events`, resubscribing through `proxy` if need be,
+/// until the core goes away.  Each hook runs on its own thread, so a slow
+/// one holds up neither the others nor playout; one which falls too far
+/// behind misses events.
+pub fn run_hooks(proxy: CoreProxy, hooks: Vec<Hook>, mut events: Subscription) {
+    let workers: Vec<(Hook, mpsc::SyncSender<Event>)> = hooks.into_iter()
+        .map(|hook| {
+            let (queue, queued) = mpsc::sync_channel(HOOK_QUEUE_SIZE);
+            let worker_hook = hook.clone();
+            thread::spawn(move || hook_worker(worker_hook, queued));
+            (hook, queue)
+        })
+        .collect();
+
+    loop {
+        while let Ok(event) = events.recv() {
+            for &(ref hook, ref queue) in workers.iter().filter(|&&(ref hook, _)| hook.wants(&event)) {
+                if let Err(mpsc::TrySendError::Full(_)) = queue.try_send(event.clone()) {
+                    error!("hook {:?} fell behind; dropping {}", hook.action, event.name());
+                }
+            }
+        }
+        // we fell behind and were dropped, or the core is gone; the next
+        // subscribe tells which.
+        info!("hooks lost their subscription");
+        events = match proxy.subscribe(SubscribeRequest) {
+            Ok(events) => events,
+            Err(ProxyError::CallError(err)) => {
+                error!("hooks couldn't subscribe to events: {:?}", err);
+                return;
+            },
+            Err(_) => return,
+        };
+    }
+}
+
+// Ends once run_hooks has gone.
+fn hook_worker(hook: Hook, queued: mpsc::Receiver<Event>) {
+    while let Ok(event) = queued.recv() {
+        if let Err(err) = hook.fire(&event) {
+            error!("hook {:?} failed on {}: {}", hook.action, event.name(), err);
+        }
+    }
+}
+
+fn connect(domain: &str, port: u16) -> io::Result<TcpStream> {
+    let timeout = Duration::from_secs(POST_TIMEOUT_SECS);
+    let mut last_err = io::Error::new(io::ErrorKind::Other, format!("{} has no addresses", domain));
+    for addr in try!((domain, port).to_socket_addrs()) {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+fn post(url: &url::Url, body: &str) -> io::Result<()> {
+    let domain = try!(url.domain().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::Other, "Missing hostname in URL")
+    }));
+    let port = url.port().unwrap_or(80);
+    let path = url.serialize_path().unwrap_or("/".to_string());
+
+    let mut stream = try!(connect(domain, port));
+    try!(stream.set_read_timeout(Some(Duration::from_secs(POST_TIMEOUT_SECS))));
+    try!(stream.set_write_timeout(Some(Duration::from_secs(POST_TIMEOUT_SECS))));
+
+    try!(write!(stream, "POST {} HTTP/1.0\r\n", path));
+    try!(write!(stream, "Host: {}:{}\r\n", domain, port));
+    try!(write!(stream, "User-Agent: Ireul\r\n"));
+    try!(write!(stream, "Content-Type: application/json\r\n"));
+    try!(write!(stream, "Content-Length: {}\r\n", body.len()));
+    try!(write!(stream, "\r\n"));
+    try!(stream.write_all(body.as_bytes()));
+    try!(stream.flush());
+
+    // HTTP/1.0, so the server hangs up once it's done.
+    let mut response = String::new();
+    try!(stream.read_to_string(&mut response));
+    let status = response.split(' ').nth(1).unwrap_or("");
+    if status.starts_with('2') {
+        Ok(())
+    } else {
+        let status_line = response.lines().next().unwrap_or("").to_string();
+        Err(io::Error::new(io::ErrorKind::Other, format!("unexpected response: {}", status_line)))
+    }
+}
+
+fn exec(command: &str, args: &[String], event: &Event) -> io::Result<()> {
+    let mut cmd = Command::new(command);
+    cmd.args(args).env("IREUL_EVENT", event.name());
+    match *event {
+        Event::TrackStarted(ref track) | Event::TrackEnded(ref track) => {
+            cmd.env("IREUL_TRACK_HANDLE", track.handle.0.to_string())
+                .env("IREUL_TRACK_ARTIST", &track.artist)
+                .env("IREUL_TRACK_ALBUM", &track.album)
+                .env("IREUL_TRACK_TITLE", &track.title);
+            if let Some(started_at) = track.started_at {
+                cmd.env("IREUL_TRACK_STARTED_AT", started_at.to_string());
+            }
+        },
+        _ => (),
+    }
+
+    let mut child = try!(cmd.spawn());
+    let deadline = SteadyTime::now() + ::time::Duration::seconds(EXEC_TIMEOUT_SECS);
+    loop {
+        if let Some(status) = try!(child.try_wait()) {
+            if status.success() {
+                return Ok(());
+            }
+            return Err(io::Error::new(io::ErrorKind::Other, format!("exited with {}", status)));
+        }
+        if deadline < SteadyTime::now() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "took too long and was killed"));
+        }
+        thread::sleep(Duration::from_millis(EXEC_POLL_MILLIS));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use rustc_serialize::json::Json;
+
+    use ogg::OggTrackBuf;
+
+    use ireul_interface::proxy::{EnqueueTrackRequest, Event};
+
+    use output::NullSink;
+    use station::StationBuilder;
+    use testing::track_info;
+    use super::Hook;
+
+    // A stand-in for a webhook's server, which answers `count` requests
+    // and hands back their bodies.
+    fn http_stand_in(count: usize) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let (bodies_tx, bodies) = mpsc::channel();
+        thread::spawn(move || {
+            for _ in 0..count {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut byte = [0; 1];
+                while !request.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).unwrap();
+                    request.push(byte[0]);
+                }
+                let head = String::from_utf8(request).unwrap();
+                assert!(head.starts_with("POST /hook HTTP/1.0\r\n"));
+                let length: u64 = head.lines()
+                    .filter_map(|line| line.split(": ").nth(1).map(|v| (line, v)))
+                    .find(|&(line, _)| line.starts_with("Content-Length"))
+                    .map(|(_, value)| value.parse().unwrap())
+                    .unwrap();
+
+                let mut body = String::new();
+                Read::by_ref(&mut stream).take(length).read_to_string(&mut body).unwrap();
+                stream.write_all(b"HTTP/1.0 204 No Content\r\n\r\n").unwrap();
+                bodies_tx.send(body).unwrap();
+            }
+        });
+
+        (url, bodies)
+    }
+
+    #[test]
+    fn test_post() {
+        let (url, bodies) = http_stand_in(1);
+        Hook::post(&url).unwrap().fire(&Event::TrackEnded(track_info())).unwrap();
+
+        let body = Json::from_str(&bodies.recv().unwrap()).unwrap();
+        assert_eq!(body.find("event").and_then(Json::as_string), Some("track_ended"));
+    }
+
+    #[test]
+    fn test_wants() {
+        let mut hook = Hook::exec("true", &[]);
+        assert!(hook.wants(&Event::QueueChanged));
+
+        hook.set_events(&["fallback_engaged".to_string()]);
+        assert!(hook.wants(&Event::FallbackEngaged));
+        assert!(!hook.wants(&Event::QueueChanged));
+    }
+
+    #[test]
+    fn test_post_scheme() {
+        assert!(Hook::post("http://example.com/hook").is_ok());
+        assert!(Hook::post("https://example.com/hook").is_err());
+        assert!(Hook::post("ftp://example.com/hook").is_err());
+    }
+
+    #[test]
+    fn test_exec_failure() {
+        assert!(Hook::exec("true", &[]).fire(&Event::QueueChanged).is_ok());
+        assert!(Hook::exec("false", &[]).fire(&Event::QueueChanged).is_err());
+    }
+
+    #[test]
+    fn test_station_hooks() {
+        let (url, bodies) = http_stand_in(1);
+        let mut hook = Hook::post(&url).unwrap();
+        hook.set_events(&["queue_changed".to_string()]);
+
+        let station = StationBuilder::new()
+            .set_output(Box::new(NullSink))
+            .add_hook(hook)
+            .start()
+            .unwrap();
+
+        station.enqueue_track(EnqueueTrackRequest {
+            track: OggTrackBuf::new(include_bytes!("deadair.ogg").to_vec()).unwrap(),
+            metadata: None,
+        }).ok().unwrap();
+
+        let body = Json::from_str(&bodies.recv().unwrap()).unwrap();
+        assert_eq!(body.find("event").and_then(Json::as_string), Some("queue_changed"));
+    }
+}
@@ -0,0 +1,658 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::json::Json;
+
+use ogg::OggTrackBuf;
+
+use ireul_interface::proto;
+use ireul_interface::proxy::{
+    CoreProxy,
+    ProxyError,
+    EnqueueTrackRequest,
+    FastForward,
+    FastForwardRequest,
+    ReplaceFallbackRequest,
+    InterruptRequest,
+    RequestType,
+    StatusRequest,
+};
+
+use auth::{self, User};
+use json;
+use websocket;
+
+// request line and headers together
+const HEAD_LIMIT: usize = 8192;
+
+// a client which stops sending mustn't hold a thread forever
+const READ_TIMEOUT_SECS: u64 = 30;
+
+struct Request {
+    method: String,
+    path: String,
+    // lowercased names
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&&(ref key, _)| key == name)
+            .map(|&(_, ref value)| &value[..])
+    }
+}
+
+struct Response {
+    status: u16,
+    body: Option<Json>,
+}
+
+impl Response {
+    fn ok(status: u16, body: Json) -> Response {
+        Response { status: status, body: Some(body) }
+    }
+
+    fn no_content() -> Response {
+        Response { status: 204, body: None }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response { status: status, body: Some(json::error_json(message)) }
+    }
+
+    fn write_to<W: Write>(&self, stream: &mut W) -> io::Result<()> {
+        let body = self.body.as_ref().map(|body| body.to_string()).unwrap_or(String::new());
+
+        try!(write!(stream, "HTTP/1.0 {} {}\r\n", self.status, reason_phrase(self.status)));
+        try!(write!(stream, "Server: Ireul\r\n"));
+        if self.status == 401 {
+            try!(write!(stream, "WWW-Authenticate: Basic realm=\"ireul\"\r\n"));
+        }
+        if self.body.is_some() {
+            try!(write!(stream, "Content-Type: application/json\r\n"));
+        }
+        try!(write!(stream, "Content-Length: {}\r\n", body.len()));
+        try!(write!(stream, "Connection: close\r\n"));
+        try!(write!(stream, "\r\n"));
+        try!(stream.write_all(body.as_bytes()));
+        stream.flush()
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+// Reads the request line and headers, leaving the body to `read_body`
+// once we know the client may send it.
+fn read_head<R: BufRead>(reader: &mut R) -> Result<Request, Response> {
+    let bad_request = |_| Response::error(400, "malformed request");
+
+    let mut head_length = 0;
+    let mut lines = Vec::new();
+    loop {
+        let mut line = Vec::new();
+        try!(Read::by_ref(reader).take((HEAD_LIMIT - head_length) as u64)
+            .read_until(b'\n', &mut line)
+            .map_err(bad_request));
+        head_length += line.len();
+        if !line.ends_with(b"\n") {
+            return Err(Response::error(400, "request head too large"));
+        }
+
+        let line = try!(String::from_utf8(line).map_err(|_| Response::error(400, "malformed request")));
+        let line = line.trim_right().to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+
+    let (method, path) = {
+        let mut request_line = lines.first().map(|line| &line[..]).unwrap_or("").split(' ');
+        match (request_line.next(), request_line.next()) {
+            (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+            _ => return Err(Response::error(400, "malformed request line")),
+        }
+    };
+    // no endpoint takes a query string
+    let path = path.split('?').next().unwrap().to_string();
+
+    let mut headers = Vec::new();
+    for line in lines.iter().skip(1) {
+        let mut parts = line.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => {
+                headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+            },
+            _ => return Err(Response::error(400, "malformed header")),
+        }
+    }
+
+    Ok(Request {
+        method: method,
+        path: path,
+        headers: headers,
+        body: Vec::new(),
+    })
+}
+
+fn read_body<R: BufRead>(reader: &mut R, request: &mut Request) -> Result<(), Response> {
+    let content_length = match request.header("content-length") {
+        Some(length) => try!(length.parse::<usize>()
+            .map_err(|_| Response::error(400, "invalid Content-Length"))),
+        None if request.method == "POST" || request.method == "PUT" => {
+            return Err(Response::error(411, "Content-Length is required"));
+        },
+        None => 0,
+    };
+    if proto::MESSAGE_SIZE_LIMIT < content_length {
+        return Err(Response::error(413, "request body too large"));
+    }
+
+    try!(Read::by_ref(reader).take(content_length as u64)
+        .read_to_end(&mut request.body)
+        .map_err(|_| Response::error(400, "malformed request")));
+    if request.body.len() != content_length {
+        return Err(Response::error(400, "request body truncated"));
+    }
+    Ok(())
+}
+
+struct Part {
+    name: String,
+    data: Vec<u8>,
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn part_name(head: &str) -> Option<String> {
+    for line in head.split("\r\n") {
+        let mut header = line.splitn(2, ':');
+        let (name, value) = match (header.next(), header.next()) {
+            (Some(name), Some(value)) => (name, value),
+            _ => continue,
+        };
+        if name.trim().to_lowercase() != "content-disposition" {
+            continue;
+        }
+        for param in value.split(';').skip(1) {
+            let mut param = param.trim().splitn(2, '=');
+            if let (Some("name"), Some(value)) = (param.next(), param.next()) {
+                return Some(value.trim_matches('"').to_string());
+            }
+        }
+    }
+    None
+}
+
+fn parse_multipart(body: &[u8], boundary: &str) -> Option<Vec<Part>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut next_delimiter = b"\r\n".to_vec();
+    next_delimiter.extend(delimiter.iter().cloned());
+
+    let mut rest = match find(body, &delimiter) {
+        Some(start) => &body[start + delimiter.len()..],
+        None => return None,
+    };
+
+    let mut parts = Vec::new();
+    loop {
+        if rest.starts_with(b"--") {
+            // the close delimiter
+            return Some(parts);
+        }
+        if !rest.starts_with(b"\r\n") {
+            return None;
+        }
+        rest = &rest[2..];
+
+        let head_end = match find(rest, b"\r\n\r\n") {
+            Some(head_end) => head_end,
+            None => return None,
+        };
+        let name = match str::from_utf8(&rest[..head_end]).ok().and_then(part_name) {
+            Some(name) => name,
+            None => return None,
+        };
+        rest = &rest[head_end + 4..];
+
+        let data_end = match find(rest, &next_delimiter) {
+            Some(data_end) => data_end,
+            None => return None,
+        };
+        parts.push(Part {
+            name: name,
+            data: rest[..data_end].to_vec(),
+        });
+        rest = &rest[data_end + next_delimiter.len()..];
+    }
+}
+
+/// A track sent as a raw `audio/ogg` body, or as the `track` field of a
+/// `multipart/form-data` body whose other fields are its metadata.
+fn read_upload(req: Request) -> Result<(OggTrackBuf, Option<Vec<(String, String)>>), Response> {
+    let content_type = req.header("content-type").unwrap_or("audio/ogg").to_string();
+    let media_type = content_type.split(';').next().unwrap().trim().to_lowercase();
+
+    let (data, metadata) = match &media_type[..] {
+        "audio/ogg" | "application/ogg" | "application/octet-stream" => (req.body, None),
+        "multipart/form-data" => {
+            let boundary = content_type.split(';').skip(1)
+                .filter_map(|param| {
+                    let mut param = param.trim().splitn(2, '=');
+                    match (param.next(), param.next()) {
+                        (Some("boundary"), Some(value)) => Some(value.trim_matches('"')),
+                        _ => None,
+                    }
+                })
+                .next();
+            let boundary = try!(boundary.ok_or_else(|| Response::error(400, "missing multipart boundary")));
+            let parts = try!(parse_multipart(&req.body, boundary)
+                .ok_or_else(|| Response::error(400, "malformed multipart body")));
+
+            let mut track = None;
+            let mut metadata = Vec::new();
+            for part in parts.into_iter() {
+                if part.name == "track" {
+                    track = Some(part.data);
+                } else {
+                    let value = try!(String::from_utf8(part.data)
+                        .map_err(|_| Response::error(400, "metadata must be UTF-8")));
+                    metadata.push((part.name, value));
+                }
+            }
+            let track = try!(track.ok_or_else(|| Response::error(400, "missing field: track")));
+            let metadata = if metadata.is_empty() { None } else { Some(metadata) };
+            (track, metadata)
+        },
+        _ => return Err(Response::error(415, "expected audio/ogg or multipart/form-data")),
+    };
+
+    let track = try!(OggTrackBuf::new(data).map_err(|_| Response::error(400, "invalid ogg")));
+    Ok((track, metadata))
+}
+
+/// An empty body skips the current track; otherwise one of
+/// `{"seek": sample}`, `{"forward": samples}` or `{"back": samples}`.
+fn read_fast_forward(req: &Request) -> Result<FastForward, Response> {
+    if req.body.is_empty() {
+        return Ok(FastForward::TrackBoundary);
+    }
+
+    let body = try!(str::from_utf8(&req.body).ok()
+        .and_then(|body| Json::from_str(body).ok())
+        .ok_or_else(|| Response::error(400, "malformed JSON")));
+    let field = |name| body.find(name).and_then(Json::as_u64);
+
+    match (field("seek"), field("forward"), field("back")) {
+        (Some(position), None, None) => Ok(FastForward::Seek { position: position }),
+        (None, Some(samples), None) => Ok(FastForward::SeekForward { samples: samples }),
+        (None, None, Some(samples)) => Ok(FastForward::SeekBackward { samples: samples }),
+        _ => Err(Response::error(400, "expected one of seek, forward or back")),
+    }
+}
+
+fn proxy_error<E: fmt::Debug>(err: ProxyError<E>) -> Response {
+    match err {
+        ProxyError::CallError(err) => Response::error(400, &format!("{:?}", err)),
+        err => {
+            info!("core did not respond: {:?}", err);
+            Response::error(503, "the station isn't responding")
+        },
+    }
+}
+
+/// What `req` asks of the station, for checking against a role.
+fn request_type(req: &Request) -> Option<RequestType> {
+    match (&req.method[..], &req.path[..]) {
+        ("GET", "/queue") => Some(RequestType::QueueStatus),
+        ("POST", "/queue") => Some(RequestType::EnqueueTrack),
+        ("POST", "/interrupt") => Some(RequestType::Interrupt),
+        ("PUT", "/fallback") => Some(RequestType::ReplaceFallback),
+        ("POST", "/fast-forward") => Some(RequestType::FastForward),
+        ("GET", "/feed") => Some(RequestType::Subscribe),
+        _ => None,
+    }
+}
+
+/// The name and token of a Basic `Authorization` header.
+fn basic_credentials(req: &Request) -> Option<(String, String)> {
+    let value = match req.header("authorization") {
+        Some(value) => value,
+        None => return None,
+    };
+    let mut words = value.split(' ').filter(|word| !word.is_empty());
+    match words.next() {
+        Some(scheme) if scheme.to_lowercase() == "basic" => (),
+        _ => return None,
+    }
+    let decoded = match words.next().map(|encoded| encoded.from_base64()) {
+        Some(Ok(decoded)) => decoded,
+        _ => return None,
+    };
+    let decoded = match String::from_utf8(decoded) {
+        Ok(decoded) => decoded,
+        Err(_) => return None,
+    };
+    let mut parts = decoded.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(token)) => Some((name.to_string(), token.to_string())),
+        _ => None,
+    }
+}
+
+/// Who sent `req` from `peer`, as they're known in the logs, so long as
+/// their role allows it.  With no users, anyone may do anything.
+fn authorize(users: &[User], req: &Request, peer: &str) -> Result<String, Response> {
+    if users.is_empty() {
+        return Ok(format!("anonymous@{}", peer));
+    }
+    let user = match basic_credentials(req) {
+        Some((name, token)) => match auth::authenticate_as(users, &name, &token) {
+            Some(user) => user,
+            None => {
+                info!("{}@{} (unverified): HTTP {} {}: bad credentials", name, peer, req.method, req.path);
+                return Err(Response::error(401, "bad credentials"));
+            },
+        },
+        None => return Err(Response::error(401, "log in with HTTP Basic authentication")),
+    };
+
+    let who = format!("{}@{}", user.name(), peer);
+    match request_type(req) {
+        Some(req_type) if !user.role().allows(req_type) => {
+            let err_msg = format!("{} may not send {:?}", who, req_type);
+            info!("{}", err_msg);
+            Err(Response::error(403, &err_msg))
+        },
+        _ => Ok(who),
+    }
+}
+
+fn route(proxy: &CoreProxy, req: Request) -> Result<Response, Response> {
+    // uploads take the request, body and all
+    let (method, path) = (req.method.clone(), req.path.clone());
+    match (&method[..], &path[..]) {
+        ("GET", "/queue") => {
+            let queue = try!(proxy.queue_status(StatusRequest).map_err(proxy_error));
+            Ok(Response::ok(200, json::queue_json(&queue)))
+        },
+        ("POST", "/queue") => {
+            let (track, metadata) = try!(read_upload(req));
+            let handle = try!(proxy.enqueue_track(EnqueueTrackRequest {
+                track: track,
+                metadata: metadata,
+            }).map_err(proxy_error));
+            Ok(Response::ok(201, json::handle_json(handle)))
+        },
+        ("POST", "/interrupt") => {
+            let (track, metadata) = try!(read_upload(req));
+            let handle = try!(proxy.interrupt(InterruptRequest {
+                track: track,
+                metadata: metadata,
+            }).map_err(proxy_error));
+            Ok(Response::ok(201, json::handle_json(handle)))
+        },
+        ("PUT", "/fallback") => {
+            let (track, metadata) = try!(read_upload(req));
+            try!(proxy.replace_fallback(ReplaceFallbackRequest {
+                track: track,
+                metadata: metadata,
+            }).map_err(proxy_error));
+            Ok(Response::no_content())
+        },
+        ("POST", "/fast-forward") => {
+            let kind = try!(read_fast_forward(&req));
+            try!(proxy.fast_forward(FastForwardRequest { kind: kind }).map_err(proxy_error));
+            Ok(Response::no_content())
+        },
+        (_, "/queue") | (_, "/interrupt") | (_, "/fallback") | (_, "/fast-forward") => {
+            Err(Response::error(405, "method not allowed"))
+        },
+        _ => Err(Response::error(404, "not found")),
+    }
+}
+
+fn client_worker(stream: TcpStream, proxy: CoreProxy, users: Arc<Vec<User>>) -> io::Result<()> {
+    try!(stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECS))));
+    let peer = stream.peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or("unknown".to_string());
+    let mut reader = BufReader::new(try!(stream.try_clone()));
+    let mut writer = stream;
+
+    let mut req = match read_head(&mut reader) {
+        Ok(req) => req,
+        Err(err) => return err.write_to(&mut writer),
+    };
+    // before reading what might be 100MB from a stranger
+    let who = match authorize(&users, &req, &peer) {
+        Ok(who) => who,
+        Err(err) => return err.write_to(&mut writer),
+    };
+    info!("{}: HTTP {} {}", who, req.method, req.path);
+    if let Err(err) = read_body(&mut reader, &mut req) {
+        return err.write_to(&mut writer);
+    }
+
+    let response = if req.path == "/feed" {
+        match feed_key(&req) {
+            // the socket is the feed's now; anything buffered from the
+            // client was a frame, and those are ignored anyway.
+            Ok(key) => return websocket::serve_feed(writer, key, proxy),
+            Err(err) => err,
+        }
+    } else {
+        route(&proxy, req).unwrap_or_else(|err| err)
+    };
+    response.write_to(&mut writer)
+}
+
+/// The `Sec-WebSocket-Key` of a WebSocket handshake for `/feed`.
+fn feed_key(req: &Request) -> Result<&str, Response> {
+    if req.method != "GET" {
+        return Err(Response::error(405, "method not allowed"));
+    }
+    let upgrade = req.header("upgrade").map(|value| value.to_lowercase());
+    if upgrade.as_ref().map(|value| &value[..]) != Some("websocket") {
+        return Err(Response::error(400, "expected a WebSocket upgrade"));
+    }
+    req.header("sec-websocket-key")
+        .ok_or_else(|| Response::error(400, "missing Sec-WebSocket-Key"))
+}
+
+/// Serves the HTTP API to clients connecting to `server`, who must give
+/// the name and token of one of `users`, if there are any, to do what
+/// their role allows.
+pub fn client_acceptor(server: TcpListener, proxy: CoreProxy, users: Arc<Vec<User>>) {
+    for stream in server.incoming() {
+        match stream {
+            Ok(stream) => {
+                let client_proxy = proxy.clone();
+                let client_users = users.clone();
+                thread::spawn(move || {
+                    if let Err(err) = client_worker(stream, client_proxy, client_users) {
+                        info!("HTTP client disconnected with error: {:?}", err);
+                    }
+                });
+            },
+            Err(err) => {
+                info!("error accepting new HTTP client: {:?}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use rustc_serialize::base64::{ToBase64, STANDARD};
+    use rustc_serialize::json::Json;
+
+    use auth::{Role, User};
+    use output::NullSink;
+    use station::StationBuilder;
+    use super::parse_multipart;
+
+    const DEAD_AIR: &'static [u8] = include_bytes!("deadair.ogg");
+
+    fn start_station() -> String {
+        start_station_with(&[])
+    }
+
+    fn start_station_with(users: &[User]) -> String {
+        let mut builder = StationBuilder::new();
+        builder.set_output(Box::new(NullSink));
+        for user in users.iter() {
+            builder.add_user(user.clone());
+        }
+        let station = builder.start().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || station.serve_http(listener));
+        addr
+    }
+
+    // sends a request, returning the status and body of the response
+    fn request(addr: &str, head: &str, body: &[u8]) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "{}Content-Length: {}\r\n\r\n", head, body.len()).unwrap();
+        stream.write_all(body).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response.split(' ').nth(1).unwrap().parse().unwrap();
+        let body = response.splitn(2, "\r\n\r\n").nth(1).unwrap().to_string();
+        (status, body)
+    }
+
+    #[test]
+    fn test_parse_multipart() {
+        let body = b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"TITLE\"\r\n\r\n\
+            silence\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"track\"; filename=\"a.ogg\"\r\n\
+            Content-Type: audio/ogg\r\n\r\n\
+            OggS\r\n\x00\r\n--XyZ--\r\n";
+        let parts = parse_multipart(body, "XyZ").unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "TITLE");
+        assert_eq!(&parts[0].data[..], b"silence");
+        assert_eq!(parts[1].name, "track");
+        assert_eq!(&parts[1].data[..], b"OggS\r\n\x00");
+
+        assert!(parse_multipart(b"--XyZ\r\nno head", "XyZ").is_none());
+    }
+
+    #[test]
+    fn test_enqueue_and_show() {
+        let addr = start_station();
+
+        let (status, body) = request(&addr,
+            "POST /queue HTTP/1.0\r\nContent-Type: audio/ogg\r\n", DEAD_AIR);
+        assert_eq!(status, 201);
+        let handle = Json::from_str(&body).unwrap().find("handle").and_then(Json::as_u64).unwrap();
+
+        let (status, body) = request(&addr, "GET /queue HTTP/1.0\r\n", b"");
+        assert_eq!(status, 200);
+        let queue = Json::from_str(&body).unwrap();
+        let upcoming = queue.find("upcoming").and_then(Json::as_array).unwrap();
+        assert!(upcoming.iter().any(|track| track.find("handle").and_then(Json::as_u64) == Some(handle)));
+    }
+
+    #[test]
+    fn test_multipart_upload() {
+        let addr = start_station();
+
+        let mut body = b"--b0undary\r\n\
+            Content-Disposition: form-data; name=\"ARTIST\"\r\n\r\n\
+            nobody\r\n--b0undary\r\n\
+            Content-Disposition: form-data; name=\"track\"; filename=\"deadair.ogg\"\r\n\r\n".to_vec();
+        body.extend(DEAD_AIR.iter().cloned());
+        body.extend(b"\r\n--b0undary--\r\n".iter().cloned());
+
+        let (status, _) = request(&addr,
+            "POST /queue HTTP/1.0\r\nContent-Type: multipart/form-data; boundary=b0undary\r\n", &body);
+        assert_eq!(status, 201);
+
+        let (_, body) = request(&addr, "GET /queue HTTP/1.0\r\n", b"");
+        let queue = Json::from_str(&body).unwrap();
+        let upcoming = queue.find("upcoming").and_then(Json::as_array).unwrap();
+        assert!(upcoming.iter().any(|track| track.find("artist").and_then(Json::as_string) == Some("nobody")));
+    }
+
+    #[test]
+    fn test_errors() {
+        let addr = start_station();
+
+        let (status, body) = request(&addr, "POST /queue HTTP/1.0\r\n", b"not ogg");
+        assert_eq!(status, 400);
+        assert!(Json::from_str(&body).unwrap().find("error").is_some());
+
+        assert_eq!(request(&addr, "GET /nowhere HTTP/1.0\r\n", b"").0, 404);
+        assert_eq!(request(&addr, "GET /feed HTTP/1.0\r\n", b"").0, 400);
+        assert_eq!(request(&addr, "DELETE /queue HTTP/1.0\r\n", b"").0, 405);
+        assert_eq!(request(&addr, "POST /fast-forward HTTP/1.0\r\n", b"{\"seek\": 1, \"back\": 1}").0, 400);
+        assert_eq!(request(&addr, "POST /fast-forward HTTP/1.0\r\n", b"").0, 204);
+    }
+
+    #[test]
+    fn test_auth() {
+        let addr = start_station_with(&[
+            User::new("ann", "secret", Role::Dj),
+            User::new("bob", "hunter2", Role::Requester),
+        ]);
+        let as_user = |credentials: &str| {
+            format!("Authorization: Basic {}\r\n", credentials.as_bytes().to_base64(STANDARD))
+        };
+
+        assert_eq!(request(&addr, "GET /queue HTTP/1.0\r\n", b"").0, 401);
+        let head = format!("GET /queue HTTP/1.0\r\n{}", as_user("bob:secret"));
+        assert_eq!(request(&addr, &head, b"").0, 401);
+        let head = format!("GET /queue HTTP/1.0\r\n{}", as_user("bob:hunter2"));
+        assert_eq!(request(&addr, &head, b"").0, 200);
+
+        // a requester may add tracks, but not skip them
+        let head = format!("POST /fast-forward HTTP/1.0\r\n{}", as_user("bob:hunter2"));
+        assert_eq!(request(&addr, &head, b"").0, 403);
+        let head = format!("POST /fast-forward HTTP/1.0\r\n{}", as_user("ann:secret"));
+        assert_eq!(request(&addr, &head, b"").0, 204);
+        let head = format!("PUT /fallback HTTP/1.0\r\n{}", as_user("ann:secret"));
+        assert_eq!(request(&addr, &head, DEAD_AIR).0, 403);
+
+        // strangers are turned away before they send a body
+        let mut stream = TcpStream::connect(&addr[..]).unwrap();
+        write!(stream, "POST /queue HTTP/1.0\r\nContent-Length: 1000000\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 401 "));
+    }
+}
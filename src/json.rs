@@ -0,0 +1,100 @@
+//! The JSON forms of the things in `ireul_interface::proxy`, as spoken by
+//! the HTTP API and webhooks.
+
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use ireul_interface::proxy::Event;
+use ireul_interface::proxy::track::model::{Handle, Queue, TrackInfo};
+
+pub fn handle_json(handle: Handle) -> Json {
+    let mut obj = BTreeMap::new();
+    obj.insert("handle".to_string(), handle.0.to_json());
+    Json::Object(obj)
+}
+
+pub fn track_json(track: &TrackInfo) -> Json {
+    let mut obj = BTreeMap::new();
+    obj.insert("handle".to_string(), track.handle.0.to_json());
+    if let Some(started_at) = track.started_at {
+        obj.insert("started_at".to_string(), started_at.to_json());
+    }
+    obj.insert("artist".to_string(), track.artist.to_json());
+    obj.insert("album".to_string(), track.album.to_json());
+    obj.insert("title".to_string(), track.title.to_json());
+    obj.insert("sample_rate".to_string(), track.sample_rate.to_json());
+    obj.insert("sample_count".to_string(), track.sample_count.to_json());
+    obj.insert("sample_position".to_string(), track.sample_position.to_json());
+
+    let metadata: Vec<Json> = track.metadata.iter()
+        .map(|&(ref key, ref value)| Json::Array(vec![key.to_json(), value.to_json()]))
+        .collect();
+    obj.insert("metadata".to_string(), Json::Array(metadata));
+    Json::Object(obj)
+}
+
+pub fn queue_json(queue: &Queue) -> Json {
+    let mut obj = BTreeMap::new();
+    obj.insert("playing".to_string(), queue.playing.as_ref().map(track_json).unwrap_or(Json::Null));
+    obj.insert("upcoming".to_string(), Json::Array(queue.upcoming.iter().map(track_json).collect()));
+    obj.insert("history".to_string(), Json::Array(queue.history.iter().map(track_json).collect()));
+    Json::Object(obj)
+}
+
+/// `{"event": "track_started", "track": {...}}`, with `track` only for
+/// the events about a track.
+pub fn event_json(event: &Event) -> Json {
+    let mut obj = BTreeMap::new();
+    obj.insert("event".to_string(), event.name().to_json());
+    match *event {
+        Event::TrackStarted(ref track) | Event::TrackEnded(ref track) => {
+            obj.insert("track".to_string(), track_json(track));
+        },
+        _ => (),
+    }
+    Json::Object(obj)
+}
+
+/// `{"error": message}`
+pub fn error_json(message: &str) -> Json {
+    let mut obj = BTreeMap::new();
+    obj.insert("error".to_string(), message.to_json());
+    Json::Object(obj)
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+
+    use ireul_interface::proxy::Event;
+    use ireul_interface::proxy::track::model::Queue;
+
+    use testing::track_info;
+    use super::{event_json, queue_json};
+
+    #[test]
+    fn test_event_json() {
+        let json = event_json(&Event::TrackStarted(track_info()));
+        assert_eq!(json.find("event").and_then(Json::as_string), Some("track_started"));
+        assert_eq!(json.find_path(&["track", "handle"]).and_then(Json::as_u64), Some(3));
+        assert_eq!(json.find_path(&["track", "title"]).and_then(Json::as_string), Some("title"));
+
+        let json = event_json(&Event::OutputDisconnected);
+        assert_eq!(json.find("event").and_then(Json::as_string), Some("output_disconnected"));
+        assert!(json.find("track").is_none());
+    }
+
+    #[test]
+    fn test_queue_json() {
+        let json = queue_json(&Queue {
+            playing: None,
+            upcoming: vec![track_info()],
+            history: Vec::new(),
+        });
+        let upcoming = json.find("upcoming").and_then(Json::as_array).unwrap();
+        assert_eq!(upcoming[0].find("artist").and_then(Json::as_string), Some("artist"));
+        assert_eq!(json.find("history").and_then(Json::as_array).map(|h| h.len()), Some(0));
+        assert!(json.find("playing").unwrap().is_null());
+    }
+}
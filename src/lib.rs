@@ -0,0 +1,50 @@
+//! Ireul plays a queue of Ogg Vorbis tracks out to Icecast, or some other
+//! output, as one continuous stream.
+//!
+//! A `Station` can be embedded directly, or driven over the network with
+//! the protocol in `ireul_interface`, which is what the `ireul` binary does.
+
+#[macro_use]
+extern crate log;
+
+extern crate byteorder;
+extern crate ireul_interface;
+extern crate ogg;
+extern crate ogg_clock;
+extern crate rand;
+extern crate rustc_serialize;
+extern crate toml;
+extern crate url;
+extern crate time;
+#[cfg(feature = "tls")]
+extern crate openssl;
+#[cfg(feature = "tls")]
+extern crate openssl_verify;
+
+mod auth;
+mod core;
+mod queue;
+mod server;
+mod station;
+mod config;
+mod hooks;
+mod http;
+mod json;
+mod mpd;
+mod websocket;
+pub mod icecastwriter;
+pub mod output;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(test)]
+mod testing;
+
+pub use auth::{Role, User};
+pub use config::Config;
+pub use core::Backoff;
+pub use hooks::Hook;
+pub use station::{Station, StationBuilder};
+#[cfg(unix)]
+pub use server::bind_unix;
+pub use icecastwriter::{IceCastWriterOptions, SourceMethod};
+pub use output::OutputSink;
@@ -1,656 +1,61 @@
-#[macro_use]
-extern crate log;
-
-extern crate byteorder;
 extern crate env_logger;
-extern crate ireul_interface;
-extern crate ogg;
-extern crate ogg_clock;
-extern crate rand;
-extern crate rustc_serialize;
-extern crate toml;
-extern crate url;
-extern crate time;
+extern crate ireul;
 
-use std::thread;
 use std::env;
-use std::mem;
-use std::sync::{Arc, Mutex};
-use std::net::{TcpStream, TcpListener};
-use std::collections::VecDeque;
-use std::io::{self, Read, Write};
-use std::fs::File;
-use std::time::Duration;
-
-use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian, ByteOrder};
-use time::SteadyTime;
-
-use ogg::{OggTrack, OggTrackBuf, OggPageBuf, OggBuilder};
-use ogg::vorbis::{VorbisPacket, VorbisPacketBuf, Comments as VorbisComments};
-use ogg_clock::OggClock;
-
-use ireul_interface::proto;
-use ireul_interface::proxy::track::model::{self, Handle};
-use ireul_interface::proxy::track::{
-    StatusRequest,
-    StatusResult,
-};
-
-use ireul_interface::proxy::{
-    RequestType,
-    EnqueueTrackRequest,
-    EnqueueTrackError,
-    EnqueueTrackResult,
-    FastForward,
-    FastForwardRequest,
-    FastForwardResult,
-    ReplaceFallbackRequest,
-    ReplaceFallbackResult,
-    ReplaceFallbackError,
-};
-
-mod queue;
-mod icecastwriter;
-
-use queue::{PlayQueue, PlayQueueError};
-use icecastwriter::{
-    IceCastWriter,
-    IceCastWriterOptions,
-};
-
-const DEAD_AIR: &'static [u8] = include_bytes!("deadair.ogg");
-
-const MAX_RECONNECT_WAIT: u64 = 120;
-
-
-#[derive(RustcDecodable, Debug)]
-struct MetadataConfig {
-    name: Option<String>,
-    description: Option<String>,
-    url: Option<String>,
-    genre: Option<String>,
-}
-
-#[derive(RustcDecodable, Debug)]
-struct Config {
-    icecast_url: String,
-    metadata: Option<MetadataConfig>,
-    fallback_track: Option<String>,
-    listen_addr: Option<String>,
-}
-
-impl Config {
-    fn icecast_url(&self) -> Result<url::Url, String> {
-        let url = try!(url::Url::parse(&self.icecast_url)
-            .map_err(|err| format!("Malformed URL: {:?}", err)));
-        Ok(url)
-    }
-
-    fn icecast_writer_opts(&self) -> Result<IceCastWriterOptions, String> {
-        let mut opts = IceCastWriterOptions::default();
-        if let Some(ref metadata) = self.metadata {
-            if let Some(ref name) = metadata.name {
-                opts.set_name(name);
-            }
-            if let Some(ref description) = metadata.description {
-                opts.set_description(description);
-            }
-            if let Some(ref url) = metadata.url {
-                opts.set_url(url);
-            }
-            if let Some(ref genre) = metadata.genre {
-                opts.set_genre(genre);
-            }
-        }
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::thread;
 
-        Ok(opts)
-    }
-}
+use ireul::{Config, Station, StationBuilder};
 
 fn main() {
     env_logger::init().unwrap();
 
     let config_file = env::args_os().nth(1).unwrap();
+    let config = Config::from_file(&config_file).unwrap();
 
-    let config: Config = {
-        let mut reader = File::open(&config_file).expect("failed to open config file");
-        let mut config_buf = String::new();
-        reader.read_to_string(&mut config_buf).expect("failed to read config");
-        toml::decode_str(&config_buf).expect("invalid config file")
-    };
-
-    let icecast_url = config.icecast_url().unwrap();
-    let icecast_options = config.icecast_writer_opts().unwrap();
-    let connector = IceCastWriter::with_options(&icecast_url, &icecast_options).unwrap();
+    let control = config.listen_addr().map(|addr| TcpListener::bind(addr).unwrap());
+    let station = StationBuilder::from_config(&config)
+        .and_then(|mut builder| builder.start())
+        .unwrap();
 
-    let mut offline_track = OggTrack::new(DEAD_AIR).unwrap().to_owned();
-
-    if let Some(ref filename) = config.fallback_track {
-        let mut file = File::open(filename  ).unwrap();
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).unwrap();
-        offline_track = OggTrackBuf::new(buffer).unwrap();
+    if let Some(addr) = config.http_listen_addr() {
+        let http = TcpListener::bind(addr).unwrap();
+        let http_station = station.clone();
+        thread::spawn(move || http_station.serve_http(http));
     }
 
-    let control_listen = config.listen_addr
-        .as_ref()
-        .map(String::clone)
-        .unwrap_or_else(|| "[::]:3001".into());
-
-    let control = TcpListener::bind(&control_listen[..]).unwrap();
-    let core = Arc::new(Mutex::new(Core {
-        connector: connector,
-        icecast_url: icecast_url,
-        icecast_options: icecast_options,
-
-        cur_serial: 0,
-        clock: OggClock::new(48000),
-        playing_offline: false,
-        buffer: VecDeque::new(),
-
-        prev_ogg_granule_pos: 0,
-        prev_ogg_serial: 0,
-        prev_ogg_sequence: 0,
-
-        play_queue: PlayQueue::new(40),
-        offline_track: queue::Track::from_ogg_track(Handle(0), offline_track),
-        playing: None,
-        history: Vec::new(),
-    }));
-
-    let client_core = core.clone();
-    thread::spawn(move || {
-        client_acceptor(control, client_core.clone());
-    });
-
-    loop {
-        let next_tick_deadline = {
-            let mut exc_core = core.lock().unwrap();
-            exc_core.tick()
-        };
-
-        let sleep_time = next_tick_deadline - SteadyTime::now();
-        thread::sleep(Duration::from_millis(sleep_time.num_milliseconds() as u64));
-
+    if let Some(addr) = config.mpd_listen_addr() {
+        let mpd = TcpListener::bind(addr).unwrap();
+        let media_root = config.media_root().map(PathBuf::from);
+        let mpd_station = station.clone();
+        thread::spawn(move || mpd_station.serve_mpd(mpd, media_root));
     }
-}
 
-fn validate_positions(track: &OggTrack) -> Result<(), ()> {
-    let mut current = 0;
-    let mut is_first = true;
+    spawn_unix_control(&config, &station);
 
-    for page in track.pages() {
-        let position = page.position();
-
-        if is_first {
-            is_first = false;
-
-            if position != 0 {
-                return Err(());
-            }
-        }
-
-        if position < current {
-            return Err(());
-        }
-        current = position;
-    }
-
-    Ok(())
-}
-
-fn validate_comment_section(track: &OggTrack) -> Result<(), ()> {
-    let _ = try!(VorbisPacket::find_comments(track.pages()));
-    Ok(())
-}
-
-fn check_sample_rate(req: u32, track: &OggTrack) -> Result<(), ()> {
-    let packet = try!(VorbisPacket::find_identification(track.pages()));
-
-    // find_identification will always find a packet with an identification_header
-    let id_header = packet.identification_header().unwrap();
-
-    if id_header.audio_sample_rate == req {
-        Ok(())
-    } else {
-        Err(())
-    }
-}
-
-
-fn update_serial(serial: u32, track: &mut OggTrack) {
-    for page in track.pages_mut() {
-        page.set_serial(serial);
+    match control {
+        Some(control) => station.serve(control),
+        // the other listeners serve on their own threads
+        None => loop {
+            thread::park();
+        },
     }
 }
 
-fn client_worker(mut stream: TcpStream, core: Arc<Mutex<Core>>) -> io::Result<()> {
-    loop {
-        let version = try!(stream.read_u8());
-
-        if version != 0 {
-            let err_msg = format!("invalid version: {}", version);
-            return Err(io::Error::new(io::ErrorKind::Other, err_msg));
-        }
-
-        let op_code = try!(stream.read_u32::<BigEndian>());
-        if op_code == 0 {
-            info!("goodbye, client");
-            return Ok(());
-        }
-
-        let req_type = try!(RequestType::from_op_code(op_code).map_err(|_| {
-            let err_msg = format!("unknown op-code {:?}", op_code);
-            io::Error::new(io::ErrorKind::Other, err_msg)
-        }));
-
-        let frame_length = try!(stream.read_u32::<BigEndian>()) as usize;
-        if proto::MESSAGE_SIZE_LIMIT < frame_length {
-            let err_msg = format!("datagram too large: {} bytes (limit is {})",
-                frame_length, proto::MESSAGE_SIZE_LIMIT);
-            return Err(io::Error::new(io::ErrorKind::Other, err_msg));
-        }
-
-        let mut req_buf = Vec::new();
-        {
-            let mut limit_reader = Read::by_ref(&mut stream).take(frame_length as u64);
-            try!(limit_reader.read_to_end(&mut req_buf));
-        }
-
-        if req_buf.len() != frame_length {
-            let err_msg = format!(
-                "datagram truncated: got {} bytes, expected {}",
-                req_buf.len(), frame_length);
-            return Err(io::Error::new(io::ErrorKind::Other, err_msg));
-        }
-
-        let mut cursor = io::Cursor::new(req_buf);
-        let response = match req_type {
-            RequestType::EnqueueTrack => {
-                let req = proto::deserialize(&mut cursor).unwrap();
-                let resp = {
-                    let mut exc_core = core.lock().unwrap();
-                    exc_core.enqueue_track(req)
-                };
-                proto::serialize(&resp).unwrap()
-            },
-            RequestType::FastForward => {
-                let req = proto::deserialize(&mut cursor).unwrap();
-                let resp = {
-                    let mut exc_core = core.lock().unwrap();
-                    exc_core.fast_forward(req)
-                };
-                proto::serialize(&resp).unwrap()
-            },
-            RequestType::QueueStatus => {
-                let req = proto::deserialize(&mut cursor).unwrap();
-                let resp = {
-                    let mut exc_core = core.lock().unwrap();
-                    exc_core.queue_status(req)
-                };
-                proto::serialize(&resp).unwrap()
-            },
-            RequestType::ReplaceFallback => {
-                let req = proto::deserialize(&mut cursor).unwrap();
-                let resp = {
-                    let mut exc_core = core.lock().unwrap();
-                    exc_core.replace_fallback(req)
-                };
-                proto::serialize(&resp).unwrap()            }
-        };
-        try!(stream.write_u32::<BigEndian>(response.len() as u32));
-        try!(stream.write_all(&response));
-    }
-}
-
-fn client_acceptor(server: TcpListener, core: Arc<Mutex<Core>>) {
-    for stream in server.incoming() {
-        match stream {
-            Ok(stream) => {
-                let client_core = core.clone();
-                thread::spawn(move || {
-                    if let Err(err) = client_worker(stream, client_core) {
-                        info!("client disconnected with error: {:?}", err);
-                    }
-                });
-            },
-            Err(err) => {
-                info!("error accepting new client: {:?}", err);
-            }
-        }
-    }
-}
-
-/// Connects to IceCast and holds references to streamable content.
-struct Core {
-    connector: IceCastWriter,
-    icecast_url: url::Url,
-    icecast_options: IceCastWriterOptions,
-
-    cur_serial: u32,
-    clock: OggClock,
-
-    playing_offline: bool,
-    buffer: VecDeque<OggPageBuf>,
-
-    prev_ogg_granule_pos: u64,
-    prev_ogg_serial: u32,
-    prev_ogg_sequence: u32,
-
-    play_queue: PlayQueue,
-    offline_track: queue::Track,
-    playing: Option<model::TrackInfo>,
-
-    history: Vec<model::TrackInfo>,
-}
-
-impl Core {
-    fn fill_buffer(&mut self) {
-        if let Some(tinfo) = self.playing.take() {
-            self.history.push(tinfo);
-            self.history_cleanup();
-        }
-
-        let track: queue::Track = match self.play_queue.pop_track() {
-            Some(track) => {
-                self.playing_offline = false;
-                let mut tinfo = track.get_track_info();
-                tinfo.started_at = Some(time::get_time().sec);
-                self.playing = Some(tinfo);
-                track
-            },
-            None => {
-                self.playing_offline = true;
-                self.playing = None;
-                self.offline_track.clone()
-            }
-        };
-        let mut track = track.into_inner();
-        // not sure why we as_mut instead of just using &mut track
-        update_serial(self.cur_serial, track.as_mut());
-        self.cur_serial = self.cur_serial.wrapping_add(1);
-
-        self.buffer.extend(track.pages().map(|x| x.to_owned()));
-    }
-
-    fn get_next_page(&mut self) -> OggPageBuf {
-        if self.buffer.is_empty() {
-            self.fill_buffer();
-        }
-        self.buffer.pop_front().unwrap()
-    }
-
-    fn fast_forward_track_boundary(&mut self) -> FastForwardResult {
-        let old_buffer = mem::replace(&mut self.buffer, VecDeque::new());
-
-        let mut page_iter = old_buffer.into_iter();
-
-        while let Some(page) = page_iter.next() {
-            debug!("checking buffer for non-continued page...");
-            if page.as_ref().continued() {
-                debug!("checking buffer for non-continued page... continued; kept");
-                self.buffer.push_back(page);
-            } else {
-                debug!("checking buffer for non-continued page...found page-aligned packet!");
-                break;
-            }
-        }
-        while let Some(mut page) = page_iter.next() {
-            // debug!("checking page for EOS...");
-            if page.as_ref().eos() {
-                {
-                    let mut tx = page.as_mut().begin();
-                    tx.set_position(self.prev_ogg_granule_pos);
-                    tx.set_serial(self.prev_ogg_serial);
-                    tx.set_sequence(self.prev_ogg_sequence + 1);
-                }
-                debug!("checking page for EOS... found it!");
-                self.buffer.push_back(page);
-                break;
-            }
-        }
-
-        self.buffer.extend(page_iter);
-        Ok(())
-    }
-
-    // **
-    fn enqueue_track(&mut self, req: EnqueueTrackRequest) -> EnqueueTrackResult {
-        let EnqueueTrackRequest { track, metadata } = req;
-        {
-            let mut pages = 0;
-            let mut samples = 0;
-            for page in track.pages() {
-                pages += 1;
-                samples = page.position();
-            }
-
-            info!("a client sent {} samples in {} pages", samples, pages);
-        }
-
-        if track.as_u8_slice().len() == 0 {
-            return Err(EnqueueTrackError::InvalidTrack);
-        }
-
-        try!(validate_positions(&track)
-            .map_err(|()| EnqueueTrackError::InvalidTrack));
-
-        try!(validate_comment_section(&track)
-            .map_err(|()| EnqueueTrackError::InvalidTrack));
-
-        try!(check_sample_rate(self.clock.sample_rate(), &track)
-            .map_err(|()| EnqueueTrackError::BadSampleRate));
-
-        let track = rewrite_comments(track.as_ref(), |comments| {
-            comments.vendor = "Ireul Core".to_string();
-            if let Some(ref metadata) = metadata {
-                comments.comments.clear();
-                comments.comments.extend(metadata.iter().cloned());
-            }
-        });
-
-        let handle = self.play_queue.add_track(track.as_ref())
-            .map_err(|err| match err {
-                PlayQueueError::Full => EnqueueTrackError::Full,
-            });
-
-        if self.playing_offline {
-            self.fast_forward_track_boundary().unwrap();
-        }
-
-        handle
+#[cfg(unix)]
+fn spawn_unix_control(config: &Config, station: &Station) {
+    if let Some(path) = config.unix_listen_path() {
+        let mode = config.unix_listen_mode().unwrap();
+        let unix_control = ireul::bind_unix(path, mode).unwrap();
+        let unix_station = station.clone();
+        thread::spawn(move || unix_station.serve_unix(unix_control));
     }
-
-    fn fast_forward(&mut self, req: FastForwardRequest) -> FastForwardResult {
-        match req.kind {
-            FastForward::TrackBoundary => {
-                try!(self.fast_forward_track_boundary());
-                Ok(())
-            }
-        }
-    }
-
-    fn queue_status(&mut self, _req: StatusRequest) -> StatusResult {
-        let mut upcoming: Vec<model::TrackInfo> = Vec::new();
-        if let Some(ref playing) = self.playing {
-            upcoming.push(playing.clone());
-        }
-        upcoming.extend(self.play_queue.track_infos().into_iter());
-
-        Ok(model::Queue {
-            upcoming: upcoming,
-            history: self.history.clone(),
-        })
-    }
-
-    fn replace_fallback(&mut self, req: ReplaceFallbackRequest) -> ReplaceFallbackResult {
-        let ReplaceFallbackRequest { track, metadata } = req;
-        {
-            let mut pages = 0;
-            let mut samples = 0;
-            for page in track.pages() {
-                pages += 1;
-                samples = page.position();
-            }
-
-            info!("a client sent {} samples in {} pages", samples, pages);
-        }
-
-        if track.as_u8_slice().len() == 0 {
-            return Err(ReplaceFallbackError::InvalidTrack);
-        }
-
-        try!(validate_positions(&track)
-            .map_err(|()| ReplaceFallbackError::InvalidTrack));
-
-        try!(validate_comment_section(&track)
-            .map_err(|()| ReplaceFallbackError::InvalidTrack));
-
-        try!(check_sample_rate(self.clock.sample_rate(), &track)
-            .map_err(|()| ReplaceFallbackError::BadSampleRate));
-
-        let track = rewrite_comments(track.as_ref(), |comments| {
-            comments.vendor = "Ireul Core".to_string();
-            if let Some(ref metadata) = metadata {
-                comments.comments.clear();
-                comments.comments.extend(metadata.iter().cloned());
-            }
-        });
-
-        self.offline_track = queue::Track::from_ogg_track(Handle(0), track);
-
-        Ok(())
-    }
-
-    fn reconnect_icecast(&mut self) -> io::Result<()> {
-        self.connector = try!(IceCastWriter::with_options(
-            &self.icecast_url, &self.icecast_options));
-        Ok(())
-    }
-
-    // copy a page and tells us up to when we have no work to do
-    fn tick(&mut self) -> SteadyTime {
-        let page = self.get_next_page();
-
-        self.prev_ogg_granule_pos = page.position();
-        self.prev_ogg_serial = page.serial();
-        self.prev_ogg_sequence = page.sequence();
-
-        if let Err(err) = self.connector.send_ogg_page(&page) {
-            error!("Disconnected from icecast server: {:?}", err);
-
-            if let Err(err) = self.fast_forward_track_boundary() {
-                error!("Error fast forwarding: {:?}", err);
-            }
-
-            let mut wait = 5;
-            let mut attempt: u64 = 1;
-            loop {
-                if MAX_RECONNECT_WAIT < wait {
-                    wait = MAX_RECONNECT_WAIT;
-                }
-                if let Err(err) = self.reconnect_icecast() {
-                    error!("error reconnecting [attempt={}]: {}", attempt, err);
-                    wait += wait;
-                    attempt += 1;
-                    thread::sleep(Duration::from_millis(wait * 1000));
-                } else {
-                    break;
-                }
-            }
-            return SteadyTime::now();
-        }
-
-        if let Some(playing) = self.playing.as_mut() {
-            playing.sample_position = page.position();
-        }
-
-        debug!("copied page :: granule_pos = {:?}; serial = {:?}; sequence = {:?}; bos = {:?}; eos = {:?}",
-            page.position(),
-            page.serial(),
-            page.sequence(),
-            page.bos(),
-            page.eos());
-
-        let vhdr = page.raw_packets().nth(0)
-            .and_then(|packet| VorbisPacket::new(packet).ok())
-            .and_then(|vhdr| vhdr.identification_header());
-
-        if let Some(vhdr) = vhdr {
-            debug!("            :: {:?}", vhdr);
-        }
-
-        SteadyTime::now() + self.clock.wait_duration(&page)
-    }
-
-    fn history_cleanup(&mut self) {
-        let old_hist = std::mem::replace(&mut self.history, Vec::new());
-        let mut old_hist: VecDeque<_> = old_hist.into_iter().collect();
-        while 20 < old_hist.len() {
-            let old_item = old_hist.pop_front().unwrap();
-            if let Err(()) = self.play_queue.dispose(&old_item) {
-                error!("error disposing history: {:?}", old_item);
-            }
-            debug!("discard history: {:?}", old_item);
-        }
-        self.history.extend(old_hist.into_iter())
-    }
-
 }
 
-
-fn rewrite_comments<F>(track: &OggTrack, func: F) -> OggTrackBuf
-    where F: Fn(&mut VorbisComments) -> ()
-{
-    let mut track_rw: Vec<u8> = Vec::new();
-
-    for page in track.pages() {
-        // determine if we have a comment packet
-        let mut have_comment = false;
-        for packet in page.raw_packets() {
-            if let Ok(vpkt) = VorbisPacket::new(packet) {
-                if vpkt.comments().is_some() {
-                    have_comment = true;
-                }
-            }
-        }
-
-        // fast-path: no comment
-        if !have_comment {
-            track_rw.extend(page.as_u8_slice());
-            continue;
-        }
-
-        let mut builder = OggBuilder::new();
-        for packet in page.raw_packets() {
-            let mut emitted = false;
-            if let Ok(vpkt) = VorbisPacket::new(packet) {
-                if let Some(mut comments) = vpkt.comments() {
-                    func(&mut comments);
-
-                    let new_vpkt = VorbisPacketBuf::build_comment_packet(&comments);
-                    builder.add_packet(new_vpkt.as_u8_slice());
-                    emitted = true;
-                }
-            }
-            if !emitted {
-                builder.add_packet(packet);
-            }
-        }
-
-        let mut new_page = builder.build().unwrap();
-        {
-            let mut tx = new_page.as_mut().begin();
-            tx.set_position(page.position());
-            tx.set_serial(page.serial());
-            tx.set_sequence(page.sequence());
-            tx.set_continued(page.continued());
-            tx.set_bos(page.bos());
-            tx.set_eos(page.eos());
-        }
-
-        track_rw.extend(new_page.as_u8_slice());
+#[cfg(not(unix))]
+fn spawn_unix_control(config: &Config, _station: &Station) {
+    if config.unix_listen_path().is_some() {
+        panic!("unix_listen_path needs a Unix system");
     }
-
-    OggTrackBuf::new(track_rw).unwrap()
 }
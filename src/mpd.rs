@@ -0,0 +1,1097 @@
+//! Enough of the Music Player Daemon protocol for ordinary MPD clients to
+//! act as a remote for the station.
+//!
+//! The MPD playlist is what `queue_status` calls upcoming: the track on
+//! air, if it isn't the fallback, then the queue.  Tracks can be added
+//! from a media root on disk, deleted, moved about and skipped; there's no
+//! pausing or stopping a radio station.  If the station has users, clients
+//! give a user's token with `password` and may then do what its role
+//! allows.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use time::SteadyTime;
+
+use ogg::OggTrackBuf;
+
+use ireul_interface::proxy::track::model::{Handle, Queue, TrackInfo};
+use ireul_interface::proxy::{
+    CoreProxy,
+    ProxyError,
+    Event,
+    Subscription,
+    SubscribeRequest,
+    EnqueueTrackRequest,
+    EnqueueTrackError,
+    FastForward,
+    FastForwardRequest,
+    StatusRequest,
+    RemoveTrackRequest,
+    MoveTrackRequest,
+    RequestType,
+};
+
+use auth::{self, Role, User};
+
+const GREETING: &'static str = "OK MPD 0.19.0\n";
+
+// longer lines than this end the connection
+const LINE_LIMIT: usize = 4096;
+
+// how often an idle client is checked for `noidle`
+const IDLE_POLL_MILLIS: u64 = 100;
+
+// how far `add` goes into nested directories
+const ADD_DEPTH_LIMIT: usize = 32;
+
+const ACK_ERROR_NOT_LIST: u32 = 1;
+const ACK_ERROR_ARG: u32 = 2;
+const ACK_ERROR_PASSWORD: u32 = 3;
+const ACK_ERROR_PERMISSION: u32 = 4;
+const ACK_ERROR_UNKNOWN: u32 = 5;
+const ACK_ERROR_NO_EXIST: u32 = 50;
+const ACK_ERROR_PLAYLIST_MAX: u32 = 51;
+const ACK_ERROR_SYSTEM: u32 = 52;
+
+const COMMANDS: &'static [&'static str] = &[
+    "add", "addid", "close", "command_list_begin", "command_list_end",
+    "command_list_ok_begin", "commands", "currentsong", "decoders",
+    "delete", "deleteid", "idle", "lsinfo", "move", "moveid", "next",
+    "noidle", "notcommands", "outputs", "password", "ping", "playlistid",
+    "playlistinfo", "plchanges", "plchangesposid", "stats", "status",
+    "tagtypes", "urlhandlers",
+];
+
+const SUBSYSTEMS: &'static [&'static str] = &["player", "playlist", "output"];
+
+/// An error for the client, written as
+/// `ACK [code@command_list_index] {command} message`.
+#[derive(Debug)]
+struct Ack {
+    code: u32,
+    message: String,
+}
+
+impl Ack {
+    fn new(code: u32, message: &str) -> Ack {
+        Ack { code: code, message: message.to_string() }
+    }
+}
+
+fn proxy_error<E: ::std::fmt::Debug>(err: ProxyError<E>) -> Ack {
+    match err {
+        ProxyError::CallError(err) => Ack::new(ACK_ERROR_SYSTEM, &format!("{:?}", err)),
+        err => {
+            info!("core did not respond: {:?}", err);
+            Ack::new(ACK_ERROR_SYSTEM, "the station isn't responding")
+        },
+    }
+}
+
+struct Song {
+    id: u32,
+    // for tracks added over MPD
+    file: Option<String>,
+}
+
+/// MPD wants small song ids and a playlist version, which we keep for
+/// every client to share.
+struct Songs {
+    next_id: u32,
+    songs: HashMap<Handle, Song>,
+    version: u32,
+    last_order: Vec<Handle>,
+}
+
+impl Songs {
+    fn new() -> Songs {
+        Songs {
+            next_id: 1,
+            songs: HashMap::new(),
+            version: 1,
+            last_order: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, handle: Handle, file: Option<String>) -> u32 {
+        if let Some(song) = self.songs.get_mut(&handle) {
+            if file.is_some() {
+                song.file = file;
+            }
+            return song.id;
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.songs.insert(handle, Song { id: id, file: file });
+        id
+    }
+
+    /// Catches up with `queue`, forgetting tracks which have left it.
+    fn sync(&mut self, queue: &Queue) {
+        let order: Vec<Handle> = queue.upcoming.iter().map(|track| track.handle).collect();
+        if order != self.last_order {
+            self.version = self.version.wrapping_add(1);
+        }
+        for &handle in order.iter() {
+            self.add(handle, None);
+        }
+        let songs = mem::replace(&mut self.songs, HashMap::new());
+        self.songs = songs.into_iter()
+            .filter(|&(handle, _)| order.contains(&handle))
+            .collect();
+        self.last_order = order;
+    }
+}
+
+struct Shared {
+    proxy: CoreProxy,
+    users: Arc<Vec<User>>,
+    media_root: Option<PathBuf>,
+    songs: Mutex<Songs>,
+    started: SteadyTime,
+}
+
+/// `queue_status`, with MPD's view of it.
+struct Playlist {
+    queue: Queue,
+    version: u32,
+    ids: Vec<u32>,
+    files: Vec<Option<String>>,
+}
+
+impl Playlist {
+    fn len(&self) -> usize {
+        self.queue.upcoming.len()
+    }
+
+    // the first queued track's position
+    fn queue_offset(&self) -> usize {
+        if self.queue.playing.is_some() { 1 } else { 0 }
+    }
+
+    fn is_playing(&self, pos: usize) -> bool {
+        pos < self.queue_offset()
+    }
+
+    fn position_of(&self, id: u32) -> Option<usize> {
+        self.ids.iter().position(|&other| other == id)
+    }
+
+    fn write_song(&self, pos: usize, out: &mut String) {
+        let track = &self.queue.upcoming[pos];
+        let file = self.files[pos].clone()
+            .unwrap_or_else(|| format!("ireul:{:016x}", track.handle.0));
+        write_song(out, &file, track);
+        let _ = write!(out, "Pos: {}\nId: {}\n", pos, self.ids[pos]);
+    }
+}
+
+fn seconds(samples: u64, sample_rate: u64) -> f64 {
+    if sample_rate == 0 {
+        return 0.0;
+    }
+    samples as f64 / sample_rate as f64
+}
+
+fn write_song(out: &mut String, file: &str, track: &TrackInfo) {
+    let duration = seconds(track.sample_count, track.sample_rate);
+    let _ = write!(out, "file: {}\n", file);
+    for &(tag, value) in [("Artist", &track.artist), ("Album", &track.album), ("Title", &track.title)].iter() {
+        if !value.is_empty() {
+            let _ = write!(out, "{}: {}\n", tag, value);
+        }
+    }
+    let _ = write!(out, "Time: {}\nduration: {:.3}\n", duration.round() as u64, duration);
+}
+
+/// Splits a command line into its words, which may be double-quoted with
+/// backslash escapes.
+fn parse_args(line: &str) -> Result<Vec<String>, Ack> {
+    let bad = || Ack::new(ACK_ERROR_ARG, "malformed command line");
+
+    let mut args = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    loop {
+        while chars.peek() == Some(&' ') || chars.peek() == Some(&'\t') {
+            chars.next();
+        }
+        let first = match chars.next() {
+            Some(first) => first,
+            None => return Ok(args),
+        };
+
+        let mut arg = String::new();
+        if first == '"' {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => arg.push(try!(chars.next().ok_or_else(&bad))),
+                    Some(c) => arg.push(c),
+                    None => return Err(bad()),
+                }
+            }
+            match chars.peek() {
+                None | Some(&' ') | Some(&'\t') => (),
+                Some(_) => return Err(bad()),
+            }
+        } else {
+            arg.push(first);
+            while let Some(&c) = chars.peek() {
+                if c == ' ' || c == '\t' {
+                    break;
+                }
+                arg.push(c);
+                chars.next();
+            }
+        }
+        args.push(arg);
+    }
+}
+
+fn parse_number(arg: &str) -> Result<usize, Ack> {
+    arg.parse().map_err(|_| Ack::new(ACK_ERROR_ARG, &format!("need a number, not {:?}", arg)))
+}
+
+/// `POS` or `START:END`, with END optional, as positions in a playlist of
+/// `len` songs.
+fn parse_range(arg: &str, len: usize) -> Result<(usize, usize), Ack> {
+    let (start, end) = match arg.find(':') {
+        Some(colon) => {
+            let start = try!(parse_number(&arg[..colon]));
+            let end = if colon + 1 == arg.len() { len } else { try!(parse_number(&arg[colon + 1..])) };
+            (start, end)
+        },
+        None => {
+            let pos = try!(parse_number(arg));
+            let end = try!(pos.checked_add(1)
+                .ok_or_else(|| Ack::new(ACK_ERROR_ARG, "Bad song index")));
+            (pos, end)
+        },
+    };
+    if end < start || len < end || start == len {
+        return Err(Ack::new(ACK_ERROR_ARG, "Bad song index"));
+    }
+    Ok((start, end))
+}
+
+fn arg<'a>(args: &'a [String], index: usize) -> Result<&'a str, Ack> {
+    args.get(index)
+        .map(|arg| &arg[..])
+        .ok_or_else(|| Ack::new(ACK_ERROR_ARG, "missing argument"))
+}
+
+fn is_track_file(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => {
+            let ext = ext.to_lowercase();
+            ext == "ogg" || ext == "oga"
+        },
+        None => false,
+    }
+}
+
+// directories first, then files, each sorted by name
+fn list_dir(dir: &Path) -> io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for entry in try!(fs::read_dir(dir)) {
+        let path = try!(entry).path();
+        if path.is_dir() {
+            dirs.push(path);
+        } else if is_track_file(&path) {
+            files.push(path);
+        }
+    }
+    dirs.sort();
+    files.sort();
+    Ok((dirs, files))
+}
+
+/// What a command asks of the station, for checking against a role.
+fn request_type(command: &str) -> Option<RequestType> {
+    match command {
+        "status" | "currentsong" | "playlistinfo" | "playlistid" |
+        "plchanges" | "plchangesposid" => Some(RequestType::QueueStatus),
+        // browsing is for adding
+        "add" | "addid" | "lsinfo" => Some(RequestType::EnqueueTrack),
+        "delete" | "deleteid" => Some(RequestType::RemoveTrack),
+        "move" | "moveid" => Some(RequestType::MoveTrack),
+        "next" => Some(RequestType::FastForward),
+        "idle" => Some(RequestType::Subscribe),
+        _ => None,
+    }
+}
+
+struct Session {
+    shared: Arc<Shared>,
+    // who the connection is in the logs
+    who: String,
+    peer: String,
+    // None until a password is given, if there are users
+    role: Option<Role>,
+    // taken on the first idle
+    events: Option<Subscription>,
+    changed: Vec<&'static str>,
+}
+
+impl Session {
+    fn playlist(&self) -> Result<Playlist, Ack> {
+        let queue = try!(self.shared.proxy.queue_status(StatusRequest).map_err(proxy_error));
+        let mut songs = self.shared.songs.lock().unwrap();
+        songs.sync(&queue);
+
+        let ids = queue.upcoming.iter().map(|track| songs.songs[&track.handle].id).collect();
+        let files = queue.upcoming.iter().map(|track| songs.songs[&track.handle].file.clone()).collect();
+        Ok(Playlist {
+            queue: queue,
+            version: songs.version,
+            ids: ids,
+            files: files,
+        })
+    }
+
+    /// Where `uri` is under the media root, so long as it stays there.
+    fn resolve(&self, uri: &str) -> Result<PathBuf, Ack> {
+        let root = try!(self.shared.media_root.as_ref()
+            .ok_or_else(|| Ack::new(ACK_ERROR_NO_EXIST, "no media root is configured")));
+        let uri = Path::new(uri);
+        for component in uri.components() {
+            match component {
+                Component::Normal(_) | Component::CurDir => (),
+                _ => return Err(Ack::new(ACK_ERROR_ARG, "paths are relative to the media root")),
+            }
+        }
+        Ok(root.join(uri))
+    }
+
+    fn enqueue_file(&self, path: &Path, uri: &str) -> Result<u32, Ack> {
+        let mut buffer = Vec::new();
+        try!(File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut buffer))
+            .map_err(|err| Ack::new(ACK_ERROR_SYSTEM, &format!("error reading {}: {}", uri, err))));
+        let track = try!(OggTrackBuf::new(buffer)
+            .map_err(|_| Ack::new(ACK_ERROR_ARG, &format!("{} isn't an Ogg file", uri))));
+
+        let handle = try!(self.shared.proxy.enqueue_track(EnqueueTrackRequest {
+            track: track,
+            metadata: None,
+        }).map_err(|err| match err {
+            ProxyError::CallError(EnqueueTrackError::Full) => {
+                Ack::new(ACK_ERROR_PLAYLIST_MAX, "the queue is full")
+            },
+            ProxyError::CallError(err) => {
+                Ack::new(ACK_ERROR_ARG, &format!("{} can't be played: {:?}", uri, err))
+            },
+            err => proxy_error(err),
+        }));
+        Ok(self.shared.songs.lock().unwrap().add(handle, Some(uri.to_string())))
+    }
+
+    // files are added in order, directories recursively.
+    fn add(&self, uri: &str) -> Result<Vec<u32>, Ack> {
+        let path = try!(self.resolve(uri));
+        if path.is_file() {
+            return Ok(vec![try!(self.enqueue_file(&path, uri))]);
+        }
+
+        let mut ids = Vec::new();
+        try!(self.add_dir(&path, 0, &mut HashSet::new(), &mut ids));
+        Ok(ids)
+    }
+
+    // A directory reached again through a symlink is skipped, so a loop
+    // can't have us going round forever.
+    fn add_dir(&self, dir: &Path, depth: usize, visited: &mut HashSet<PathBuf>, ids: &mut Vec<u32>) -> Result<(), Ack> {
+        if ADD_DEPTH_LIMIT < depth {
+            return Err(Ack::new(ACK_ERROR_ARG, "directories are nested too deeply"));
+        }
+        let canonical = try!(fs::canonicalize(dir)
+            .map_err(|_| Ack::new(ACK_ERROR_NO_EXIST, "No such directory")));
+        if !visited.insert(canonical) {
+            return Ok(());
+        }
+
+        let (dirs, files) = try!(list_dir(dir)
+            .map_err(|_| Ack::new(ACK_ERROR_NO_EXIST, "No such directory")));
+        for file in files.iter() {
+            let uri = self.uri_of(file);
+            ids.push(try!(self.enqueue_file(file, &uri)));
+        }
+        for dir in dirs.iter() {
+            try!(self.add_dir(dir, depth + 1, visited, ids));
+        }
+        Ok(())
+    }
+
+    fn uri_of(&self, path: &Path) -> String {
+        let root = self.shared.media_root.as_ref().unwrap();
+        path.strip_prefix(root).unwrap_or(path).to_string_lossy().into_owned()
+    }
+
+    fn skip(&self) -> Result<(), Ack> {
+        self.shared.proxy.fast_forward(FastForwardRequest {
+            kind: FastForward::TrackBoundary,
+        }).map_err(proxy_error)
+    }
+
+    fn delete(&self, playlist: &Playlist, start: usize, end: usize) -> Result<(), Ack> {
+        // skipping last, so the queue can't move on to a track we're
+        // about to remove.
+        let mut skip = false;
+        for pos in start..end {
+            if playlist.is_playing(pos) {
+                skip = true;
+            } else {
+                try!(self.shared.proxy.remove_track(RemoveTrackRequest {
+                    handle: playlist.queue.upcoming[pos].handle,
+                }).map_err(proxy_error));
+            }
+        }
+        if skip {
+            try!(self.skip());
+        }
+        Ok(())
+    }
+
+    fn move_to(&self, playlist: &Playlist, from: usize, to: usize) -> Result<(), Ack> {
+        if playlist.is_playing(from) {
+            return Err(Ack::new(ACK_ERROR_ARG, "the track on air can't be moved"));
+        }
+        if playlist.len() <= to {
+            return Err(Ack::new(ACK_ERROR_ARG, "Bad song index"));
+        }
+        let position = to.saturating_sub(playlist.queue_offset());
+        self.shared.proxy.move_track(MoveTrackRequest {
+            handle: playlist.queue.upcoming[from].handle,
+            position: position as u32,
+        }).map_err(proxy_error)
+    }
+
+    fn status(&self, out: &mut String) -> Result<(), Ack> {
+        let playlist = try!(self.playlist());
+        let _ = write!(out, "volume: -1\nrepeat: 0\nrandom: 0\nsingle: 0\nconsume: 1\n");
+        let _ = write!(out, "playlist: {}\nplaylistlength: {}\n", playlist.version, playlist.len());
+
+        match playlist.queue.playing {
+            Some(ref track) => {
+                let elapsed = seconds(track.sample_position, track.sample_rate);
+                let duration = seconds(track.sample_count, track.sample_rate);
+                let _ = write!(out, "state: play\nsong: 0\nsongid: {}\n", playlist.ids[0]);
+                let _ = write!(out, "time: {}:{}\nelapsed: {:.3}\nduration: {:.3}\n",
+                    elapsed as u64, duration.round() as u64, elapsed, duration);
+            },
+            None => {
+                let _ = write!(out, "state: stop\n");
+            },
+        }
+
+        let next = playlist.queue_offset();
+        if next < playlist.len() {
+            let _ = write!(out, "nextsong: {}\nnextsongid: {}\n", next, playlist.ids[next]);
+        }
+        Ok(())
+    }
+
+    fn lsinfo(&self, uri: &str, out: &mut String) -> Result<(), Ack> {
+        let path = try!(self.resolve(uri));
+        if path.is_file() {
+            let _ = write!(out, "file: {}\n", uri);
+            return Ok(());
+        }
+
+        let (dirs, files) = try!(list_dir(&path)
+            .map_err(|_| Ack::new(ACK_ERROR_NO_EXIST, "No such directory")));
+        for dir in dirs.iter() {
+            let _ = write!(out, "directory: {}\n", self.uri_of(dir));
+        }
+        for file in files.iter() {
+            let _ = write!(out, "file: {}\n", self.uri_of(file));
+        }
+        Ok(())
+    }
+
+    fn login(&mut self, token: &str) -> Result<(), Ack> {
+        match auth::authenticate_token(&self.shared.users, token) {
+            Some(user) => {
+                self.who = format!("{}@{}", user.name(), self.peer);
+                self.role = Some(user.role());
+                info!("{} logged in over MPD", self.who);
+                Ok(())
+            },
+            None => {
+                info!("{} gave a wrong MPD password", self.who);
+                Err(Ack::new(ACK_ERROR_PASSWORD, "incorrect password"))
+            },
+        }
+    }
+
+    /// Refuses `args` unless the session's role allows it, and logs the
+    /// commands which change anything.
+    fn authorize(&self, args: &[String]) -> Result<(), Ack> {
+        let mut required = request_type(&args[0]).into_iter().collect::<Vec<_>>();
+        if args[0] == "addid" && args.len() > 2 {
+            required.push(RequestType::MoveTrack);
+        }
+        if !self.shared.users.is_empty() {
+            let allowed = |req_type: &RequestType| self.role.map(|role| role.allows(*req_type)).unwrap_or(false);
+            if !required.iter().all(allowed) {
+                info!("{} may not send {:?}", self.who, args[0]);
+                return Err(Ack::new(ACK_ERROR_PERMISSION,
+                    &format!("you don't have permission for \"{}\"", args[0])));
+            }
+        }
+        match required.first() {
+            Some(&RequestType::QueueStatus) | Some(&RequestType::Subscribe) | None => (),
+            Some(_) => info!("{}: MPD {}", self.who, args.join(" ")),
+        }
+        Ok(())
+    }
+
+    /// Runs everything but the commands which affect the connection.
+    fn run(&mut self, args: &[String], out: &mut String) -> Result<(), Ack> {
+        try!(self.authorize(args));
+        match &args[0][..] {
+            "ping" => Ok(()),
+            "password" => self.login(try!(arg(args, 1))),
+            "status" => self.status(out),
+            "currentsong" => {
+                let playlist = try!(self.playlist());
+                if playlist.queue.playing.is_some() {
+                    playlist.write_song(0, out);
+                }
+                Ok(())
+            },
+            "playlistinfo" => {
+                let playlist = try!(self.playlist());
+                let (start, end) = match args.get(1) {
+                    Some(range) => try!(parse_range(range, playlist.len())),
+                    None => (0, playlist.len()),
+                };
+                for pos in start..end {
+                    playlist.write_song(pos, out);
+                }
+                Ok(())
+            },
+            "playlistid" => {
+                let playlist = try!(self.playlist());
+                match args.get(1) {
+                    Some(id) => {
+                        let id = try!(parse_number(id)) as u32;
+                        let pos = try!(playlist.position_of(id)
+                            .ok_or_else(|| Ack::new(ACK_ERROR_NO_EXIST, "No such song")));
+                        playlist.write_song(pos, out);
+                    },
+                    None => {
+                        for pos in 0..playlist.len() {
+                            playlist.write_song(pos, out);
+                        }
+                    },
+                }
+                Ok(())
+            },
+            // we don't keep old versions, so everything has changed.
+            "plchanges" => {
+                let playlist = try!(self.playlist());
+                for pos in 0..playlist.len() {
+                    playlist.write_song(pos, out);
+                }
+                Ok(())
+            },
+            "plchangesposid" => {
+                let playlist = try!(self.playlist());
+                for (pos, id) in playlist.ids.iter().enumerate() {
+                    let _ = write!(out, "cpos: {}\nId: {}\n", pos, id);
+                }
+                Ok(())
+            },
+            "next" => self.skip(),
+            "add" => {
+                try!(self.add(try!(arg(args, 1))));
+                Ok(())
+            },
+            "addid" => {
+                let uri = try!(arg(args, 1));
+                if self.resolve(uri).map(|path| !path.is_file()).unwrap_or(false) {
+                    return Err(Ack::new(ACK_ERROR_ARG, "addid takes a file, not a directory"));
+                }
+                let id = try!(self.add(uri))[0];
+                if let Some(to) = args.get(2) {
+                    let to = try!(parse_number(to));
+                    let playlist = try!(self.playlist());
+                    let from = try!(playlist.position_of(id)
+                        .ok_or_else(|| Ack::new(ACK_ERROR_NO_EXIST, "No such song")));
+                    try!(self.move_to(&playlist, from, to));
+                }
+                let _ = write!(out, "Id: {}\n", id);
+                Ok(())
+            },
+            "delete" => {
+                let playlist = try!(self.playlist());
+                let (start, end) = try!(parse_range(try!(arg(args, 1)), playlist.len()));
+                self.delete(&playlist, start, end)
+            },
+            "deleteid" => {
+                let id = try!(parse_number(try!(arg(args, 1)))) as u32;
+                let playlist = try!(self.playlist());
+                let pos = try!(playlist.position_of(id)
+                    .ok_or_else(|| Ack::new(ACK_ERROR_NO_EXIST, "No such song")));
+                self.delete(&playlist, pos, pos + 1)
+            },
+            "move" => {
+                let playlist = try!(self.playlist());
+                let from = try!(parse_number(try!(arg(args, 1))));
+                if playlist.len() <= from {
+                    return Err(Ack::new(ACK_ERROR_ARG, "Bad song index"));
+                }
+                let to = try!(parse_number(try!(arg(args, 2))));
+                self.move_to(&playlist, from, to)
+            },
+            "moveid" => {
+                let id = try!(parse_number(try!(arg(args, 1)))) as u32;
+                let to = try!(parse_number(try!(arg(args, 2))));
+                let playlist = try!(self.playlist());
+                let from = try!(playlist.position_of(id)
+                    .ok_or_else(|| Ack::new(ACK_ERROR_NO_EXIST, "No such song")));
+                self.move_to(&playlist, from, to)
+            },
+            "lsinfo" => self.lsinfo(args.get(1).map(|uri| &uri[..]).unwrap_or(""), out),
+            "stats" => {
+                let uptime = (SteadyTime::now() - self.shared.started).num_seconds();
+                let _ = write!(out, "uptime: {}\nplaytime: {}\n", uptime, uptime);
+                Ok(())
+            },
+            "outputs" => {
+                let _ = write!(out, "outputid: 0\noutputname: ireul\noutputenabled: 1\n");
+                Ok(())
+            },
+            "tagtypes" => {
+                let _ = write!(out, "tagtype: Artist\ntagtype: Album\ntagtype: Title\n");
+                Ok(())
+            },
+            "commands" => {
+                for command in COMMANDS.iter() {
+                    let _ = write!(out, "command: {}\n", command);
+                }
+                Ok(())
+            },
+            "notcommands" | "urlhandlers" | "decoders" => Ok(()),
+            "command_list_end" => Err(Ack::new(ACK_ERROR_NOT_LIST, "not in command list mode")),
+            _ => Err(Ack::new(ACK_ERROR_UNKNOWN, &format!("unknown command \"{}\"", args[0]))),
+        }
+    }
+
+    /// Runs one command, or a command list, writing what it has to say.
+    fn run_list(&mut self, commands: &[Vec<String>], list_ok: bool, out: &mut String) {
+        for (index, args) in commands.iter().enumerate() {
+            if let Err(ack) = self.run(args, out) {
+                let _ = write!(out, "ACK [{}@{}] {{{}}} {}\n", ack.code, index, args[0], ack.message);
+                return;
+            }
+            if list_ok {
+                out.push_str("list_OK\n");
+            }
+        }
+        out.push_str("OK\n");
+    }
+
+    fn take_events(&mut self) {
+        let mut lost = false;
+        if let Some(ref events) = self.events {
+            loop {
+                let subsystem = match events.recv_timeout(Duration::from_millis(0)) {
+                    Ok(Some(Event::QueueChanged)) => "playlist",
+                    Ok(Some(Event::OutputDisconnected)) | Ok(Some(Event::OutputReconnected)) => "output",
+                    Ok(Some(_)) => "player",
+                    Ok(None) => break,
+                    Err(_) => {
+                        lost = true;
+                        break;
+                    },
+                };
+                if !self.changed.contains(&subsystem) {
+                    self.changed.push(subsystem);
+                }
+            }
+        }
+
+        if lost || self.events.is_none() {
+            // we can't tell what happened while we weren't listening.
+            if lost {
+                self.changed = SUBSYSTEMS.to_vec();
+            }
+            self.events = self.shared.proxy.subscribe(SubscribeRequest).ok();
+        }
+    }
+
+    /// Waits for one of `subsystems` (or any, if empty) to change, or for
+    /// the client to send `noidle`.  Gives false if the client went away.
+    fn idle<W: Write>(&mut self, subsystems: &[String], lines: &mpsc::Receiver<String>, out: &mut W) -> io::Result<bool> {
+        loop {
+            self.take_events();
+
+            let wanted = |subsystem: &&str| subsystems.is_empty() || subsystems.iter().any(|s| s == subsystem);
+            let changed: Vec<&'static str> = self.changed.iter().cloned().filter(&wanted).collect();
+            if !changed.is_empty() {
+                self.changed.retain(|subsystem| !wanted(subsystem));
+                let mut response = String::new();
+                for subsystem in changed.iter() {
+                    let _ = write!(response, "changed: {}\n", subsystem);
+                }
+                response.push_str("OK\n");
+                try!(out.write_all(response.as_bytes()));
+                return Ok(true);
+            }
+
+            match lines.recv_timeout(Duration::from_millis(IDLE_POLL_MILLIS)) {
+                Ok(ref line) if line.trim() == "noidle" => {
+                    try!(out.write_all(b"OK\n"));
+                    return Ok(true);
+                },
+                Ok(line) => {
+                    // MPD hangs up on anything else during idle
+                    info!("MPD client sent {:?} while idle", line);
+                    return Ok(false);
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(false),
+            }
+        }
+    }
+}
+
+// Lines are read on their own thread, so idle can wait on the client and
+// the station at once.
+fn read_lines(stream: TcpStream, lines: mpsc::Sender<String>) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = Vec::new();
+        match Read::by_ref(&mut reader).take(LINE_LIMIT as u64).read_until(b'\n', &mut line) {
+            Ok(_) if line.ends_with(b"\n") => (),
+            _ => return,
+        }
+        let line = match String::from_utf8(line) {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        if lines.send(line.trim_right().to_string()).is_err() {
+            return;
+        }
+    }
+}
+
+fn client_worker(mut stream: TcpStream, shared: Arc<Shared>) -> io::Result<()> {
+    let (lines_tx, lines) = mpsc::channel();
+    let reader = try!(stream.try_clone());
+    thread::spawn(move || read_lines(reader, lines_tx));
+
+    let peer = stream.peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or("unknown".to_string());
+    let mut session = Session {
+        shared: shared,
+        who: format!("anonymous@{}", peer),
+        peer: peer,
+        role: None,
+        events: None,
+        changed: Vec::new(),
+    };
+
+    try!(stream.write_all(GREETING.as_bytes()));
+    while let Ok(line) = lines.recv() {
+        let args = match parse_args(&line) {
+            Ok(args) => args,
+            Err(ack) => {
+                try!(write!(stream, "ACK [{}@0] {{}} {}\n", ack.code, ack.message));
+                continue;
+            },
+        };
+        if args.is_empty() {
+            continue;
+        }
+
+        let mut out = String::new();
+        match &args[0][..] {
+            "close" => return Ok(()),
+            "idle" => {
+                if let Err(ack) = session.authorize(&args) {
+                    try!(write!(stream, "ACK [{}@0] {{idle}} {}\n", ack.code, ack.message));
+                    continue;
+                }
+                if !try!(session.idle(&args[1..], &lines, &mut stream)) {
+                    return Ok(());
+                }
+                continue;
+            },
+            // without an idle to end, there's nothing to say
+            "noidle" => continue,
+            "command_list_begin" | "command_list_ok_begin" => {
+                let list_ok = args[0] == "command_list_ok_begin";
+                let mut commands = Vec::new();
+                loop {
+                    let line = match lines.recv() {
+                        Ok(line) => line,
+                        Err(_) => return Ok(()),
+                    };
+                    if line == "command_list_end" {
+                        break;
+                    }
+                    match parse_args(&line) {
+                        Ok(ref args) if args.is_empty() => (),
+                        Ok(args) => commands.push(args),
+                        Err(ack) => {
+                            try!(write!(stream, "ACK [{}@{}] {{}} {}\n", ack.code, commands.len(), ack.message));
+                            return Ok(());
+                        },
+                    }
+                }
+                session.run_list(&commands, list_ok, &mut out);
+            },
+            _ => session.run_list(&[args], false, &mut out),
+        }
+        try!(stream.write_all(out.as_bytes()));
+    }
+    Ok(())
+}
+
+/// Serves MPD clients connecting to `server`, adding tracks from
+/// `media_root`.  If there are `users`, clients must give one's token as
+/// their password to do what its role allows.
+pub fn client_acceptor(server: TcpListener, proxy: CoreProxy, users: Arc<Vec<User>>, media_root: Option<PathBuf>) {
+    let shared = Arc::new(Shared {
+        proxy: proxy,
+        users: users,
+        media_root: media_root,
+        songs: Mutex::new(Songs::new()),
+        started: SteadyTime::now(),
+    });
+
+    for stream in server.incoming() {
+        match stream {
+            Ok(stream) => {
+                let client_shared = shared.clone();
+                thread::spawn(move || {
+                    if let Err(err) = client_worker(stream, client_shared) {
+                        info!("MPD client disconnected with error: {:?}", err);
+                    }
+                });
+            },
+            Err(err) => {
+                info!("error accepting new MPD client: {:?}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::Duration;
+
+    use auth::{Role, User};
+    use output::NullSink;
+    use station::StationBuilder;
+    use super::{parse_args, parse_range};
+
+    const DEAD_AIR: &'static [u8] = include_bytes!("deadair.ogg");
+
+    struct Client {
+        stream: TcpStream,
+        reader: BufReader<TcpStream>,
+    }
+
+    impl Client {
+        fn connect(media_root: Option<PathBuf>) -> Client {
+            Client::connect_with(media_root, &[])
+        }
+
+        fn connect_with(media_root: Option<PathBuf>, users: &[User]) -> Client {
+            let mut builder = StationBuilder::new();
+            builder.set_output(Box::new(NullSink));
+            for user in users.iter() {
+                builder.add_user(user.clone());
+            }
+            let station = builder.start().unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            thread::spawn(move || station.serve_mpd(listener, media_root));
+
+            let stream = TcpStream::connect(addr).unwrap();
+            let mut client = Client {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                stream: stream,
+            };
+            assert!(client.read_line().starts_with("OK MPD "));
+            client
+        }
+
+        fn read_line(&mut self) -> String {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            line.trim_right().to_string()
+        }
+
+        // sends `command`, returning the lines of the response up to and
+        // including its OK or ACK
+        fn command(&mut self, command: &str) -> Vec<String> {
+            write!(self.stream, "{}\n", command).unwrap();
+            let mut lines = Vec::new();
+            loop {
+                let line = self.read_line();
+                let done = line == "OK" || line.starts_with("ACK ");
+                lines.push(line);
+                if done {
+                    return lines;
+                }
+            }
+        }
+    }
+
+    fn field<'a>(lines: &'a [String], name: &str) -> Vec<&'a str> {
+        let prefix = format!("{}: ", name);
+        lines.iter()
+            .filter(|line| line.starts_with(&prefix))
+            .map(|line| &line[prefix.len()..])
+            .collect()
+    }
+
+    fn media_root(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("ireul-mpd-test-{}", name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("album")).unwrap();
+        for name in &["album/1.ogg", "album/2.ogg", "album/3.ogg"] {
+            File::create(root.join(name)).unwrap().write_all(DEAD_AIR).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_args("add \"my \\\"album\\\"/1.ogg\"").ok().unwrap(),
+            vec!["add".to_string(), "my \"album\"/1.ogg".to_string()]);
+        assert_eq!(parse_args("  move 1   2 ").ok().unwrap(),
+            vec!["move".to_string(), "1".to_string(), "2".to_string()]);
+        assert!(parse_args("add \"unterminated").is_err());
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("2", 5).ok(), Some((2, 3)));
+        assert_eq!(parse_range("1:3", 5).ok(), Some((1, 3)));
+        assert_eq!(parse_range("1:", 5).ok(), Some((1, 5)));
+        assert!(parse_range("5", 5).is_err());
+        assert!(parse_range("3:1", 5).is_err());
+        assert!(parse_range(&::std::usize::MAX.to_string(), 5).is_err());
+    }
+
+    #[test]
+    fn test_queue_editing() {
+        let mut client = Client::connect(Some(media_root("editing")));
+
+        assert_eq!(client.command("add album").last().unwrap(), "OK");
+        // the first goes on air once the fallback has been cut off
+        while field(&client.command("status"), "state") != vec!["play"] {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let playlist = client.command("playlistinfo");
+        let files = field(&playlist, "file");
+        assert_eq!(files, vec!["album/1.ogg", "album/2.ogg", "album/3.ogg"]);
+        let ids: Vec<String> = field(&playlist, "Id").iter().map(|id| id.to_string()).collect();
+
+        let status = client.command("status");
+        assert_eq!(field(&status, "songid"), vec![&ids[0][..]]);
+        assert_eq!(field(&client.command("currentsong"), "Id"), vec![&ids[0][..]]);
+
+        assert_eq!(client.command(&format!("moveid {} 1", ids[2])).last().unwrap(), "OK");
+        assert_eq!(field(&client.command("playlistinfo"), "Id"), vec![&ids[0][..], &ids[2], &ids[1]]);
+        assert!(client.command("move 0 2").last().unwrap().starts_with("ACK [2@0] {move}"));
+
+        assert_eq!(client.command("delete 1").last().unwrap(), "OK");
+        assert_eq!(field(&client.command("playlistinfo"), "Id"), vec![&ids[0][..], &ids[1]]);
+        assert!(client.command(&format!("deleteid {}", ids[2])).last().unwrap().starts_with("ACK [50@0]"));
+    }
+
+    #[test]
+    fn test_command_list() {
+        let mut client = Client::connect(Some(media_root("list")));
+
+        write!(client.stream, "command_list_ok_begin\nping\nadd album/1.ogg\nbogus\nping\ncommand_list_end\n").unwrap();
+        assert_eq!(client.read_line(), "list_OK");
+        assert_eq!(client.read_line(), "list_OK");
+        assert_eq!(client.read_line(), "ACK [5@2] {bogus} unknown command \"bogus\"");
+
+        assert!(client.command("add ../etc").last().unwrap().starts_with("ACK [2@0] {add}"));
+        assert!(client.command("add nowhere").last().unwrap().starts_with("ACK [50@0] {add}"));
+    }
+
+    #[test]
+    fn test_idle() {
+        let mut client = Client::connect(Some(media_root("idle")));
+
+        // subscribes, with nothing to report yet
+        write!(client.stream, "idle playlist\nnoidle\n").unwrap();
+        assert_eq!(client.read_line(), "OK");
+
+        assert_eq!(client.command("add album/1.ogg").last().unwrap(), "OK");
+        assert_eq!(client.command("idle playlist"), vec!["changed: playlist", "OK"]);
+
+        assert_eq!(client.command("next").last().unwrap(), "OK");
+        assert_eq!(client.command("idle player"), vec!["changed: player", "OK"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_loop() {
+        use std::os::unix::fs::symlink;
+
+        let root = media_root("loop");
+        symlink(&root, root.join("album/back")).unwrap();
+        let mut client = Client::connect(Some(root));
+
+        assert_eq!(client.command("add album").last().unwrap(), "OK");
+        assert_eq!(field(&client.command("playlistinfo"), "file").len(), 3);
+    }
+
+    #[test]
+    fn test_no_media_root() {
+        let mut client = Client::connect(None);
+        assert!(client.command("add x.ogg").last().unwrap().starts_with("ACK [50@0] {add}"));
+        assert_eq!(field(&client.command("status"), "state"), vec!["stop"]);
+    }
+
+    #[test]
+    fn test_password() {
+        let mut client = Client::connect_with(Some(media_root("password")), &[
+            User::new("ann", "secret", Role::Dj),
+            User::new("bob", "hunter2", Role::Requester),
+        ]);
+        assert_eq!(client.command("ping"), vec!["OK"]);
+        assert!(client.command("status").last().unwrap().starts_with("ACK [4@0] {status}"));
+        assert!(client.command("idle").last().unwrap().starts_with("ACK [4@0] {idle}"));
+        assert!(client.command("password hunter").last().unwrap().starts_with("ACK [3@0] {password}"));
+
+        // a requester may add tracks, but not skip them
+        assert_eq!(client.command("password hunter2"), vec!["OK"]);
+        assert_eq!(client.command("status").last().unwrap(), "OK");
+        assert_eq!(client.command("add album/1.ogg"), vec!["OK"]);
+        assert!(client.command("next").last().unwrap().starts_with("ACK [4@0] {next}"));
+        assert!(client.command("addid album/2.ogg 0").last().unwrap().starts_with("ACK [4@0] {addid}"));
+
+        assert_eq!(client.command("password secret"), vec!["OK"]);
+        assert_eq!(client.command("next"), vec!["OK"]);
+    }
+}
@@ -3,7 +3,7 @@ use std::collections::{VecDeque, HashMap, HashSet};
 
 use rand::{self, Rng, ChaChaRng};
 
-use ogg::{OggTrack, OggTrackBuf};
+use ogg::IndexedOggTrack;
 use ogg::vorbis::{Comments, VorbisPacket};
 use ireul_interface::proxy::track::model::{self, Handle};
 
@@ -27,7 +27,11 @@ impl<R> HandleAllocator<R> where R: Rng {
         if self.limit <= self.allocated.len() {
             return Err(());
         }
+        Ok(self.generate_unbounded())
+    }
 
+    /// Allocates a handle even if we're over the limit.
+    pub fn generate_unbounded(&mut self) -> Handle {
         let new_handle;
         loop {
             let foo = self.rng.next_u64();
@@ -37,9 +41,13 @@ impl<R> HandleAllocator<R> where R: Rng {
             }
         }
 
-        println!("EMITTING HANDLE {:#x}", new_handle);
+        debug!("allocated handle {:#x}", new_handle);
         self.allocated.insert(new_handle);
-        Ok(Handle(new_handle))
+        Handle(new_handle)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
     }
 
     pub fn dispose(&mut self, handle: Handle) -> Result<(), ()> {
@@ -54,7 +62,7 @@ impl<R> HandleAllocator<R> where R: Rng {
 pub struct Track {
     handle: Handle,
 
-    data: OggTrackBuf,
+    data: IndexedOggTrack,
     comments: Comments,
 
     artist: String,
@@ -63,10 +71,14 @@ pub struct Track {
 
     sample_rate: u64,
     sample_count: u64,
+
+    // Set on interrupted tracks, which pick up where they left off.
+    started_at: Option<i64>,
+    resume_position: Option<u64>,
 }
 
 impl Track {
-    pub fn from_ogg_track(handle: Handle, ogg: OggTrackBuf) -> Track {
+    pub fn from_ogg_track(handle: Handle, ogg: IndexedOggTrack) -> Track {
         use std::ascii::AsciiExt;
 
         let id_header = match VorbisPacket::find_identification(ogg.pages()) {
@@ -115,17 +127,32 @@ impl Track {
 
             sample_rate: id_header.audio_sample_rate as u64,
             sample_count: sample_count,
+
+            started_at: None,
+            resume_position: None,
         }
     }
 
-    pub fn into_inner(self) -> OggTrackBuf {
+    /// Makes the track continue from just after `position` when it's next
+    /// played, rather than from the beginning.
+    pub fn resume_from(mut self, started_at: Option<i64>, position: u64) -> Track {
+        self.started_at = started_at;
+        self.resume_position = Some(position);
+        self
+    }
+
+    pub fn resume_position(&self) -> Option<u64> {
+        self.resume_position
+    }
+
+    pub fn into_inner(self) -> IndexedOggTrack {
         self.data
     }
 
     pub fn get_track_info(&self) -> model::TrackInfo {
         model::TrackInfo {
             handle: self.handle,
-            started_at: None,
+            started_at: self.started_at,
 
             artist: self.artist.clone(),
             album: self.album.clone(),
@@ -133,7 +160,7 @@ impl Track {
 
             sample_rate: self.sample_rate,
             sample_count: self.sample_count,
-            sample_position: 0,
+            sample_position: self.resume_position.unwrap_or(0),
             metadata: self.comments.comments.clone(),
         }
     }
@@ -153,7 +180,8 @@ impl PlayQueue {
         }
     }
 
-    #[allow(dead_code)] // queue manip stub
+    /// Puts the tracks in the order of `handle_ord`.  Tracks left out of
+    /// it are dropped.
     pub fn reorder(&mut self, handle_ord: &[Handle]) {
         let old_items: VecDeque<Track> = mem::replace(&mut self.items, VecDeque::new());
 
@@ -169,24 +197,49 @@ impl PlayQueue {
         }
     }
 
-    #[allow(dead_code)] // queue manip stub
-    pub fn remove_by_handle(&mut self, handle: Handle) {
+    pub fn remove_by_handle(&mut self, handle: Handle) -> Result<(), ()> {
+        let old_len = self.items.len();
         let old_items: VecDeque<Track> = mem::replace(&mut self.items, VecDeque::new());
         for item in old_items.into_iter() {
             if item.handle != handle {
                 self.items.push_back(item);
             }
         }
+        if self.items.len() == old_len {
+            return Err(());
+        }
+        self.halloc.dispose(handle)
+    }
+
+    /// How many tracks may be added, not counting interrupts.
+    pub fn capacity(&self) -> usize {
+        self.halloc.limit()
+    }
+
+    pub fn handles(&self) -> Vec<Handle> {
+        self.items.iter().map(|item| item.handle).collect()
     }
 
-    pub fn add_track(&mut self, track: &OggTrack) -> Result<Handle, PlayQueueError> {
+    pub fn add_track(&mut self, track: IndexedOggTrack) -> Result<Handle, PlayQueueError> {
         let handle = try!(self.halloc.generate()
                 .map_err(|()| PlayQueueError::Full));
 
-        self.items.push_back(Track::from_ogg_track(handle, track.to_owned()));
+        self.items.push_back(Track::from_ogg_track(handle, track));
         Ok(handle)
     }
 
+    /// Puts `track` at the front of the queue, followed by `resume` if
+    /// given.  Interrupts are never refused for the queue being full.
+    pub fn add_interrupt(&mut self, track: IndexedOggTrack, resume: Option<Track>) -> Handle {
+        let handle = self.halloc.generate_unbounded();
+
+        if let Some(resume) = resume {
+            self.items.push_front(resume);
+        }
+        self.items.push_front(Track::from_ogg_track(handle, track));
+        handle
+    }
+
     pub fn pop_track(&mut self) -> Option<Track> {
         self.items.pop_front()
     }
@@ -0,0 +1,865 @@
+use std::thread;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc;
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+#[cfg(unix)]
+use rand;
+
+use ireul_interface::proto;
+use ireul_interface::proxy::{
+    PROTOCOL_VERSION,
+    PROTOCOL_VERSION_TAGGED,
+    EVENT_QUEUE_SIZE,
+    CoreProxy,
+    RequestType,
+    Subscription,
+    SubscribeResult,
+    LoginRequest,
+    LoginResult,
+    LoginError,
+    ErrorCode,
+    ProtocolError,
+};
+
+use auth::{self, Role, User};
+
+// how many of a tagged connection's requests may be waiting on the core
+const MAX_IN_FLIGHT: usize = 16;
+
+/// A connection we can read requests from on one thread while writing
+/// responses on another.
+trait ClientStream: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl ClientStream for TcpStream {
+    fn try_clone(&self) -> io::Result<TcpStream> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl ClientStream for UnixStream {
+    fn try_clone(&self) -> io::Result<UnixStream> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+/// What a connection is allowed to do, and who it is in the logs.
+struct Connection {
+    who: String,
+    peer: String,
+    role: Option<Role>,
+    proxy: CoreProxy,
+    users: Arc<Vec<User>>,
+}
+
+/// How to answer a request.
+enum Served {
+    Frame(Vec<u8>),
+    /// The core's answer, once it has one
+    Pending(mpsc::Receiver<Vec<u8>>),
+    /// Send the frame, then give up on the client for the reason given
+    HangUp(Vec<u8>, String),
+    /// Send the frame, then the subscription's events
+    Subscribed(Vec<u8>, Subscription),
+}
+
+impl Connection {
+    fn new(peer: &str, proxy: CoreProxy, users: Arc<Vec<User>>) -> Connection {
+        Connection {
+            who: format!("anonymous@{}", peer),
+            peer: peer.to_string(),
+            // with no users configured, anyone may do anything
+            role: if users.is_empty() { Some(Role::Admin) } else { None },
+            proxy: proxy,
+            users: users,
+        }
+    }
+
+    // Reads as much of `body` as the request needs; whoever called this
+    // skips the rest.
+    fn serve<R: Read>(&mut self, op_code: u32, body: &mut R, request_id: u64) -> io::Result<Served> {
+        let req_type = match RequestType::from_op_code(op_code) {
+            Ok(req_type) => req_type,
+            Err(()) => {
+                let err_msg = format!("unknown op-code {:?}", op_code);
+                info!("{}: {}", self.who, err_msg);
+                return error_frame(ErrorCode::UnknownOpCode, err_msg, request_id).map(Served::Frame);
+            }
+        };
+
+        if !self.role.map(|role| role.allows(req_type)).unwrap_or(auth::before_login(req_type)) {
+            let err_msg = format!("{} may not send {:?}", self.who, req_type);
+            info!("{}", err_msg);
+            return error_frame(ErrorCode::PermissionDenied, err_msg, request_id).map(Served::Frame);
+        }
+        info!("{}: {:?}", self.who, req_type);
+
+        if req_type.carries_track() {
+            // decoded and checked here, so the core is handed a track
+            return match self.proxy.start_track_call_from(req_type, body) {
+                Ok(responses) => Ok(Served::Pending(responses)),
+                Err(err) => {
+                    let err_msg = format!("core did not respond: {:?}", err);
+                    error_frame(ErrorCode::Unavailable, err_msg, request_id).map(Served::Frame)
+                },
+            };
+        }
+
+        let mut req_buf = Vec::new();
+        try!(body.read_to_end(&mut req_buf));
+
+        if req_type == RequestType::Login {
+            return self.login(req_buf, request_id);
+        }
+
+        if req_type == RequestType::Subscribe {
+            let (response, subscription) = match self.proxy.subscribe_raw(req_buf) {
+                Ok(answer) => answer,
+                Err(err) => {
+                    let err_msg = format!("core did not respond: {:?}", err);
+                    let frame = try!(error_frame(ErrorCode::Unavailable, err_msg.clone(), request_id));
+                    return Ok(Served::HangUp(frame, err_msg));
+                }
+            };
+            if ProtocolError::is_error_frame(&response) {
+                return with_request_id(response, request_id).map(Served::Frame);
+            }
+
+            let result: SubscribeResult = try!(proto::deserialize(&mut io::Cursor::new(response.clone())));
+            if result.is_ok() {
+                return Ok(Served::Subscribed(response, subscription));
+            }
+            return Ok(Served::Frame(response));
+        }
+
+        match self.proxy.start_call_raw(req_type, req_buf) {
+            Ok(responses) => Ok(Served::Pending(responses)),
+            Err(err) => {
+                let err_msg = format!("core did not respond: {:?}", err);
+                error_frame(ErrorCode::Unavailable, err_msg, request_id).map(Served::Frame)
+            },
+        }
+    }
+
+    fn login(&mut self, req_buf: Vec<u8>, request_id: u64) -> io::Result<Served> {
+        let req: LoginRequest = match proto::deserialize(&mut io::Cursor::new(req_buf)) {
+            Ok(req) => req,
+            Err(err) => {
+                let err_msg = format!("bad {:?} request: {}", RequestType::Login, err);
+                return error_frame(ErrorCode::MalformedRequest, err_msg, request_id).map(Served::Frame);
+            }
+        };
+        let result: LoginResult = if self.users.is_empty() {
+            // nothing to check against; the name is just for the logs
+            self.who = format!("{}@{} (unverified)", req.user, self.peer);
+            Ok(())
+        } else {
+            match auth::authenticate(&self.users, &req) {
+                Some(user) => {
+                    self.who = format!("{}@{}", user.name(), self.peer);
+                    self.role = Some(user.role());
+                    Ok(())
+                },
+                None => Err(LoginError::BadCredentials),
+            }
+        };
+
+        let frame = try!(proto::serialize(&result));
+        if result.is_err() {
+            let err_msg = format!("{} failed to log in as {}", self.who, req.user);
+            return Ok(Served::HangUp(frame, err_msg));
+        }
+        info!("{} logged in", self.who);
+        Ok(Served::Frame(frame))
+    }
+}
+
+/// Serves one client on `stream`, which is known by `peer` in the logs.
+/// The version byte of the first request decides whether requests are
+/// answered in lock-step or are tagged with ids and may be pipelined.
+/// Requests which can't be served are answered with a `ProtocolError`;
+/// we only hang up when we've lost track of where the next request
+/// starts.
+fn client_worker<S>(mut stream: S, peer: &str, proxy: CoreProxy, users: Arc<Vec<User>>) -> io::Result<()>
+    where S: ClientStream
+{
+    let conn = Connection::new(peer, proxy, users);
+    let version = try!(stream.read_u8());
+    if version == PROTOCOL_VERSION_TAGGED {
+        return tagged_worker(stream, conn);
+    }
+    lock_step_worker(stream, conn, version)
+}
+
+fn lock_step_worker<S>(mut stream: S, mut conn: Connection, first_version: u8) -> io::Result<()>
+    where S: ClientStream
+{
+    // requests are numbered from 1 in the order they arrive
+    let mut request_id: u64 = 0;
+    let mut version = first_version;
+
+    loop {
+        if version != PROTOCOL_VERSION {
+            let err_msg = format!("invalid version: {}", version);
+            let frame = try!(error_frame(ErrorCode::BadVersion, err_msg.clone(), 0));
+            try!(write_frame(&mut stream, &frame));
+            return Err(io::Error::new(io::ErrorKind::Other, err_msg));
+        }
+
+        let op_code = try!(stream.read_u32::<BigEndian>());
+        if op_code == 0 {
+            info!("goodbye, client");
+            return Ok(());
+        }
+        request_id += 1;
+
+        let frame_length = match try!(read_length(&mut stream, request_id)) {
+            Ok(frame_length) => frame_length,
+            Err((frame, err_msg)) => {
+                try!(write_frame(&mut stream, &frame));
+                return Err(io::Error::new(io::ErrorKind::Other, err_msg));
+            }
+        };
+        let served = {
+            let mut body = Read::by_ref(&mut stream).take(frame_length);
+            let served = try!(conn.serve(op_code, &mut body, request_id));
+            try!(finish_body(&mut body, frame_length));
+            served
+        };
+
+        match served {
+            Served::Frame(frame) => try!(write_frame(&mut stream, &frame)),
+            Served::Pending(responses) => {
+                try!(write_frame(&mut stream, &try!(await_response(responses, request_id))));
+            },
+            Served::HangUp(frame, err_msg) => {
+                try!(write_frame(&mut stream, &frame));
+                return Err(io::Error::new(io::ErrorKind::Other, err_msg));
+            },
+            Served::Subscribed(frame, subscription) => {
+                try!(write_frame(&mut stream, &frame));
+                return send_events(stream, subscription);
+            },
+        }
+
+        version = try!(stream.read_u8());
+    }
+}
+
+/// Something for the writer of a tagged connection to do.
+enum Outgoing {
+    Frame(u64, Vec<u8>),
+    /// Send what's queued before this, then hang up
+    Close,
+}
+
+/// Counts a connection's requests which are waiting on the core, so a
+/// client can't have us start a thread for every request it can write.
+struct InFlight {
+    count: Mutex<usize>,
+    finished: Condvar,
+}
+
+impl InFlight {
+    fn new() -> InFlight {
+        InFlight {
+            count: Mutex::new(0),
+            finished: Condvar::new(),
+        }
+    }
+
+    /// Waits until there's room for another request.
+    fn start(&self) {
+        let mut count = self.count.lock().unwrap();
+        while MAX_IN_FLIGHT <= *count {
+            count = self.finished.wait(count).unwrap();
+        }
+        *count += 1;
+    }
+
+    fn finish(&self) {
+        *self.count.lock().unwrap() -= 1;
+        self.finished.notify_all();
+    }
+
+    /// Waits until every request has its answer.
+    fn wait_idle(&self) {
+        let mut count = self.count.lock().unwrap();
+        while *count != 0 {
+            count = self.finished.wait(count).unwrap();
+        }
+    }
+}
+
+// Requests are read here and passed on to the core in the order they
+// arrive, but answered as soon as their answers are ready, so one which
+// keeps the core busy doesn't hold up those which don't need it.  A writer
+// thread sends the answers along with events from any number of
+// subscriptions.  Each frame carries the id of the request it answers;
+// events carry the id of the subscribe request.
+fn tagged_worker<S: ClientStream>(mut stream: S, mut conn: Connection) -> io::Result<()> {
+    let (outgoing, queued) = mpsc::sync_channel(EVENT_QUEUE_SIZE);
+    let writer_stream = try!(stream.try_clone());
+    let writer = thread::spawn(move || write_tagged(writer_stream, queued));
+
+    let in_flight = Arc::new(InFlight::new());
+    let result = read_tagged(&mut stream, &mut conn, &in_flight, &outgoing);
+    // a client which says goodbye still gets its answers
+    in_flight.wait_idle();
+    let _ = outgoing.send(Outgoing::Close);
+    let _ = writer.join();
+    result
+}
+
+fn read_tagged<S>(stream: &mut S, conn: &mut Connection, in_flight: &Arc<InFlight>, outgoing: &mpsc::SyncSender<Outgoing>) -> io::Result<()>
+    where S: ClientStream
+{
+    let send = |request_id: u64, frame: Vec<u8>| {
+        outgoing.send(Outgoing::Frame(request_id, frame)).map_err(|_| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "writer went away")
+        })
+    };
+
+    // the first version byte was read by client_worker
+    let mut version = PROTOCOL_VERSION_TAGGED;
+    loop {
+        if version != PROTOCOL_VERSION_TAGGED {
+            let err_msg = format!("invalid version: {}", version);
+            try!(send(0, try!(error_frame(ErrorCode::BadVersion, err_msg.clone(), 0))));
+            return Err(io::Error::new(io::ErrorKind::Other, err_msg));
+        }
+
+        let op_code = try!(stream.read_u32::<BigEndian>());
+        if op_code == 0 {
+            info!("goodbye, client");
+            return Ok(());
+        }
+        let request_id = try!(stream.read_u64::<BigEndian>());
+
+        let frame_length = match try!(read_length(stream, request_id)) {
+            Ok(frame_length) => frame_length,
+            Err((frame, err_msg)) => {
+                try!(send(request_id, frame));
+                return Err(io::Error::new(io::ErrorKind::Other, err_msg));
+            }
+        };
+        let served = {
+            let mut body = Read::by_ref(stream).take(frame_length);
+            let served = try!(conn.serve(op_code, &mut body, request_id));
+            try!(finish_body(&mut body, frame_length));
+            served
+        };
+
+        match served {
+            Served::Frame(frame) => try!(send(request_id, frame)),
+            Served::Pending(responses) => {
+                in_flight.start();
+                let in_flight = in_flight.clone();
+                let outgoing = outgoing.clone();
+                thread::spawn(move || {
+                    // a failure here is the writer's to notice
+                    if let Ok(frame) = await_response(responses, request_id) {
+                        let _ = outgoing.send(Outgoing::Frame(request_id, frame));
+                    }
+                    in_flight.finish();
+                });
+            },
+            Served::HangUp(frame, err_msg) => {
+                try!(send(request_id, frame));
+                return Err(io::Error::new(io::ErrorKind::Other, err_msg));
+            },
+            Served::Subscribed(frame, subscription) => {
+                try!(send(request_id, frame));
+                let events = outgoing.clone();
+                thread::spawn(move || forward_events(request_id, subscription, events));
+            },
+        }
+
+        version = try!(stream.read_u8());
+    }
+}
+
+fn write_tagged<S: ClientStream>(mut stream: S, queued: mpsc::Receiver<Outgoing>) {
+    while let Ok(Outgoing::Frame(request_id, frame)) = queued.recv() {
+        let written = stream.write_u64::<BigEndian>(request_id)
+            .map_err(io::Error::from)
+            .and_then(|()| write_frame(&mut stream, &frame));
+        if let Err(err) = written {
+            info!("error writing to client: {:?}", err);
+            break;
+        }
+    }
+    // stops the reader too, if it's still waiting on the client
+    let _ = stream.shutdown();
+}
+
+// Ends when the core drops the subscription or the connection closes.
+fn forward_events(request_id: u64, subscription: Subscription, outgoing: mpsc::SyncSender<Outgoing>) {
+    while let Ok(event) = subscription.recv_raw() {
+        if outgoing.send(Outgoing::Frame(request_id, event)).is_err() {
+            return;
+        }
+    }
+    info!("subscription ended by the core");
+}
+
+// Reads the length of a request's body.  A request we won't read gives
+// the error frame to send before hanging up, and why.
+fn read_length<S: Read>(stream: &mut S, request_id: u64) -> io::Result<Result<u64, (Vec<u8>, String)>> {
+    let frame_length = try!(stream.read_u32::<BigEndian>()) as usize;
+    if proto::MESSAGE_SIZE_LIMIT < frame_length {
+        let err_msg = format!("datagram too large: {} bytes (limit is {})",
+            frame_length, proto::MESSAGE_SIZE_LIMIT);
+        let frame = try!(error_frame(ErrorCode::TooLarge, err_msg.clone(), request_id));
+        return Ok(Err((frame, err_msg)));
+    }
+    Ok(Ok(frame_length as u64))
+}
+
+// Skips whatever of the body the request didn't read, so the next request
+// starts in the right place.  A body cut short ends the connection.
+fn finish_body<S: Read>(body: &mut io::Take<S>, frame_length: u64) -> io::Result<()> {
+    try!(io::copy(body, &mut io::sink()));
+    if body.limit() != 0 {
+        let err_msg = format!(
+            "datagram truncated: got {} bytes, expected {}",
+            frame_length - body.limit(), frame_length);
+        return Err(io::Error::new(io::ErrorKind::Other, err_msg));
+    }
+    Ok(())
+}
+
+fn await_response(responses: mpsc::Receiver<Vec<u8>>, request_id: u64) -> io::Result<Vec<u8>> {
+    match responses.recv() {
+        Ok(response) => with_request_id(response, request_id),
+        Err(err) => {
+            let err_msg = format!("core did not respond: {:?}", err);
+            error_frame(ErrorCode::Unavailable, err_msg, request_id)
+        },
+    }
+}
+
+fn write_frame<S: Write>(stream: &mut S, frame: &[u8]) -> io::Result<()> {
+    try!(stream.write_u32::<BigEndian>(frame.len() as u32));
+    stream.write_all(frame)
+}
+
+// The core may answer with a ProtocolError, not knowing which request it
+// was answering, so we fill that in.
+fn with_request_id(response: Vec<u8>, request_id: u64) -> io::Result<Vec<u8>> {
+    if !ProtocolError::is_error_frame(&response) {
+        return Ok(response);
+    }
+    let mut err: ProtocolError = try!(proto::deserialize(&mut io::Cursor::new(response)));
+    err.request_id = request_id;
+    proto::serialize(&err)
+}
+
+fn error_frame(code: ErrorCode, message: String, request_id: u64) -> io::Result<Vec<u8>> {
+    let mut err = ProtocolError::new(code, message);
+    err.request_id = request_id;
+    proto::serialize(&err)
+}
+
+// The client has nothing more to say once it has subscribed; it hangs up
+// when it's had enough.
+fn send_events<S: Write>(mut stream: S, subscription: Subscription) -> io::Result<()> {
+    while let Ok(event) = subscription.recv_raw() {
+        try!(write_frame(&mut stream, &event));
+    }
+    info!("subscription ended by the core");
+    Ok(())
+}
+
+/// Serves clients connecting to `server`.  If there are any `users`,
+/// clients must log in as one of them, and may only make the requests
+/// their role allows.
+pub fn client_acceptor(server: TcpListener, proxy: CoreProxy, users: Arc<Vec<User>>) {
+    for stream in server.incoming() {
+        match stream {
+            Ok(stream) => {
+                let client_proxy = proxy.clone();
+                let client_users = users.clone();
+                thread::spawn(move || {
+                    let peer = stream.peer_addr()
+                        .map(|addr| addr.to_string())
+                        .unwrap_or("unknown".to_string());
+                    if let Err(err) = client_worker(stream, &peer, client_proxy, client_users) {
+                        info!("client disconnected with error: {:?}", err);
+                    }
+                });
+            },
+            Err(err) => {
+                info!("error accepting new client: {:?}", err);
+            }
+        }
+    }
+}
+
+/// Binds a Unix socket at `path`, replacing a stale one left behind by an
+/// earlier run, and gives it `mode` if set.  Fails with `AddrInUse` if
+/// something is still listening there.
+#[cfg(unix)]
+pub fn bind_unix<P: AsRef<Path>>(path: P, mode: Option<u32>) -> io::Result<UnixListener> {
+    let path = path.as_ref();
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            let err_msg = format!("{} exists and isn't a socket", path.display());
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, err_msg));
+        }
+        if UnixStream::connect(path).is_ok() {
+            let err_msg = format!("something is already listening on {}", path.display());
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, err_msg));
+        }
+        try!(fs::remove_file(path));
+    }
+
+    // The socket is bound in a directory only we can get into and moved
+    // into place once it has its mode, so nobody can connect before then.
+    let file_name = try!(path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't a file name", path.display()))
+    }));
+    let private_name = format!(".{}.{:08x}", file_name.to_string_lossy(), rand::random::<u32>());
+    let private_dir = path.with_file_name(private_name);
+    try!(fs::DirBuilder::new().mode(0o700).create(&private_dir));
+    let private_path = private_dir.join(file_name);
+
+    let bound = UnixListener::bind(&private_path).and_then(|listener| {
+        if let Some(mode) = mode {
+            try!(fs::set_permissions(&private_path, fs::Permissions::from_mode(mode)));
+        }
+        try!(fs::rename(&private_path, path));
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&private_path);
+    let _ = fs::remove_dir(&private_dir);
+    bound
+}
+
+/// As `client_acceptor`, for clients connecting to a Unix socket.
+#[cfg(unix)]
+pub fn unix_client_acceptor(server: UnixListener, proxy: CoreProxy, users: Arc<Vec<User>>) {
+    for stream in server.incoming() {
+        match stream {
+            Ok(stream) => {
+                let client_proxy = proxy.clone();
+                let client_users = users.clone();
+                thread::spawn(move || {
+                    if let Err(err) = client_worker(stream, "unix", client_proxy, client_users) {
+                        info!("client disconnected with error: {:?}", err);
+                    }
+                });
+            },
+            Err(err) => {
+                info!("error accepting new client: {:?}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::env;
+    #[cfg(unix)]
+    use std::fs;
+    use std::io::{self, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+
+    use ogg::{OggPage, OggTrackBuf};
+
+    use ireul_interface::client::{IreulClient, Pipeline};
+    use ireul_interface::proto;
+    use ireul_interface::proxy::{
+        ErrorCode,
+        ProtocolError,
+        OP_ENQUEUE_TRACK,
+        OP_QUEUE_STATUS,
+        EnqueueTrackRequest,
+        EnqueueTrackResult,
+        StatusRequest,
+        StatusResult,
+        SubscribeRequest,
+        SubscribeResult,
+        Event,
+    };
+
+    use output::{NullSink, OutputSink};
+    use station::StationBuilder;
+
+    const DEAD_AIR: &'static [u8] = include_bytes!("deadair.ogg");
+    #[cfg(unix)]
+    use super::bind_unix;
+
+    /// Holds up the core in its first write until `release` is dropped.
+    struct StalledSink {
+        release: mpsc::Receiver<()>,
+    }
+
+    impl OutputSink for StalledSink {
+        fn send_ogg_page(&mut self, _page: &OggPage) -> io::Result<()> {
+            let _ = self.release.recv();
+            Ok(())
+        }
+
+        fn reconnect(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn is_healthy(&self) -> bool {
+            true
+        }
+    }
+
+    fn send(conn: &mut TcpStream, version: u8, op_code: u32, body: &[u8]) {
+        conn.write_u8(version).unwrap();
+        conn.write_u32::<BigEndian>(op_code).unwrap();
+        conn.write_u32::<BigEndian>(body.len() as u32).unwrap();
+        conn.write_all(body).unwrap();
+    }
+
+    fn send_tagged(conn: &mut TcpStream, op_code: u32, request_id: u64, body: &[u8]) {
+        conn.write_u8(1).unwrap();
+        conn.write_u32::<BigEndian>(op_code).unwrap();
+        conn.write_u64::<BigEndian>(request_id).unwrap();
+        conn.write_u32::<BigEndian>(body.len() as u32).unwrap();
+        conn.write_all(body).unwrap();
+    }
+
+    fn recv_error(conn: &mut TcpStream) -> ProtocolError {
+        let length = conn.read_u32::<BigEndian>().unwrap();
+        let mut frame = Vec::new();
+        Read::by_ref(conn).take(length as u64).read_to_end(&mut frame).unwrap();
+        assert!(ProtocolError::is_error_frame(&frame));
+        proto::deserialize(&mut io::Cursor::new(frame)).unwrap()
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        let station = StationBuilder::new()
+            .set_output(Box::new(NullSink))
+            .start()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || station.serve(listener));
+
+        let mut conn = TcpStream::connect(addr).unwrap();
+        send(&mut conn, 0, 0x7777, b"whatever");
+        let err = recv_error(&mut conn);
+        assert_eq!(err.code, ErrorCode::UnknownOpCode);
+        assert_eq!(err.request_id, 1);
+
+        send(&mut conn, 0, OP_ENQUEUE_TRACK, &[0x00, 0x05, 0xFF]);
+        let err = recv_error(&mut conn);
+        assert_eq!(err.code, ErrorCode::MalformedRequest);
+        assert_eq!(err.request_id, 2);
+
+        // none of that lost us the connection
+        let mut client = IreulClient::from_stream(conn.try_clone().unwrap());
+        assert!(client.queue_status().unwrap().is_ok());
+
+        // but there's no carrying on after a bad version
+        conn.write_u8(9).unwrap();
+        assert_eq!(recv_error(&mut conn).code, ErrorCode::BadVersion);
+        // closed, or reset if it had more to read
+        assert!(conn.read(&mut [0; 1]).map(|len| len == 0).unwrap_or(true));
+    }
+
+    #[test]
+    fn test_pipelining() {
+        let station = StationBuilder::new()
+            .set_output(Box::new(NullSink))
+            .start()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || station.serve(listener));
+
+        let mut pipeline = Pipeline::connect(addr).unwrap();
+        let subscribe_id = pipeline.send(&SubscribeRequest).unwrap();
+        let enqueue_id = pipeline.send(&EnqueueTrackRequest {
+            track: OggTrackBuf::new(DEAD_AIR.to_vec()).unwrap(),
+            metadata: None,
+        }).unwrap();
+        let status_id = pipeline.send(&StatusRequest).unwrap();
+
+        // the subscription doesn't stop the connection taking requests,
+        // and its events are told apart by id.
+        let mut handle = None;
+        let mut answered = 0;
+        loop {
+            let tagged = pipeline.recv().unwrap();
+            if tagged.request_id == subscribe_id {
+                if answered == 0 {
+                    let result: SubscribeResult = tagged.decode().unwrap();
+                    assert!(result.is_ok());
+                    answered += 1;
+                    continue;
+                }
+                match tagged.decode().unwrap() {
+                    Event::TrackStarted(ref track) if Some(track.handle) == handle => break,
+                    _ => (),
+                }
+            } else if tagged.request_id == enqueue_id {
+                let result: EnqueueTrackResult = tagged.decode().unwrap();
+                handle = Some(result.unwrap());
+                answered += 1;
+            } else if tagged.request_id == status_id {
+                let result: StatusResult = tagged.decode().unwrap();
+                assert!(result.is_ok());
+                answered += 1;
+            } else {
+                panic!("unexpected request id {}", tagged.request_id);
+            }
+        }
+        assert_eq!(answered, 3);
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let (release, stalled) = mpsc::channel();
+        let station = StationBuilder::new()
+            .set_output(Box::new(StalledSink { release: stalled }))
+            .start()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || station.serve(listener));
+
+        let mut conn = TcpStream::connect(addr).unwrap();
+        send_tagged(&mut conn, OP_QUEUE_STATUS, 1, &proto::serialize(&StatusRequest).unwrap());
+        send_tagged(&mut conn, 0x7777, 2, &[]);
+
+        // the core is busy, but an unknown op code doesn't need it
+        assert_eq!(conn.read_u64::<BigEndian>().unwrap(), 2);
+        assert_eq!(recv_error(&mut conn).code, ErrorCode::UnknownOpCode);
+
+        drop(release);
+        assert_eq!(conn.read_u64::<BigEndian>().unwrap(), 1);
+        let length = conn.read_u32::<BigEndian>().unwrap();
+        let mut frame = Vec::new();
+        Read::by_ref(&mut conn).take(length as u64).read_to_end(&mut frame).unwrap();
+        let result: StatusResult = proto::deserialize(&mut io::Cursor::new(frame)).unwrap();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_goodbye_after_pipelining() {
+        let station = StationBuilder::new()
+            .set_output(Box::new(NullSink))
+            .start()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || station.serve(listener));
+
+        let mut conn = TcpStream::connect(addr).unwrap();
+        let status = proto::serialize(&StatusRequest).unwrap();
+        for request_id in 1..4 {
+            send_tagged(&mut conn, OP_QUEUE_STATUS, request_id, &status);
+        }
+        // goodbye
+        conn.write_u8(1).unwrap();
+        conn.write_u32::<BigEndian>(0).unwrap();
+
+        let mut answered = Vec::new();
+        for _ in 1..4 {
+            answered.push(conn.read_u64::<BigEndian>().unwrap());
+            let length = conn.read_u32::<BigEndian>().unwrap();
+            let mut frame = Vec::new();
+            Read::by_ref(&mut conn).take(length as u64).read_to_end(&mut frame).unwrap();
+            let result: StatusResult = proto::deserialize(&mut io::Cursor::new(frame)).unwrap();
+            assert!(result.is_ok());
+        }
+        answered.sort();
+        assert_eq!(answered, vec![1, 2, 3]);
+        // and then we're hung up on
+        assert_eq!(conn.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_mixed_versions() {
+        let station = StationBuilder::new()
+            .set_output(Box::new(NullSink))
+            .start()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || station.serve(listener));
+
+        let mut conn = TcpStream::connect(addr).unwrap();
+        send_tagged(&mut conn, 0x7777, 42, &[]);
+        assert_eq!(conn.read_u64::<BigEndian>().unwrap(), 42);
+        assert_eq!(recv_error(&mut conn).code, ErrorCode::UnknownOpCode);
+
+        // a lock-step request on a tagged connection, which is hung up on
+        // before it's all been sent
+        conn.write_u8(0).unwrap();
+        assert_eq!(conn.read_u64::<BigEndian>().unwrap(), 0);
+        assert_eq!(recv_error(&mut conn).code, ErrorCode::BadVersion);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket() {
+        let path = env::temp_dir().join("ireul-test-control.sock");
+        // a socket left over from an earlier run can't be opened as a file
+        let _ = fs::remove_file(&path);
+        fs::File::create(&path).unwrap();
+        // not going to replace a file which isn't a socket
+        assert!(bind_unix(&path, None).is_err());
+        fs::remove_file(&path).unwrap();
+
+        let station = StationBuilder::new()
+            .set_output(Box::new(NullSink))
+            .start()
+            .unwrap();
+        let listener = bind_unix(&path, Some(0o600)).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        thread::spawn(move || station.serve_unix(listener));
+
+        let mut client = IreulClient::connect_unix(&path).unwrap();
+        assert!(client.queue_status().unwrap().is_ok());
+
+        // not while the station is still listening on it
+        let err = bind_unix(&path, None).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        // but a stale socket is replaced
+        let stale_path = env::temp_dir().join("ireul-test-stale.sock");
+        let _ = fs::remove_file(&stale_path);
+        drop(bind_unix(&stale_path, None).unwrap());
+        drop(bind_unix(&stale_path, None).unwrap());
+        fs::remove_file(&stale_path).unwrap();
+    }
+}
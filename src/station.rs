@@ -0,0 +1,411 @@
+use std::mem;
+use std::thread;
+use std::sync::mpsc;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::fs::File;
+use std::io::Read;
+
+use ogg::IndexedOggTrack;
+use ogg::vorbis::VorbisPacket;
+
+use ireul_interface::proxy::track::model::{Handle, Queue};
+use ireul_interface::proxy::{
+    CoreProxy,
+    ProxyResult,
+    EnqueueTrackRequest,
+    EnqueueTrackError,
+    FastForward,
+    FastForwardRequest,
+    FastForwardError,
+    ReplaceFallbackRequest,
+    ReplaceFallbackError,
+    InterruptRequest,
+    InterruptError,
+    StatusRequest,
+    StatusError,
+    RemoveTrackRequest,
+    RemoveTrackError,
+    MoveTrackRequest,
+    MoveTrackError,
+    HelloRequest,
+    HelloError,
+    Capabilities,
+    Subscription,
+    SubscribeRequest,
+    SubscribeError,
+};
+
+use auth::User;
+use config::Config;
+use core::{self, Backoff, Core};
+use hooks::{self, Hook};
+use icecastwriter::IceCastWriterOptions;
+use output::{self, OutputSink};
+use http;
+use mpd;
+use server;
+
+const DEAD_AIR: &'static [u8] = include_bytes!("deadair.ogg");
+
+const SAMPLE_RATE: u32 = 48000;
+
+const DEFAULT_QUEUE_SIZE: usize = 40;
+
+// requests waiting for the core beyond this block their callers
+const REQUEST_QUEUE_SIZE: usize = 32;
+
+/// Sets up a `Station`.
+pub struct StationBuilder {
+    output_url: Option<String>,
+    output: Option<Box<OutputSink>>,
+    icecast_options: IceCastWriterOptions,
+    fallback_track: Option<IndexedOggTrack>,
+    backoff: Backoff,
+    // in seconds
+    rewind: u64,
+    queue_size: usize,
+    hooks: Vec<Hook>,
+    users: Vec<User>,
+}
+
+impl StationBuilder {
+    pub fn new() -> StationBuilder {
+        StationBuilder {
+            output_url: None,
+            output: None,
+            icecast_options: IceCastWriterOptions::default(),
+            fallback_track: None,
+            backoff: Backoff::default(),
+            rewind: 0,
+            queue_size: DEFAULT_QUEUE_SIZE,
+            hooks: Vec::new(),
+            users: Vec::new(),
+        }
+    }
+
+    /// Sets up a station as described by a config file, loading its
+    /// fallback track if it has one.
+    pub fn from_config(config: &Config) -> Result<StationBuilder, String> {
+        let mut builder = StationBuilder::new();
+        builder.set_output_url(try!(config.output_url()))
+            .set_icecast_options(try!(config.icecast_writer_opts()))
+            .set_backoff(config.backoff())
+            .set_rewind(config.rewind_seconds());
+        for hook in try!(config.hooks()) {
+            builder.add_hook(hook);
+        }
+        for user in try!(config.users()) {
+            builder.add_user(user);
+        }
+
+        if let Some(filename) = config.fallback_track() {
+            let mut file = try!(File::open(filename)
+                .map_err(|err| format!("error opening {}: {}", filename, err)));
+            let mut buffer = Vec::new();
+            try!(file.read_to_end(&mut buffer)
+                .map_err(|err| format!("error reading {}: {}", filename, err)));
+            let track = try!(IndexedOggTrack::new(buffer)
+                .map_err(|err| format!("invalid fallback track {}: {:?}", filename, err)));
+            builder.set_fallback_track(track);
+        }
+
+        Ok(builder)
+    }
+
+    /// Where to send the stream, as understood by `output::open`.
+    pub fn set_output_url(&mut self, output_url: &str) -> &mut Self {
+        self.output_url = Some(output_url.to_string());
+        self
+    }
+
+    /// Sends the stream to an already opened output instead of
+    /// `output_url`.
+    pub fn set_output(&mut self, output: Box<OutputSink>) -> &mut Self {
+        self.output = Some(output);
+        self
+    }
+
+    pub fn set_icecast_options(&mut self, options: IceCastWriterOptions) -> &mut Self {
+        self.icecast_options = options;
+        self
+    }
+
+    /// What to play while the queue is empty.  Defaults to silence.
+    pub fn set_fallback_track(&mut self, track: IndexedOggTrack) -> &mut Self {
+        self.fallback_track = Some(track);
+        self
+    }
+
+    pub fn set_backoff(&mut self, backoff: Backoff) -> &mut Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Seconds of the current track to replay after reconnecting.
+    pub fn set_rewind(&mut self, seconds: u64) -> &mut Self {
+        self.rewind = seconds;
+        self
+    }
+
+    /// How many tracks may wait in the queue.
+    pub fn set_queue_size(&mut self, queue_size: usize) -> &mut Self {
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Fires `hook` on events from the station.
+    pub fn add_hook(&mut self, hook: Hook) -> &mut Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Lets `user` log in to the control protocol.  Once there are any
+    /// users, nobody else gets in.
+    pub fn add_user(&mut self, user: User) -> &mut Self {
+        self.users.push(user);
+        self
+    }
+
+    /// Connects to the output and starts playing on a new thread.
+    pub fn start(&mut self) -> Result<Station, String> {
+        let offline_track = match self.fallback_track.take() {
+            Some(track) => track,
+            None => IndexedOggTrack::new(DEAD_AIR.to_vec()).unwrap(),
+        };
+
+        let output = match (self.output.take(), self.output_url.as_ref()) {
+            (Some(output), _) => output,
+            (None, Some(output_url)) => {
+                let mut icecast_options = self.icecast_options.clone();
+                if let Ok(packet) = VorbisPacket::find_identification(offline_track.pages()) {
+                    // find_identification will always find a packet with an identification_header
+                    icecast_options.set_audio_info_from(&packet.identification_header().unwrap());
+                }
+                try!(output::open(output_url, &icecast_options))
+            },
+            (None, None) => return Err("no output configured".to_string()),
+        };
+
+        let resume_rewind = self.rewind * SAMPLE_RATE as u64;
+        let core = Core::new(offline_track, SAMPLE_RATE, self.queue_size, resume_rewind);
+        let backoff = self.backoff.clone();
+
+        let (requests_tx, requests) = mpsc::sync_channel(REQUEST_QUEUE_SIZE);
+        thread::spawn(move || {
+            core::run_core(core, output, backoff, requests);
+        });
+
+        let proxy = CoreProxy::new(requests_tx, SAMPLE_RATE);
+
+        if !self.hooks.is_empty() {
+            // subscribed before anyone else can get a request in, so the
+            // hooks see everything.
+            let events = try!(proxy.subscribe(SubscribeRequest)
+                .map_err(|err| format!("error subscribing hooks: {:?}", err)));
+            let hooks = mem::replace(&mut self.hooks, Vec::new());
+            let hooks_proxy = proxy.clone();
+            thread::spawn(move || {
+                hooks::run_hooks(hooks_proxy, hooks, events);
+            });
+        }
+
+        Ok(Station {
+            proxy: proxy,
+            users: Arc::new(mem::replace(&mut self.users, Vec::new())),
+        })
+    }
+}
+
+/// A running station.  It stops once it and every proxy taken from it
+/// have been dropped.
+#[derive(Clone)]
+pub struct Station {
+    proxy: CoreProxy,
+    users: Arc<Vec<User>>,
+}
+
+impl Station {
+    /// A handle which can be sent to other threads.
+    pub fn proxy(&self) -> CoreProxy {
+        self.proxy.clone()
+    }
+
+    /// Serves the ireul control protocol to clients connecting to
+    /// `listener`, who must log in if any users were added.  Never
+    /// returns.
+    pub fn serve(&self, listener: TcpListener) {
+        server::client_acceptor(listener, self.proxy.clone(), self.users.clone());
+    }
+
+    /// Serves the ireul control protocol on a Unix socket, such as one
+    /// from `bind_unix`.  Never returns.
+    #[cfg(unix)]
+    pub fn serve_unix(&self, listener: UnixListener) {
+        server::unix_client_acceptor(listener, self.proxy.clone(), self.users.clone());
+    }
+
+    /// Serves the HTTP/JSON API to clients connecting to `listener`, who
+    /// must use Basic authentication if any users were added.  Never
+    /// returns.
+    pub fn serve_http(&self, listener: TcpListener) {
+        http::client_acceptor(listener, self.proxy.clone(), self.users.clone());
+    }
+
+    /// Serves the MPD protocol to clients connecting to `listener`, which
+    /// can add tracks from `media_root`.  If any users were added, clients
+    /// must give one's token as their password.  Never returns.
+    pub fn serve_mpd(&self, listener: TcpListener, media_root: Option<PathBuf>) {
+        mpd::client_acceptor(listener, self.proxy.clone(), self.users.clone(), media_root);
+    }
+
+    pub fn enqueue_track(&self, req: EnqueueTrackRequest) -> ProxyResult<Handle, EnqueueTrackError> {
+        self.proxy.enqueue_track(req)
+    }
+
+    pub fn fast_forward(&self, kind: FastForward) -> ProxyResult<(), FastForwardError> {
+        self.proxy.fast_forward(FastForwardRequest { kind: kind })
+    }
+
+    pub fn queue_status(&self) -> ProxyResult<Queue, StatusError> {
+        self.proxy.queue_status(StatusRequest)
+    }
+
+    pub fn replace_fallback(&self, req: ReplaceFallbackRequest) -> ProxyResult<(), ReplaceFallbackError> {
+        self.proxy.replace_fallback(req)
+    }
+
+    pub fn interrupt(&self, req: InterruptRequest) -> ProxyResult<Handle, InterruptError> {
+        self.proxy.interrupt(req)
+    }
+
+    /// What the station supports, as a client would see it.
+    pub fn capabilities(&self) -> ProxyResult<Capabilities, HelloError> {
+        self.proxy.hello(HelloRequest)
+    }
+
+    pub fn remove_track(&self, handle: Handle) -> ProxyResult<(), RemoveTrackError> {
+        self.proxy.remove_track(RemoveTrackRequest { handle: handle })
+    }
+
+    /// Moves a queued track to `position`, 0 being the next to play.
+    pub fn move_track(&self, handle: Handle, position: u32) -> ProxyResult<(), MoveTrackError> {
+        self.proxy.move_track(MoveTrackRequest { handle: handle, position: position })
+    }
+
+    /// Listens for things happening on the station.
+    pub fn subscribe(&self) -> ProxyResult<Subscription, SubscribeError> {
+        self.proxy.subscribe(SubscribeRequest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use ogg::OggTrackBuf;
+
+    use ireul_interface::client::{IreulClient, ClientError};
+    use ireul_interface::proxy::{EnqueueTrackRequest, FastForward, Event, RequestType, ErrorCode};
+
+    use auth::{Role, User};
+    use output::NullSink;
+    use super::{StationBuilder, DEAD_AIR};
+
+    #[test]
+    fn test_station() {
+        let station = StationBuilder::new()
+            .set_output(Box::new(NullSink))
+            .start()
+            .unwrap();
+
+        let handle = station.enqueue_track(EnqueueTrackRequest {
+            track: OggTrackBuf::new(DEAD_AIR.to_vec()).unwrap(),
+            metadata: Some(vec![("TITLE".to_string(), "silence".to_string())]),
+        }).ok().unwrap();
+
+        let queue = station.queue_status().ok().unwrap();
+        assert!(queue.upcoming.iter().any(|track| track.handle == handle));
+
+        station.fast_forward(FastForward::TrackBoundary).ok().unwrap();
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let station = StationBuilder::new()
+            .set_output(Box::new(NullSink))
+            .start()
+            .unwrap();
+        let events = station.subscribe().ok().unwrap();
+
+        let handle = station.enqueue_track(EnqueueTrackRequest {
+            track: OggTrackBuf::new(DEAD_AIR.to_vec()).unwrap(),
+            metadata: None,
+        }).ok().unwrap();
+
+        match events.recv().unwrap() {
+            Event::QueueChanged => (),
+            other => panic!("unexpected event: {:?}", other),
+        }
+        loop {
+            match events.recv().unwrap() {
+                Event::TrackStarted(ref track) if track.handle == handle => break,
+                Event::QueueChanged => (),
+                other => panic!("unexpected event: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let station = StationBuilder::new()
+            .set_output(Box::new(NullSink))
+            .set_queue_size(7)
+            .start()
+            .unwrap();
+        let caps = station.capabilities().ok().unwrap();
+        assert_eq!(caps.sample_rate, 48000);
+        assert_eq!(caps.queue_capacity, 7);
+        assert!(caps.supports(RequestType::MoveTrack));
+    }
+
+    #[test]
+    fn test_login() {
+        let station = StationBuilder::new()
+            .set_output(Box::new(NullSink))
+            .add_user(User::new("ann", "secret", Role::Requester))
+            .start()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || station.serve(listener));
+
+        // nothing but a login before logging in
+        let mut client = IreulClient::connect(addr).unwrap();
+        assert!(client.queue_status().is_err());
+
+        let mut client = IreulClient::connect(addr).unwrap();
+        // but anyone can say hello
+        assert!(client.hello().unwrap().is_ok());
+        assert!(client.login("ann", "wrong").unwrap().is_err());
+
+        let mut client = IreulClient::connect(addr).unwrap();
+        assert!(client.login("ann", "secret").unwrap().is_ok());
+        assert!(client.queue_status().unwrap().is_ok());
+        // requesters can't skip, but may carry on after trying
+        match client.fast_forward(FastForward::TrackBoundary) {
+            Err(ClientError::Protocol(err)) => assert_eq!(err.code, ErrorCode::PermissionDenied),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(client.queue_status().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_no_output() {
+        assert!(StationBuilder::new().start().is_err());
+    }
+}
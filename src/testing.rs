@@ -0,0 +1,17 @@
+//! Fixtures shared by the unit tests.
+
+use ireul_interface::proxy::track::model::{Handle, TrackInfo};
+
+pub fn track_info() -> TrackInfo {
+    TrackInfo {
+        handle: Handle(3),
+        started_at: Some(1450000000),
+        artist: "artist".to_string(),
+        album: "album".to_string(),
+        title: "title".to_string(),
+        sample_rate: 48000,
+        sample_count: 480000,
+        sample_position: 0,
+        metadata: vec![("TITLE".to_string(), "title".to_string())],
+    }
+}
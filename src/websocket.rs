@@ -0,0 +1,472 @@
+//! The `/feed` WebSocket: what's playing, the queue, and where we are in
+//! the track, pushed as JSON text frames for the station website.
+//!
+//! Messages are objects with a `type` of `now_playing`, `queue` or
+//! `position`.  Pings and closes from the client are answered; anything
+//! else it sends is ignored.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::base64::{ToBase64, STANDARD};
+use rustc_serialize::json::{Json, ToJson};
+use time::SteadyTime;
+
+use ireul_interface::proxy::{CoreProxy, Event, SubscribeRequest, StatusRequest};
+use ireul_interface::proxy::track::model::TrackInfo;
+
+use json;
+
+const GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+// how often the position goes out
+const TICK_MILLIS: i64 = 1000;
+
+// How often we ask the core where the track is, rather than working it
+// out from the clock.  Seeks aren't events, so this is how we notice them.
+const RESYNC_SECS: i64 = 30;
+
+// clients have no reason to send us anything bigger
+const CLIENT_FRAME_LIMIT: u64 = 64 * 1024;
+
+// a website visitor on a bad connection mustn't hold a thread forever
+const WRITE_TIMEOUT_SECS: u64 = 10;
+
+fn sha1(message: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    let bit_length = (message.len() as u64).wrapping_mul(8);
+    for i in 0..8 {
+        padded.push((bit_length >> (56 - 8 * i)) as u8);
+    }
+
+    for block in padded.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = (block[4 * i] as u32) << 24
+                | (block[4 * i + 1] as u32) << 16
+                | (block[4 * i + 2] as u32) << 8
+                | block[4 * i + 3] as u32;
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = if i < 20 {
+                ((b & c) | (!b & d), 0x5A827999)
+            } else if i < 40 {
+                (b ^ c ^ d, 0x6ED9EBA1)
+            } else if i < 60 {
+                ((b & c) | (b & d) | (c & d), 0x8F1BBCDC)
+            } else {
+                (b ^ c ^ d, 0xCA62C1D6)
+            };
+            let temp = a.rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+        h[4] = h[4].wrapping_add(e);
+    }
+
+    let mut digest = [0; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[4 * i] = (word >> 24) as u8;
+        digest[4 * i + 1] = (word >> 16) as u8;
+        digest[4 * i + 2] = (word >> 8) as u8;
+        digest[4 * i + 3] = *word as u8;
+    }
+    digest
+}
+
+/// The `Sec-WebSocket-Accept` answering a `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    let mut challenge = key.as_bytes().to_vec();
+    challenge.extend(GUID.as_bytes().iter().cloned());
+    sha1(&challenge).to_base64(STANDARD)
+}
+
+// Servers never mask their frames, and we never fragment ours.
+fn write_frame<W: Write>(stream: &mut W, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut head = vec![0x80 | opcode];
+    if payload.len() < 126 {
+        head.push(payload.len() as u8);
+    } else if payload.len() <= 0xFFFF {
+        head.push(126);
+        head.push((payload.len() >> 8) as u8);
+        head.push(payload.len() as u8);
+    } else {
+        head.push(127);
+        let length = payload.len() as u64;
+        for i in 0..8 {
+            head.push((length >> (56 - 8 * i)) as u8);
+        }
+    }
+    try!(stream.write_all(&head));
+    try!(stream.write_all(payload));
+    stream.flush()
+}
+
+// Reads one frame, unmasking it if need be, giving its opcode and payload.
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut head = [0; 2];
+    try!(reader.read_exact(&mut head));
+    let mut length = (head[1] & 0x7F) as u64;
+    let extended = match length {
+        126 => 2,
+        127 => 8,
+        _ => 0,
+    };
+    if extended != 0 {
+        let mut bytes = vec![0; extended];
+        try!(reader.read_exact(&mut bytes));
+        length = bytes.iter().fold(0, |acc, &byte| acc << 8 | byte as u64);
+    }
+    if CLIENT_FRAME_LIMIT < length {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+    }
+
+    let mut mask = [0; 4];
+    let masked = head[1] & 0x80 != 0;
+    if masked {
+        try!(reader.read_exact(&mut mask));
+    }
+    let mut payload = vec![0; length as usize];
+    try!(reader.read_exact(&mut payload));
+    if masked {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+    Ok((head[0] & 0x0F, payload))
+}
+
+fn send<W: Write>(stream: &mut W, kind: &str, fields: Vec<(&str, Json)>) -> io::Result<()> {
+    let mut obj = BTreeMap::new();
+    obj.insert("type".to_string(), kind.to_json());
+    for (name, value) in fields.into_iter() {
+        obj.insert(name.to_string(), value);
+    }
+    write_frame(stream, OPCODE_TEXT, Json::Object(obj).to_string().as_bytes())
+}
+
+fn send_now_playing<W: Write>(stream: &mut W, track: Option<&TrackInfo>) -> io::Result<()> {
+    let track = track.map(json::track_json).unwrap_or(Json::Null);
+    send(stream, "now_playing", vec![("track", track)])
+}
+
+fn send_position<W: Write>(stream: &mut W, track: &TrackInfo) -> io::Result<()> {
+    let sample_rate = if track.sample_rate == 0 { 1 } else { track.sample_rate };
+    send(stream, "position", vec![
+        ("handle", track.handle.0.to_json()),
+        ("sample_position", track.sample_position.to_json()),
+        ("sample_rate", track.sample_rate.to_json()),
+        ("position", (track.sample_position as f64 / sample_rate as f64).to_json()),
+        ("duration", (track.sample_count as f64 / sample_rate as f64).to_json()),
+    ])
+}
+
+fn core_gone() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "the station isn't responding")
+}
+
+/// The track on air, as of when we last heard about it.
+struct Playing {
+    track: TrackInfo,
+    as_of: SteadyTime,
+    // false while the output is down, and nothing is being played
+    running: bool,
+}
+
+impl Playing {
+    fn new(track: TrackInfo) -> Playing {
+        Playing {
+            track: track,
+            as_of: SteadyTime::now(),
+            running: true,
+        }
+    }
+
+    /// The track with where it should have got to by now.
+    fn now(&self) -> TrackInfo {
+        let mut track = self.track.clone();
+        if self.running {
+            let elapsed = cmp::max((SteadyTime::now() - self.as_of).num_milliseconds(), 0) as u64;
+            let position = track.sample_position + elapsed * track.sample_rate / 1000;
+            track.sample_position = cmp::min(position, track.sample_count);
+        }
+        track
+    }
+
+    fn stop(&mut self) {
+        self.track = self.now();
+        self.as_of = SteadyTime::now();
+        self.running = false;
+    }
+}
+
+// Answers the client's pings and closes until it goes away.  Writes share
+// the feed's lock so they don't land in the middle of its frames.
+fn read_client(mut stream: TcpStream, writer: Arc<Mutex<TcpStream>>) -> io::Result<()> {
+    loop {
+        let (opcode, payload) = try!(read_frame(&mut stream));
+        match opcode {
+            OPCODE_PING => {
+                try!(write_frame(&mut *writer.lock().unwrap(), OPCODE_PONG, &payload));
+            },
+            OPCODE_CLOSE => {
+                // echoing the status code, if there was one
+                let status = if 2 <= payload.len() { &payload[..2] } else { &[] };
+                try!(write_frame(&mut *writer.lock().unwrap(), OPCODE_CLOSE, status));
+                return stream.shutdown(Shutdown::Both);
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Completes the handshake for a client which sent `key` as its
+/// `Sec-WebSocket-Key`, then feeds it until either side goes away.
+pub fn serve_feed(stream: TcpStream, key: &str, proxy: CoreProxy) -> io::Result<()> {
+    // the client may have nothing to say for as long as it's connected
+    try!(stream.set_read_timeout(None));
+    try!(stream.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT_SECS))));
+
+    let reader = try!(stream.try_clone());
+    let writer = Arc::new(Mutex::new(try!(stream.try_clone())));
+    let client_writer = writer.clone();
+    thread::spawn(move || {
+        if let Err(err) = read_client(reader, client_writer) {
+            debug!("feed client went away: {:?}", err);
+        }
+    });
+
+    let result = feed(&writer, key, proxy);
+    // stops the reader too
+    let _ = stream.shutdown(Shutdown::Both);
+    result
+}
+
+fn feed(writer: &Mutex<TcpStream>, key: &str, proxy: CoreProxy) -> io::Result<()> {
+    {
+        let mut stream = writer.lock().unwrap();
+        try!(write!(stream, "HTTP/1.1 101 Switching Protocols\r\n"));
+        try!(write!(stream, "Server: Ireul\r\n"));
+        try!(write!(stream, "Upgrade: websocket\r\n"));
+        try!(write!(stream, "Connection: Upgrade\r\n"));
+        try!(write!(stream, "Sec-WebSocket-Accept: {}\r\n", accept_key(key)));
+        try!(write!(stream, "\r\n"));
+        try!(stream.flush());
+    }
+
+    // subscribe before taking the snapshot, so nothing slips between them.
+    let events = match proxy.subscribe(SubscribeRequest) {
+        Ok(events) => events,
+        Err(err) => {
+            info!("feed couldn't subscribe to events: {:?}", err);
+            return write_frame(&mut *writer.lock().unwrap(), OPCODE_CLOSE, &[]);
+        },
+    };
+
+    let queue = try!(proxy.queue_status(StatusRequest).map_err(|_| core_gone()));
+    try!(send(&mut *writer.lock().unwrap(), "queue", vec![("queue", json::queue_json(&queue))]));
+    try!(send_now_playing(&mut *writer.lock().unwrap(), queue.playing.as_ref()));
+    let mut playing = queue.playing.map(Playing::new);
+
+    let tick = ::time::Duration::milliseconds(TICK_MILLIS);
+    let resync = ::time::Duration::seconds(RESYNC_SECS);
+    let mut next_tick = SteadyTime::now() + tick;
+    let mut next_resync = SteadyTime::now() + resync;
+    loop {
+        let wait = next_tick - SteadyTime::now();
+        let wait = Duration::from_millis(cmp::max(wait.num_milliseconds(), 0) as u64);
+
+        match events.recv_timeout(wait) {
+            Ok(Some(Event::TrackStarted(track))) => {
+                try!(send_now_playing(&mut *writer.lock().unwrap(), Some(&track)));
+                playing = Some(Playing::new(track));
+            },
+            Ok(Some(Event::FallbackEngaged)) => {
+                try!(send_now_playing(&mut *writer.lock().unwrap(), None));
+                playing = None;
+            },
+            Ok(Some(Event::QueueChanged)) => {
+                let queue = try!(proxy.queue_status(StatusRequest).map_err(|_| core_gone()));
+                try!(send(&mut *writer.lock().unwrap(), "queue", vec![("queue", json::queue_json(&queue))]));
+                playing = queue.playing.map(Playing::new);
+                next_resync = SteadyTime::now() + resync;
+            },
+            Ok(Some(Event::OutputDisconnected)) => {
+                if let Some(ref mut playing) = playing {
+                    playing.stop();
+                }
+            },
+            Ok(Some(Event::OutputReconnected)) => {
+                // the track may have been backed up a little
+                next_resync = SteadyTime::now();
+            },
+            Ok(Some(_)) => (),
+            Ok(None) => {
+                next_tick = next_tick + tick;
+                if next_resync <= SteadyTime::now() {
+                    let queue = try!(proxy.queue_status(StatusRequest).map_err(|_| core_gone()));
+                    playing = queue.playing.map(Playing::new);
+                    next_resync = SteadyTime::now() + resync;
+                }
+                if let Some(ref playing) = playing {
+                    try!(send_position(&mut *writer.lock().unwrap(), &playing.now()));
+                }
+            },
+            Err(err) => {
+                // we fell behind and were dropped, or the core is gone.  The
+                // website reconnects and gets a fresh snapshot either way.
+                info!("feed lost its subscription: {:?}", err);
+                return write_frame(&mut *writer.lock().unwrap(), OPCODE_CLOSE, &[]);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use rustc_serialize::json::Json;
+
+    use output::NullSink;
+    use station::StationBuilder;
+    use super::{sha1, accept_key, write_frame, OPCODE_TEXT, OPCODE_CLOSE, OPCODE_PING, OPCODE_PONG};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn read_frame<R: Read>(reader: &mut R) -> (u8, Vec<u8>) {
+        super::read_frame(reader).unwrap()
+    }
+
+    // as a client must send it
+    fn write_masked<W: Write>(stream: &mut W, opcode: u8, payload: &[u8]) {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        frame.extend(mask.iter().cloned());
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        stream.write_all(&frame).unwrap();
+    }
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(hex(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // the example from RFC 6455
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_frame_lengths() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, OPCODE_TEXT, &[b'x'; 300]).unwrap();
+        assert_eq!(&frame[..4], &[0x81, 126, 0x01, 0x2C]);
+        assert_eq!(read_frame(&mut &frame[..]).1.len(), 300);
+    }
+
+    #[test]
+    fn test_feed() {
+        let station = StationBuilder::new()
+            .set_output(Box::new(NullSink))
+            .start()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || station.serve_http(listener));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET /feed HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Version: 13\r\n\r\n").unwrap();
+
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut head = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            head.push(line);
+        }
+        assert!(head[0].starts_with("HTTP/1.1 101 "));
+        assert!(head.iter().any(|line| line == "Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        let (opcode, payload) = read_frame(&mut reader);
+        assert_eq!(opcode, OPCODE_TEXT);
+        let message = Json::from_str(&String::from_utf8(payload).unwrap()).unwrap();
+        assert_eq!(message.find("type").and_then(Json::as_string), Some("queue"));
+        assert!(message.find_path(&["queue", "upcoming"]).is_some());
+
+        let (_, payload) = read_frame(&mut reader);
+        let message = Json::from_str(&String::from_utf8(payload).unwrap()).unwrap();
+        assert_eq!(message.find("type").and_then(Json::as_string), Some("now_playing"));
+
+        write_masked(&mut stream, OPCODE_PING, b"hi");
+        loop {
+            match read_frame(&mut reader) {
+                (OPCODE_PONG, payload) => {
+                    assert_eq!(&payload[..], b"hi");
+                    break;
+                },
+                (OPCODE_TEXT, _) => (),
+                (opcode, _) => panic!("unexpected opcode {}", opcode),
+            }
+        }
+
+        write_masked(&mut stream, OPCODE_CLOSE, &[0x03, 0xE8]);
+        loop {
+            match read_frame(&mut reader) {
+                (OPCODE_CLOSE, payload) => {
+                    assert_eq!(&payload[..], &[0x03, 0xE8]);
+                    break;
+                },
+                (OPCODE_TEXT, _) => (),
+                (opcode, _) => panic!("unexpected opcode {}", opcode),
+            }
+        }
+    }
+}